@@ -1,13 +1,21 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use futures::channel::mpsc::UnboundedSender;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::channel::oneshot;
 use futures::executor::ThreadPool;
 use futures::future::{self, FutureExt};
+use futures::stream::{Stream, StreamExt};
+use futures_timer::Delay;
 
+use crate::context::CallContext;
 use crate::error::{Error, Result};
-use crate::server::RpcFuture;
+use crate::server::{RpcFuture, RpcStream};
 
 pub struct Rpc {
     pub(crate) client_name: String,
@@ -15,6 +23,9 @@ pub struct Rpc {
     pub(crate) req: Option<Vec<u8>>,
     pub(crate) resp: Option<oneshot::Sender<Result<Vec<u8>>>>,
     pub(crate) hooks: Arc<Mutex<Option<Arc<dyn RpcHooks>>>>,
+    pub(crate) ctx: CallContext,
+    // Where the messages of a streaming reply go, `None` for unary calls.
+    pub(crate) stream: Option<UnboundedSender<Result<Vec<u8>>>>,
 }
 
 impl Rpc {
@@ -37,6 +48,63 @@ pub trait RpcHooks: Sync + Send + 'static {
     fn after_dispatch(&self, fq_name: &str, resp: Result<Vec<u8>>) -> Result<Vec<u8>>;
 }
 
+/// Retries a call that timed out, waiting `backoff << n` before the n-th retry,
+/// saturating at `Duration::MAX`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize, backoff: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            backoff,
+        }
+    }
+
+    /// Only lost requests and replies are worth retrying, other errors
+    /// would happen again.
+    pub fn is_retryable(&self, e: &Error) -> bool {
+        matches!(e, Error::Timeout)
+    }
+
+    /// The wait before retry `n`, counting from 0.
+    pub fn delay(&self, n: usize) -> Duration {
+        let factor = u32::try_from(n)
+            .ok()
+            .and_then(|n| 1u32.checked_shl(n))
+            .unwrap_or(u32::MAX);
+        self.backoff.saturating_mul(factor)
+    }
+}
+
+/// Options of a call, the default is no deadline and no retry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallOptions {
+    /// The caller gives up after this long, retries included, and the call
+    /// fails with `Error::DeadlineExceeded`.
+    pub timeout: Option<Duration>,
+    pub retry: Option<RetryPolicy>,
+}
+
+impl CallOptions {
+    pub fn new() -> CallOptions {
+        CallOptions::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> CallOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> CallOptions {
+        self.retry = Some(retry);
+        self
+    }
+}
+
 #[derive(Clone)]
 pub struct Client {
     // this end-point's name
@@ -44,12 +112,28 @@ pub struct Client {
     // copy of Network.sender
     pub(crate) sender: UnboundedSender<Rpc>,
     pub(crate) hooks: Arc<Mutex<Option<Arc<dyn RpcHooks>>>>,
+    pub(crate) options: CallOptions,
+    // sent along with every call, see `CallContext::metadata`
+    pub(crate) metadata: Arc<HashMap<String, String>>,
 
     pub worker: ThreadPool,
 }
 
 impl Client {
     pub fn call<Req, Rsp>(&self, fq_name: &'static str, req: &Req) -> RpcFuture<Result<Rsp>>
+    where
+        Req: labcodec::Message,
+        Rsp: labcodec::Message + 'static,
+    {
+        self.call_with_options(fq_name, req, self.options)
+    }
+
+    pub fn call_with_options<Req, Rsp>(
+        &self,
+        fq_name: &'static str,
+        req: &Req,
+        options: CallOptions,
+    ) -> RpcFuture<Result<Rsp>>
     where
         Req: labcodec::Message,
         Rsp: labcodec::Message + 'static,
@@ -59,13 +143,51 @@ impl Client {
             return Box::pin(future::err(Error::Encode(e)));
         }
 
+        let deadline = options.timeout.map(|t| Instant::now() + t);
+        let retry = match options.retry {
+            Some(retry) => retry,
+            None => return self.send(fq_name, buf, deadline),
+        };
+
+        let client = self.clone();
+        Box::pin(async move {
+            let mut i = 0;
+            loop {
+                let res = client.send(fq_name, buf.clone(), deadline).await;
+                match res {
+                    Err(e) if i < retry.max_retries && retry.is_retryable(&e) => {
+                        let backoff = retry.delay(i);
+                        let wake = Instant::now().checked_add(backoff);
+                        if deadline.is_some_and(|d| wake.is_none_or(|wake| wake >= d)) {
+                            return Err(Error::DeadlineExceeded);
+                        }
+                        Delay::new(backoff).await;
+                        i += 1;
+                    }
+                    res => return res,
+                }
+            }
+        })
+    }
+
+    fn send<Rsp>(
+        &self,
+        fq_name: &'static str,
+        req: Vec<u8>,
+        deadline: Option<Instant>,
+    ) -> RpcFuture<Result<Rsp>>
+    where
+        Rsp: labcodec::Message + 'static,
+    {
         let (tx, rx) = oneshot::channel();
         let rpc = Rpc {
             client_name: self.name.clone(),
             fq_name,
-            req: Some(buf),
+            req: Some(req),
             resp: Some(tx),
             hooks: self.hooks.clone(),
+            ctx: CallContext::new(deadline, self.metadata.clone()),
+            stream: None,
         };
 
         // Sends requests and waits responses.
@@ -82,6 +204,82 @@ impl Client {
         }))
     }
 
+    /// Calls a streaming method, resolves once the server has accepted the
+    /// call.
+    ///
+    /// The deadline of the options covers the whole stream, and the call is
+    /// never retried since the server may have sent some of the messages.
+    /// Dropping the stream cancels the call.
+    pub fn call_stream<Req, Rsp>(
+        &self,
+        fq_name: &'static str,
+        req: &Req,
+    ) -> RpcFuture<Result<RpcStream<Rsp>>>
+    where
+        Req: labcodec::Message,
+        Rsp: labcodec::Message + 'static,
+    {
+        let mut buf = vec![];
+        if let Err(e) = labcodec::encode(req, &mut buf) {
+            return Box::pin(future::err(Error::Encode(e)));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let (items_tx, items_rx) = unbounded();
+        let deadline = self.options.timeout.map(|t| Instant::now() + t);
+        let ctx = CallContext::new(deadline, self.metadata.clone());
+        let rpc = Rpc {
+            client_name: self.name.clone(),
+            fq_name,
+            req: Some(buf),
+            resp: Some(tx),
+            hooks: self.hooks.clone(),
+            ctx: ctx.clone(),
+            stream: Some(items_tx),
+        };
+        if self.sender.unbounded_send(rpc).is_err() {
+            return Box::pin(future::err(Error::Stopped));
+        }
+
+        let items = Incoming {
+            items: items_rx,
+            ctx,
+        };
+        Box::pin(rx.then(|res| async move {
+            match res {
+                Ok(Ok(_)) => {
+                    let items = items.map(|item| {
+                        let item = item?;
+                        labcodec::decode(&item).map_err(Error::Decode)
+                    });
+                    Ok(Box::pin(items) as RpcStream<Rsp>)
+                }
+                Ok(Err(e)) => Err(e),
+                Err(e) => Err(Error::Recv(e)),
+            }
+        }))
+    }
+
+    /// Returns a Client sharing this end-point whose calls use `options`
+    /// by default.
+    pub fn with_options(&self, options: CallOptions) -> Client {
+        Client {
+            options,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a Client sharing this end-point that attaches `key: value`
+    /// to every call.
+    pub fn with_metadata(&self, key: &str, value: &str) -> Client {
+        let mut metadata = (*self.metadata).clone();
+        metadata.insert(key.to_owned(), value.to_owned());
+        Client {
+            metadata: Arc::new(metadata),
+            ..self.clone()
+        }
+    }
+
     pub fn set_hooks(&self, hooks: Arc<dyn RpcHooks>) {
         *self.hooks.lock().unwrap() = Some(hooks);
     }
@@ -90,3 +288,23 @@ impl Client {
         *self.hooks.lock().unwrap() = None;
     }
 }
+
+/// Messages of a streaming reply, cancels the call once dropped.
+struct Incoming {
+    items: UnboundedReceiver<Result<Vec<u8>>>,
+    ctx: CallContext,
+}
+
+impl Stream for Incoming {
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.items.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        self.ctx.cancel();
+    }
+}
@@ -0,0 +1,149 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+
+use futures::future::{self, Either, FutureExt};
+use futures::stream::Stream;
+use futures_timer::Delay;
+
+thread_local! {
+    static CURRENT: RefCell<Option<CallContext>> = const { RefCell::new(None) };
+}
+
+struct ContextInner {
+    deadline: Option<Instant>,
+    metadata: Arc<HashMap<String, String>>,
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+/// The context of an ongoing RPC, shared by the caller and the handler.
+///
+/// A call is cancelled when its deadline passes or when the caller drops
+/// the `RpcFuture` before the reply arrives. Handlers can get the context
+/// of the call they are serving with `CallContext::current`.
+#[derive(Clone)]
+pub struct CallContext {
+    inner: Arc<ContextInner>,
+}
+
+impl Default for CallContext {
+    fn default() -> CallContext {
+        CallContext::new(None, Arc::default())
+    }
+}
+
+impl CallContext {
+    pub(crate) fn new(
+        deadline: Option<Instant>,
+        metadata: Arc<HashMap<String, String>>,
+    ) -> CallContext {
+        CallContext {
+            inner: Arc::new(ContextInner {
+                deadline,
+                metadata,
+                cancelled: AtomicBool::new(false),
+                wakers: Mutex::new(vec![]),
+            }),
+        }
+    }
+
+    /// Returns the context of the call being handled on this thread.
+    ///
+    /// Only available while a handler future is polled, so handlers should
+    /// call it before their first `.await` and keep the result.
+    pub fn current() -> Option<CallContext> {
+        CURRENT.with(|c| c.borrow().clone())
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+
+    /// Returns the metadata the client attached to the call, e.g. an auth
+    /// token.
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.inner.metadata.get(key).map(String::as_str)
+    }
+
+    pub fn deadline_exceeded(&self) -> bool {
+        self.inner.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// Whether the caller has given up on this call.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire) || self.deadline_exceeded()
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        for w in self.inner.wakers.lock().unwrap().drain(..) {
+            w.wake();
+        }
+    }
+
+    /// Resolves once the caller has given up on this call.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let ctx = self.clone();
+        let cancelled = future::poll_fn(move |cx| {
+            if ctx.inner.cancelled.load(Ordering::Acquire) {
+                return Poll::Ready(());
+            }
+            let mut wakers = ctx.inner.wakers.lock().unwrap();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            drop(wakers);
+            // Check again in case it was cancelled before the waker is
+            // registered.
+            if ctx.inner.cancelled.load(Ordering::Acquire) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        });
+        let deadline = match self.inner.deadline {
+            Some(d) => Either::Left(Delay::new(d.saturating_duration_since(Instant::now()))),
+            None => Either::Right(future::pending()),
+        };
+        future::select(cancelled, deadline).map(|_| ())
+    }
+
+    /// Makes the context available through `CallContext::current` while
+    /// `fut`, a future or a stream, is polled.
+    pub(crate) fn scope<F: Unpin>(self, fut: F) -> Scoped<F> {
+        Scoped { ctx: self, fut }
+    }
+}
+
+pub(crate) struct Scoped<F> {
+    ctx: CallContext,
+    fut: F,
+}
+
+impl<F: Future + Unpin> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let prev = CURRENT.with(|c| c.replace(Some(self.ctx.clone())));
+        let res = Pin::new(&mut self.fut).poll(cx);
+        CURRENT.with(|c| *c.borrow_mut() = prev);
+        res
+    }
+}
+
+impl<S: Stream + Unpin> Stream for Scoped<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let prev = CURRENT.with(|c| c.replace(Some(self.ctx.clone())));
+        let res = Pin::new(&mut self.fut).poll_next(cx);
+        CURRENT.with(|c| *c.borrow_mut() = prev);
+        res
+    }
+}
@@ -11,7 +11,11 @@ pub enum Error {
     Decode(DecodeError),
     Recv(Canceled),
     Timeout,
+    DeadlineExceeded,
     Stopped,
+    /// The request was turned away before it reached the handler, e.g. by
+    /// an interceptor, so it had no effect.
+    Rejected(String),
     Other(String),
 }
 
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use crate::context::CallContext;
+use crate::error::{Error, Result};
+use crate::server::{RpcFuture, RpcStream};
+
+/// A call received by a server, before it reaches the handler.
+pub struct ServerCall {
+    pub fq_name: &'static str,
+    /// The encoded request.
+    pub req: Vec<u8>,
+    pub ctx: CallContext,
+}
+
+impl ServerCall {
+    /// Decodes the request, the caller has to know its type from `fq_name`.
+    pub fn decode<M: labcodec::Message + Default>(&self) -> Result<M> {
+        labcodec::decode(&self.req).map_err(Error::Decode)
+    }
+
+    /// Returns the metadata the client attached to the call.
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.ctx.metadata(key)
+    }
+}
+
+type Endpoint<T> = Box<dyn FnOnce(ServerCall) -> RpcFuture<Result<T>> + Send>;
+
+/// The rest of an interceptor chain, ending with the handler.
+pub struct Next<T> {
+    chain: Arc<[Arc<dyn Interceptor>]>,
+    index: usize,
+    endpoint: Endpoint<T>,
+}
+
+impl<T> Next<T> {
+    pub(crate) fn new(chain: Arc<[Arc<dyn Interceptor>]>, endpoint: Endpoint<T>) -> Next<T> {
+        Next {
+            chain,
+            index: 0,
+            endpoint,
+        }
+    }
+
+    fn advance(&mut self) -> Option<Arc<dyn Interceptor>> {
+        let interceptor = self.chain.get(self.index).cloned();
+        self.index += 1;
+        interceptor
+    }
+}
+
+impl Next<Vec<u8>> {
+    /// Passes the call to the next interceptor, or the handler if this is
+    /// the last one.
+    pub fn run(mut self, call: ServerCall) -> RpcFuture<Result<Vec<u8>>> {
+        match self.advance() {
+            Some(interceptor) => interceptor.intercept(call, self),
+            None => (self.endpoint)(call),
+        }
+    }
+}
+
+impl Next<RpcStream<Vec<u8>>> {
+    /// Passes the streaming call to the next interceptor, or the handler if
+    /// this is the last one.
+    pub fn run(mut self, call: ServerCall) -> RpcFuture<Result<RpcStream<Vec<u8>>>> {
+        match self.advance() {
+            Some(interceptor) => interceptor.intercept_stream(call, self),
+            None => (self.endpoint)(call),
+        }
+    }
+}
+
+/// Server side middleware, registered on a `ServerBuilder`.
+///
+/// Interceptors run in the order they are added, each one decides whether
+/// and how to pass the call down the chain, so it can inspect or rewrite
+/// the request, fail the call early, or look at the reply.
+pub trait Interceptor: Sync + Send + 'static {
+    fn intercept(&self, call: ServerCall, next: Next<Vec<u8>>) -> RpcFuture<Result<Vec<u8>>>;
+
+    /// Intercepts a call to a streaming method, passes it through by default.
+    fn intercept_stream(
+        &self,
+        call: ServerCall,
+        next: Next<RpcStream<Vec<u8>>>,
+    ) -> RpcFuture<Result<RpcStream<Vec<u8>>>> {
+        next.run(call)
+    }
+}
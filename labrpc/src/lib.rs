@@ -2,16 +2,25 @@
 #![allow(clippy::derive_partial_eq_without_eq)]
 
 mod client;
+mod context;
 mod error;
+mod interceptor;
 #[macro_use]
 mod macros;
+mod metrics;
 mod network;
 mod server;
 
-pub use self::client::{Client, Rpc, RpcHooks};
+pub use self::client::{CallOptions, Client, RetryPolicy, Rpc, RpcHooks};
+pub use self::context::CallContext;
 pub use self::error::{Error, Result};
+pub use self::interceptor::{Interceptor, Next, ServerCall};
+pub use self::metrics::{Histogram, Metrics, MetricsKey, RpcStats};
 pub use self::network::Network;
-pub use self::server::{Handler, HandlerFactory, RpcFuture, Server, ServerBuilder};
+pub use self::server::{
+    encode_stream, Handler, HandlerFactory, RpcFuture, RpcStream, Server, ServerBuilder,
+    StreamHandler,
+};
 
 #[cfg(test)]
 pub mod tests {
@@ -22,6 +31,7 @@ pub mod tests {
 
     use futures::channel::oneshot::Canceled;
     use futures::executor::{block_on, ThreadPool};
+    use futures::future::{self, FutureExt};
     use futures::stream::StreamExt;
     use futures_timer::Delay;
     use prost_derive::Message;
@@ -35,6 +45,10 @@ pub mod tests {
             rpc handler2(JunkArgs) returns (JunkReply);
             rpc handler3(JunkArgs) returns (JunkReply);
             rpc handler4(JunkArgs) returns (JunkReply);
+            rpc handler5(JunkPayload) returns (JunkPayload);
+            rpc handler6(JunkArgs) returns (JunkReply);
+            rpc handler7(JunkArgs) returns (stream JunkReply);
+            rpc handler8(JunkArgs) returns (stream JunkReply);
         }
     }
     use junk::{add_service, Client as JunkClient, Service as Junk};
@@ -50,10 +64,17 @@ pub mod tests {
         #[prost(string, tag = "1")]
         pub x: String,
     }
+    #[derive(Clone, PartialEq, Message)]
+    pub struct JunkPayload {
+        #[prost(bytes, tag = "1")]
+        pub data: Vec<u8>,
+    }
 
     #[derive(Default)]
     struct JunkInner {
         log2: Vec<i64>,
+        cancelled: usize,
+        streamed: usize,
     }
     #[derive(Clone)]
     struct JunkService {
@@ -85,6 +106,43 @@ pub mod tests {
                 x: "pointer".to_owned(),
             })
         }
+        async fn handler5(&self, args: JunkPayload) -> Result<JunkPayload> {
+            Ok(args)
+        }
+        async fn handler6(&self, args: JunkArgs) -> Result<JunkReply> {
+            let ctx = CallContext::current().unwrap();
+            let sleep = Delay::new(Duration::from_secs(20));
+            futures::select! {
+                _ = sleep.fuse() => {}
+                _ = ctx.cancelled().fuse() => {
+                    self.inner.lock().unwrap().cancelled += 1;
+                }
+            }
+            Ok(JunkReply {
+                x: format!("handler6-{}", args.x),
+            })
+        }
+        async fn handler7(&self, args: JunkArgs) -> Result<RpcStream<JunkReply>> {
+            let replies = (0..args.x).map(|i| {
+                Ok(JunkReply {
+                    x: format!("handler7-{}", i),
+                })
+            });
+            Ok(Box::pin(futures::stream::iter(replies)))
+        }
+        async fn handler8(&self, _: JunkArgs) -> Result<RpcStream<JunkReply>> {
+            // Replies every 10ms until the caller gives up.
+            let inner = self.inner.clone();
+            let replies = futures::stream::unfold(inner, |inner| async move {
+                Delay::new(Duration::from_millis(10)).await;
+                inner.lock().unwrap().streamed += 1;
+                let reply = JunkReply {
+                    x: "handler8".to_owned(),
+                };
+                Some((Ok(reply), inner))
+            });
+            Ok(Box::pin(replies))
+        }
     }
 
     fn init_logger() {
@@ -122,6 +180,9 @@ pub mod tests {
             server.dispatch("badjunk.handler4", &[]).await.unwrap_err();
 
             server.dispatch("junk.badhandler", &[]).await.unwrap_err();
+
+            // Streaming methods can not be called as unary ones.
+            server.dispatch("junk.handler7", &[]).await.unwrap_err();
         });
     }
 
@@ -185,9 +246,18 @@ pub mod tests {
     }
 
     fn junk_suit() -> (Network, Server, JunkService) {
+        junk_suit_with_interceptors(vec![])
+    }
+
+    fn junk_suit_with_interceptors(
+        interceptors: Vec<Arc<dyn Interceptor>>,
+    ) -> (Network, Server, JunkService) {
         let net = Network::new();
         let server_name = "test_server".to_owned();
         let mut builder = ServerBuilder::new(server_name);
+        for interceptor in interceptors {
+            builder.add_interceptor(interceptor);
+        }
         let junk_server = JunkService::new();
         add_service(junk_server.clone(), &mut builder).unwrap();
         let server = builder.build();
@@ -256,6 +326,322 @@ pub mod tests {
         assert_eq!(net.count("test_server"), 17);
     }
 
+    #[test]
+    fn test_metrics() {
+        init_logger();
+
+        let (net, _, _) = junk_suit();
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        net.enable("test_client", true);
+
+        for i in 0..10 {
+            block_on(async { client.handler2(&JunkArgs { x: i }).await.unwrap() });
+        }
+        block_on(async { client.handler4(&JunkArgs::default()).await.unwrap() });
+        net.enable("test_client", false);
+        block_on(async { client.handler4(&JunkArgs::default()).await.unwrap_err() });
+
+        let metrics = net.metrics();
+        let handler2 = metrics.method("junk.handler2");
+        assert_eq!(handler2.calls, 10);
+        assert_eq!(handler2.errors, 0);
+        assert_eq!(handler2.latency.count(), 10);
+        assert_eq!(handler2.resp_bytes.count(), 10);
+        assert!(handler2.req_bytes.sum() > 0.0);
+
+        let handler4 = metrics.method("junk.handler4");
+        assert_eq!(handler4.calls, 2);
+        assert_eq!(handler4.errors, 1);
+        assert_eq!(handler4.resp_bytes.count(), 1);
+
+        let pair = metrics.pair("test_client", "test_server");
+        assert_eq!(pair.calls, 12);
+        assert_eq!(metrics.service("junk"), pair);
+        assert_eq!(metrics.total().bytes(), net.total_bytes());
+        assert_eq!(metrics.by_method().len(), 2);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE labrpc_latency_seconds histogram"));
+        assert!(text.contains(
+            "labrpc_requests_total{method=\"junk.handler2\",client=\"test_client\",\
+             server=\"test_server\"} 10"
+        ));
+        assert!(text.contains(
+            "labrpc_request_bytes_count{method=\"junk.handler4\",client=\"test_client\",\
+             server=\"test_server\"} 2"
+        ));
+
+        metrics.reset();
+        assert_eq!(metrics.total().calls, 0);
+    }
+
+    #[test]
+    fn test_bandwidth() {
+        init_logger();
+
+        let (net, _, _) = junk_suit();
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        net.enable("test_client", true);
+
+        let args = JunkPayload {
+            data: vec![0; 10_000],
+        };
+        // 100KB/s, each direction takes about 100ms.
+        net.set_bandwidth("test_client", Some(100_000));
+        let t0 = Instant::now();
+        let reply = block_on(async { client.handler5(&args).await.unwrap() });
+        assert_eq!(reply, args);
+        let dur = t0.elapsed();
+        assert!(dur >= Duration::from_millis(200), "took {:?}", dur);
+
+        // Concurrent messages queue behind each other.
+        let t0 = Instant::now();
+        let calls = (0..4).map(|_| client.handler5(&args));
+        for reply in block_on(futures::future::join_all(calls)) {
+            reply.unwrap();
+        }
+        let dur = t0.elapsed();
+        assert!(dur >= Duration::from_millis(500), "took {:?}", dur);
+
+        // The default applies to links without an explicit cap.
+        net.set_bandwidth("test_client", None);
+        net.set_default_bandwidth(Some(50_000));
+        let t0 = Instant::now();
+        block_on(async { client.handler5(&args).await.unwrap() });
+        let dur = t0.elapsed();
+        assert!(dur >= Duration::from_millis(400), "took {:?}", dur);
+    }
+
+    #[test]
+    fn test_deadline() {
+        init_logger();
+
+        let (net, _, junk_server) = junk_suit();
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        net.enable("test_client", true);
+
+        let options = CallOptions::new().timeout(Duration::from_millis(100));
+        let t0 = Instant::now();
+        let res = block_on(async {
+            client
+                .with_options(options)
+                .handler6(&JunkArgs { x: 1 })
+                .await
+        });
+        assert_eq!(res, Err(Error::DeadlineExceeded));
+        assert!(t0.elapsed() < Duration::from_secs(1));
+
+        // The handler observes that the caller gave up.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(junk_server.inner.lock().unwrap().cancelled, 1);
+
+        // A deadline also bounds the wait on a disconnected client.
+        net.enable("test_client", false);
+        net.set_long_delays(true);
+        let t0 = Instant::now();
+        let res = block_on(async {
+            client
+                .with_options(options)
+                .handler4(&JunkArgs::default())
+                .await
+        });
+        assert!(t0.elapsed() < Duration::from_secs(1));
+        assert!(
+            res == Err(Error::DeadlineExceeded) || res == Err(Error::Timeout),
+            "{:?}",
+            res
+        );
+
+        // Calls that finish in time are unaffected.
+        net.enable("test_client", true);
+        let reply = block_on(async {
+            client
+                .with_options(options)
+                .handler2(&JunkArgs { x: 2 })
+                .await
+        });
+        assert_eq!(reply.unwrap().x, "handler2-2");
+    }
+
+    #[test]
+    fn test_caller_gives_up() {
+        init_logger();
+
+        let (net, _, junk_server) = junk_suit();
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        net.enable("test_client", true);
+
+        let call = client.handler6(&JunkArgs { x: 1 });
+        let giveup = Delay::new(Duration::from_millis(100));
+        block_on(async {
+            futures::select! {
+                _ = call.fuse() => panic!("handler6 should not finish"),
+                _ = giveup.fuse() => {}
+            }
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(junk_server.inner.lock().unwrap().cancelled, 1);
+    }
+
+    #[test]
+    fn test_stream() {
+        init_logger();
+
+        let (net, _, _) = junk_suit();
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        net.enable("test_client", true);
+
+        let replies: Vec<_> = block_on(async {
+            let items = client.handler7(&JunkArgs { x: 3 }).await.unwrap();
+            items.collect().await
+        });
+        let expected: Vec<_> = (0..3)
+            .map(|i| {
+                Ok(JunkReply {
+                    x: format!("handler7-{}", i),
+                })
+            })
+            .collect();
+        assert_eq!(replies, expected);
+
+        let stats = net.metrics().method("junk.handler7");
+        assert_eq!(stats.calls, 1);
+        assert_eq!(stats.errors, 0);
+
+        // A disconnected client can not open a stream.
+        net.enable("test_client", false);
+        let res = block_on(async { client.handler7(&JunkArgs { x: 3 }).await });
+        assert_eq!(res.err(), Some(Error::Timeout));
+    }
+
+    #[test]
+    fn test_stream_cancel() {
+        init_logger();
+
+        let (net, server, junk_server) = junk_suit();
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        net.enable("test_client", true);
+
+        // Dropping the stream stops the handler.
+        block_on(async {
+            let items = client.handler8(&JunkArgs::default()).await.unwrap();
+            let replies: Vec<_> = items.take(3).collect().await;
+            assert_eq!(replies.len(), 3);
+        });
+        thread::sleep(Duration::from_millis(100));
+        let streamed = junk_server.inner.lock().unwrap().streamed;
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(junk_server.inner.lock().unwrap().streamed, streamed);
+
+        // The deadline covers the whole stream.
+        let options = CallOptions::new().timeout(Duration::from_millis(100));
+        let last = block_on(async {
+            let items = client
+                .with_options(options)
+                .handler8(&JunkArgs::default())
+                .await
+                .unwrap();
+            items.collect::<Vec<_>>().await.pop()
+        });
+        assert_eq!(last, Some(Err(Error::DeadlineExceeded)));
+
+        // So does a killed server.
+        let (tx, rx) = mpsc::channel();
+        let cli = client.clone();
+        client.spawn(async move {
+            let items = cli.handler8(&JunkArgs::default()).await.unwrap();
+            tx.send(items.collect::<Vec<_>>().await.pop()).unwrap();
+        });
+        thread::sleep(Duration::from_millis(100));
+        net.delete_server(server.name());
+        let last = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(last, Some(Err(Error::Stopped)));
+    }
+
+    #[test]
+    fn test_retry() {
+        init_logger();
+
+        let (net, _, _) = junk_suit();
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+
+        // Without a retry policy the disabled client fails.
+        block_on(async { client.handler4(&JunkArgs::default()).await.unwrap_err() });
+
+        let net_ = net.clone();
+        let enabler = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            net_.enable("test_client", true);
+        });
+        let options = CallOptions::new().retry(RetryPolicy::new(10, Duration::from_millis(20)));
+        let reply = block_on(async {
+            client
+                .with_options(options)
+                .handler2(&JunkArgs { x: 3 })
+                .await
+        });
+        assert_eq!(reply.unwrap().x, "handler2-3");
+        enabler.join().unwrap();
+
+        // Retries stop at the deadline.
+        net.enable("test_client", false);
+        let options = options.timeout(Duration::from_millis(300));
+        let t0 = Instant::now();
+        let res = block_on(async {
+            client
+                .with_options(options)
+                .handler4(&JunkArgs::default())
+                .await
+        });
+        assert!(t0.elapsed() < Duration::from_secs(1));
+        assert_eq!(res, Err(Error::DeadlineExceeded));
+    }
+
+    #[test]
+    fn test_retry_backoff_saturates() {
+        let retry = RetryPolicy::new(usize::MAX, Duration::from_millis(20));
+        assert_eq!(retry.delay(0), Duration::from_millis(20));
+        assert_eq!(retry.delay(3), Duration::from_millis(160));
+        assert_eq!(retry.delay(31), Duration::from_millis(20) * (1 << 31));
+        assert_eq!(retry.delay(32), Duration::from_millis(20) * u32::MAX);
+        assert_eq!(
+            retry.delay(usize::MAX),
+            Duration::from_millis(20) * u32::MAX
+        );
+        let retry = RetryPolicy::new(usize::MAX, Duration::MAX);
+        assert_eq!(retry.delay(1), Duration::MAX);
+
+        // Retrying for long hits the deadline instead of overflowing.
+        let (net, _, _) = junk_suit();
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        let options = CallOptions::new()
+            .retry(retry)
+            .timeout(Duration::from_millis(300));
+        let res = block_on(async {
+            client
+                .with_options(options)
+                .handler4(&JunkArgs::default())
+                .await
+        });
+        assert_eq!(res, Err(Error::DeadlineExceeded));
+    }
+
     // test RPCs from concurrent Clients
     #[test]
     fn test_concurrent_many() {
@@ -418,7 +804,7 @@ pub mod tests {
             pool.spawn_ok(async move {
                 let x = i + 100;
                 // this call ought to return false.
-                let _ = cli.handler2(&JunkArgs { x });
+                drop(cli.handler2(&JunkArgs { x }));
                 sender.send(true).unwrap();
             });
         }
@@ -537,4 +923,148 @@ pub mod tests {
         block_on(async { client.handler2(&JunkArgs { x: i }).await.unwrap() });
         assert_eq!(reply.x, format!("handler2-{}", i));
     }
+
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+    impl Interceptor for Recorder {
+        fn intercept(&self, call: ServerCall, next: Next<Vec<u8>>) -> RpcFuture<Result<Vec<u8>>> {
+            let (name, log) = (self.name, self.log.clone());
+            log.lock()
+                .unwrap()
+                .push(format!("{} before {}", name, call.fq_name));
+            let resp = next.run(call);
+            Box::pin(async move {
+                let resp = resp.await;
+                log.lock().unwrap().push(format!("{} after", name));
+                resp
+            })
+        }
+    }
+
+    // Rejects handler2 calls with a negative argument.
+    struct RejectNegative;
+    impl Interceptor for RejectNegative {
+        fn intercept(&self, call: ServerCall, next: Next<Vec<u8>>) -> RpcFuture<Result<Vec<u8>>> {
+            if call.fq_name == "junk.handler2" {
+                match call.decode::<JunkArgs>() {
+                    Ok(args) if args.x < 0 => {
+                        return Box::pin(future::err(Error::Other("negative".to_owned())));
+                    }
+                    Err(e) => return Box::pin(future::err(e)),
+                    _ => {}
+                }
+            }
+            next.run(call)
+        }
+    }
+
+    struct Auth {
+        token: &'static str,
+    }
+    impl Auth {
+        fn check(&self, call: &ServerCall) -> Result<()> {
+            if call.metadata("token") == Some(self.token) {
+                Ok(())
+            } else {
+                Err(Error::Other("unauthorized".to_owned()))
+            }
+        }
+    }
+    impl Interceptor for Auth {
+        fn intercept(&self, call: ServerCall, next: Next<Vec<u8>>) -> RpcFuture<Result<Vec<u8>>> {
+            match self.check(&call) {
+                Ok(()) => next.run(call),
+                Err(e) => Box::pin(future::err(e)),
+            }
+        }
+        fn intercept_stream(
+            &self,
+            call: ServerCall,
+            next: Next<RpcStream<Vec<u8>>>,
+        ) -> RpcFuture<Result<RpcStream<Vec<u8>>>> {
+            match self.check(&call) {
+                Ok(()) => next.run(call),
+                Err(e) => Box::pin(future::err(e)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_interceptor_chain() {
+        init_logger();
+
+        let log = Arc::new(Mutex::new(vec![]));
+        let (net, _, junk_server) = junk_suit_with_interceptors(vec![
+            Arc::new(Recorder {
+                name: "outer",
+                log: log.clone(),
+            }),
+            Arc::new(RejectNegative),
+            Arc::new(Recorder {
+                name: "inner",
+                log: log.clone(),
+            }),
+        ]);
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        net.enable("test_client", true);
+
+        let reply = block_on(async { client.handler2(&JunkArgs { x: 1 }).await });
+        assert_eq!(reply.unwrap().x, "handler2-1");
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "outer before junk.handler2",
+                "inner before junk.handler2",
+                "inner after",
+                "outer after",
+            ]
+        );
+
+        // The call never reaches the inner interceptor and the handler.
+        log.lock().unwrap().clear();
+        let reply = block_on(async { client.handler2(&JunkArgs { x: -1 }).await });
+        assert_eq!(reply, Err(Error::Other("negative".to_owned())));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["outer before junk.handler2", "outer after"]
+        );
+        assert_eq!(junk_server.inner.lock().unwrap().log2, vec![1]);
+    }
+
+    #[test]
+    fn test_interceptor_auth() {
+        init_logger();
+
+        let (net, _, _) = junk_suit_with_interceptors(vec![Arc::new(Auth { token: "secret" })]);
+
+        let client = JunkClient::new(net.create_client("test_client".to_owned()));
+        net.connect("test_client", "test_server");
+        net.enable("test_client", true);
+
+        let unauthorized = Err(Error::Other("unauthorized".to_owned()));
+        let reply = block_on(async { client.handler4(&JunkArgs::default()).await });
+        assert_eq!(reply, unauthorized);
+        let reply = block_on(async {
+            client
+                .with_metadata("token", "guess")
+                .handler4(&JunkArgs::default())
+                .await
+        });
+        assert_eq!(reply, unauthorized);
+        let res = block_on(async { client.handler7(&JunkArgs { x: 1 }).await });
+        assert_eq!(res.err(), unauthorized.err());
+
+        let client = client.with_metadata("token", "secret");
+        let reply = block_on(async { client.handler4(&JunkArgs::default()).await });
+        assert_eq!(reply.unwrap().x, "pointer");
+        let replies: Vec<_> = block_on(async {
+            let items = client.handler7(&JunkArgs { x: 2 }).await.unwrap();
+            items.collect().await
+        });
+        assert_eq!(replies.len(), 2);
+    }
 }
@@ -8,7 +8,7 @@ macro_rules! service {
         service $svc_name:ident {
             $(
                 $(#[$method_attr:meta])*
-                rpc $method_name:ident($input:ty) returns ($output:ty);
+                rpc $method_name:ident($input:ty) returns ($($output:tt)+);
             )*
         }
     ) => {
@@ -25,7 +25,7 @@ macro_rules! service {
             pub trait Service: Clone + Send + 'static {
                 $(
                     $(#[$method_attr])*
-                    async fn $method_name(&self, req: $input) -> $crate::Result<$output>;
+                    async fn $method_name(&self, req: $input) -> $crate::__rpc_output!($($output)+);
                 )*
             }
 
@@ -38,16 +38,23 @@ macro_rules! service {
                     Client { client }
                 }
 
+                /// Returns a Client whose calls use `options`, e.g. a deadline.
+                pub fn with_options(&self, options: $crate::CallOptions) -> Client {
+                    Client { client: self.client.with_options(options) }
+                }
+
+                /// Returns a Client that attaches `key: value` to every call.
+                pub fn with_metadata(&self, key: &str, value: &str) -> Client {
+                    Client { client: self.client.with_metadata(key, value) }
+                }
+
                 pub fn spawn<F>(&self, f: F)
                 where F: __futures::Future<Output = ()> + Send + 'static
                 {
                     self.client.worker.spawn_ok(f);
                 }
 
-                $(pub fn $method_name(&self, args: &$input) -> $crate::RpcFuture<$crate::Result<$output>> {
-                    let fq_name = concat!(stringify!($svc_name), ".", stringify!($method_name));
-                    self.client.call(fq_name, args)
-                })*
+                $($crate::__rpc_client_method!($svc_name, $method_name, $input, $($output)+);)*
             }
 
             pub fn add_service<T: Service>(svc: T, builder: &mut $crate::ServerBuilder) -> $crate::Result<()> {
@@ -61,24 +68,25 @@ macro_rules! service {
                         Box::new(move |req| {
                             match name {
                                 $(stringify!($method_name) => {
-                                    let request = match labcodec::decode(req) {
-                                        Ok(req) => req,
-                                        Err(e) => return Box::pin(__futures::future::err(
-                                            $crate::Error::Decode(e)
-                                        )),
-                                    };
-                                    Box::pin(async move {
-                                        let f = s.$method_name(request);
-                                        let resp = f.await;
-                                        match resp {
-                                            Ok(resp) => {
-                                                let mut rsp = vec![];
-                                                labcodec::encode(&resp, &mut rsp).map_err($crate::Error::Encode)?;
-                                                Ok(rsp)
-                                            }
-                                            Err(e) => Err(e),
-                                        }
-                                    })
+                                    $crate::__rpc_handler!(unary, s, req, $method_name, $($output)+)
+                                })*
+                                other => {
+                                    Box::pin(__futures::future::err(
+                                        $crate::Error::Unimplemented(
+                                            format!("unknown {} in {}", other, stringify!($svc_name))
+                                        )
+                                    ))
+                                }
+                            }
+                        })
+                    }
+
+                    fn stream_handler(&self, name: &'static str) -> Box<$crate::StreamHandler> {
+                        let s = self.svc.lock().unwrap().clone();
+                        Box::new(move |req| {
+                            match name {
+                                $(stringify!($method_name) => {
+                                    $crate::__rpc_handler!(stream, s, req, $method_name, $($output)+)
                                 })*
                                 other => {
                                     Box::pin(__futures::future::err(
@@ -101,3 +109,82 @@ macro_rules! service {
         }
     };
 }
+
+/// Return type of a service method, `stream T` is a streaming reply.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __rpc_output {
+    (stream $output:ty) => {
+        $crate::Result<$crate::RpcStream<$output>>
+    };
+    ($output:ty) => {
+        $crate::Result<$output>
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __rpc_client_method {
+    ($svc_name:ident, $method_name:ident, $input:ty, stream $output:ty) => {
+        pub fn $method_name(
+            &self,
+            args: &$input,
+        ) -> $crate::RpcFuture<$crate::Result<$crate::RpcStream<$output>>> {
+            let fq_name = concat!(stringify!($svc_name), ".", stringify!($method_name));
+            self.client.call_stream(fq_name, args)
+        }
+    };
+    ($svc_name:ident, $method_name:ident, $input:ty, $output:ty) => {
+        pub fn $method_name(&self, args: &$input) -> $crate::RpcFuture<$crate::Result<$output>> {
+            let fq_name = concat!(stringify!($svc_name), ".", stringify!($method_name));
+            self.client.call(fq_name, args)
+        }
+    };
+}
+
+/// Body of a unary or a streaming handler of a method, calling a method
+/// the wrong way fails with `Error::Unimplemented`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __rpc_handler {
+    (unary, $s:ident, $req:ident, $method_name:ident, stream $output:ty) => {{
+        let _ = (&$s, $req);
+        Box::pin(async {
+            Err($crate::Error::Unimplemented(format!(
+                "{} is a streaming method",
+                stringify!($method_name)
+            )))
+        })
+    }};
+    (unary, $s:ident, $req:ident, $method_name:ident, $output:ty) => {{
+        let request = match labcodec::decode($req) {
+            Ok(req) => req,
+            Err(e) => return Box::pin(async move { Err($crate::Error::Decode(e)) }),
+        };
+        Box::pin(async move {
+            let resp = $s.$method_name(request).await?;
+            let mut rsp = vec![];
+            labcodec::encode(&resp, &mut rsp).map_err($crate::Error::Encode)?;
+            Ok(rsp)
+        })
+    }};
+    (stream, $s:ident, $req:ident, $method_name:ident, stream $output:ty) => {{
+        let request = match labcodec::decode($req) {
+            Ok(req) => req,
+            Err(e) => return Box::pin(async move { Err($crate::Error::Decode(e)) }),
+        };
+        Box::pin(async move {
+            let items = $s.$method_name(request).await?;
+            Ok($crate::encode_stream(items))
+        })
+    }};
+    (stream, $s:ident, $req:ident, $method_name:ident, $output:ty) => {{
+        let _ = (&$s, $req);
+        Box::pin(async {
+            Err($crate::Error::Unimplemented(format!(
+                "{} is not a streaming method",
+                stringify!($method_name)
+            )))
+        })
+    }};
+}
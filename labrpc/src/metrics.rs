@@ -0,0 +1,333 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// Upper bounds of the latency buckets, in seconds.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0,
+];
+
+/// Upper bounds of the message size buckets, in bytes.
+pub const SIZE_BUCKETS: &[f64] = &[
+    64.0,
+    256.0,
+    1024.0,
+    4096.0,
+    16384.0,
+    65536.0,
+    262_144.0,
+    1_048_576.0,
+    4_194_304.0,
+];
+
+/// A cumulative histogram with fixed bucket bounds, modeled after the
+/// Prometheus histogram type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    // counts[i] is the number of samples in (bounds[i - 1], bounds[i]],
+    // the last slot collects everything above the largest bound.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, v: f64) {
+        let i = self
+            .bounds
+            .iter()
+            .position(|b| v <= *b)
+            .unwrap_or(self.bounds.len());
+        self.counts[i] += 1;
+        self.sum += v;
+        self.count += 1;
+    }
+
+    /// Number of observed samples.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of all observed samples.
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// Returns `(upper_bound, cumulative_count)` pairs, ending with the
+    /// `+Inf` bucket.
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        let mut acc = 0;
+        let mut buckets = Vec::with_capacity(self.counts.len());
+        for (i, c) in self.counts.iter().enumerate() {
+            acc += c;
+            let bound = self.bounds.get(i).copied().unwrap_or(f64::INFINITY);
+            buckets.push((bound, acc));
+        }
+        buckets
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        assert_eq!(
+            self.bounds, other.bounds,
+            "merge histograms of different bounds"
+        );
+        for (c, o) in self.counts.iter_mut().zip(&other.counts) {
+            *c += o;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+/// Statistics of a group of RPCs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcStats {
+    /// Number of RPCs sent.
+    pub calls: u64,
+    /// Number of RPCs that did not get a successful reply.
+    pub errors: u64,
+    /// Round trip time as seen by the client, in seconds.
+    pub latency: Histogram,
+    /// Encoded request size.
+    pub req_bytes: Histogram,
+    /// Encoded reply size, only successful replies are counted. Every
+    /// message of a streaming reply is a sample.
+    pub resp_bytes: Histogram,
+}
+
+impl Default for RpcStats {
+    fn default() -> RpcStats {
+        RpcStats {
+            calls: 0,
+            errors: 0,
+            latency: Histogram::new(LATENCY_BUCKETS),
+            req_bytes: Histogram::new(SIZE_BUCKETS),
+            resp_bytes: Histogram::new(SIZE_BUCKETS),
+        }
+    }
+}
+
+impl RpcStats {
+    /// Total bytes sent on the wire, both directions.
+    pub fn bytes(&self) -> u64 {
+        (self.req_bytes.sum() + self.resp_bytes.sum()) as u64
+    }
+
+    fn merge(&mut self, other: &RpcStats) {
+        self.calls += other.calls;
+        self.errors += other.errors;
+        self.latency.merge(&other.latency);
+        self.req_bytes.merge(&other.req_bytes);
+        self.resp_bytes.merge(&other.resp_bytes);
+    }
+}
+
+/// Identifies the RPCs of one method between a client and a server.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MetricsKey {
+    pub fq_name: &'static str,
+    pub client: String,
+    /// Empty if the client was not connected to any server.
+    pub server: String,
+}
+
+/// Per-method and per-endpoint RPC statistics collected by a `Network`.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    stats: Arc<Mutex<HashMap<MetricsKey, RpcStats>>>,
+}
+
+impl Metrics {
+    pub(crate) fn record(
+        &self,
+        key: MetricsKey,
+        latency: Duration,
+        req_len: usize,
+        resp: &Result<Vec<u8>, Error>,
+    ) {
+        let mut stats = self.stats.lock().unwrap();
+        let s = stats.entry(key).or_default();
+        s.calls += 1;
+        s.latency.observe(latency.as_secs_f64());
+        s.req_bytes.observe(req_len as f64);
+        match resp {
+            Ok(resp) => s.resp_bytes.observe(resp.len() as f64),
+            Err(_) => s.errors += 1,
+        }
+    }
+
+    /// Records a message of a streaming reply, the call itself is recorded
+    /// by `record` once the server has accepted it.
+    pub(crate) fn record_stream_item(&self, key: &MetricsKey, len: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        let s = stats.entry(key.clone()).or_default();
+        s.resp_bytes.observe(len as f64);
+    }
+
+    /// Returns a copy of all statistics, keyed by method and endpoints.
+    pub fn entries(&self) -> BTreeMap<MetricsKey, RpcStats> {
+        let stats = self.stats.lock().unwrap();
+        stats.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// Statistics of all RPCs matching `filter`.
+    pub fn aggregate<F>(&self, filter: F) -> RpcStats
+    where
+        F: Fn(&MetricsKey) -> bool,
+    {
+        let stats = self.stats.lock().unwrap();
+        let mut total = RpcStats::default();
+        for (_, s) in stats.iter().filter(|(k, _)| filter(k)) {
+            total.merge(s);
+        }
+        total
+    }
+
+    /// Statistics of a method, e.g. `raft.append_entries`.
+    pub fn method(&self, fq_name: &str) -> RpcStats {
+        self.aggregate(|k| k.fq_name == fq_name)
+    }
+
+    /// Statistics of all methods of a service, e.g. `raft`.
+    pub fn service(&self, service_name: &str) -> RpcStats {
+        self.aggregate(|k| k.fq_name.split('.').next() == Some(service_name))
+    }
+
+    /// Statistics of RPCs from a client to a server.
+    pub fn pair(&self, client: &str, server: &str) -> RpcStats {
+        self.aggregate(|k| k.client == client && k.server == server)
+    }
+
+    /// Statistics of RPCs received by a server.
+    pub fn server(&self, server: &str) -> RpcStats {
+        self.aggregate(|k| k.server == server)
+    }
+
+    /// Statistics of every RPC.
+    pub fn total(&self) -> RpcStats {
+        self.aggregate(|_| true)
+    }
+
+    pub fn by_method(&self) -> BTreeMap<&'static str, RpcStats> {
+        let stats = self.stats.lock().unwrap();
+        let mut ret: BTreeMap<&'static str, RpcStats> = BTreeMap::new();
+        for (k, s) in stats.iter() {
+            ret.entry(k.fq_name).or_default().merge(s);
+        }
+        ret
+    }
+
+    pub fn reset(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+
+    /// Dumps the statistics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let entries = self.entries();
+        let mut out = String::new();
+
+        write_header(&mut out, "labrpc_requests_total", "counter", "RPCs sent.");
+        for (k, s) in &entries {
+            writeln!(out, "labrpc_requests_total{{{}}} {}", labels(k), s.calls).unwrap();
+        }
+        write_header(
+            &mut out,
+            "labrpc_errors_total",
+            "counter",
+            "RPCs without a successful reply.",
+        );
+        for (k, s) in &entries {
+            writeln!(out, "labrpc_errors_total{{{}}} {}", labels(k), s.errors).unwrap();
+        }
+
+        write_histogram(
+            &mut out,
+            "labrpc_latency_seconds",
+            "RPC round trip time.",
+            &entries,
+            |s| &s.latency,
+        );
+        write_histogram(
+            &mut out,
+            "labrpc_request_bytes",
+            "Encoded request size.",
+            &entries,
+            |s| &s.req_bytes,
+        );
+        write_histogram(
+            &mut out,
+            "labrpc_response_bytes",
+            "Encoded reply size.",
+            &entries,
+            |s| &s.resp_bytes,
+        );
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn write_histogram<F>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    entries: &BTreeMap<MetricsKey, RpcStats>,
+    get: F,
+) where
+    F: Fn(&RpcStats) -> &Histogram,
+{
+    write_header(out, name, "histogram", help);
+    for (k, s) in entries {
+        let h = get(s);
+        let labels = labels(k);
+        for (bound, count) in h.buckets() {
+            let le = if bound.is_infinite() {
+                "+Inf".to_owned()
+            } else {
+                bound.to_string()
+            };
+            writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, le, count).unwrap();
+        }
+        writeln!(out, "{}_sum{{{}}} {}", name, labels, h.sum()).unwrap();
+        writeln!(out, "{}_count{{{}}} {}", name, labels, h.count()).unwrap();
+    }
+}
+
+fn labels(k: &MetricsKey) -> String {
+    format!(
+        "method=\"{}\",client=\"{}\",server=\"{}\"",
+        escape(k.fq_name),
+        escape(&k.client),
+        escape(&k.server)
+    )
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::future::Future;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::executor::ThreadPool;
@@ -10,12 +10,14 @@ use futures::future::FutureExt;
 use futures::select;
 use futures::stream::StreamExt;
 use futures_timer::Delay;
-use log::{debug, error};
+use log::debug;
 use rand::{thread_rng, Rng};
 
-use crate::client::{Client, Rpc};
+use crate::client::{CallOptions, Client, Rpc};
+use crate::context::CallContext;
 use crate::error::{Error, Result};
-use crate::server::Server;
+use crate::metrics::{Metrics, MetricsKey};
+use crate::server::{RpcStream, Server};
 
 #[derive(Debug)]
 struct EndInfo {
@@ -32,6 +34,27 @@ struct Endpoints {
     servers: HashMap<String, Option<Server>>,
     // client_name -> server_name
     connections: HashMap<String, Option<String>>,
+    // client_name -> bytes per second, overrides the default bandwidth
+    bandwidth: HashMap<String, u64>,
+    // bandwidth of links that have no explicit cap
+    default_bandwidth: Option<u64>,
+    // client_name -> pending transmissions on the link
+    queues: HashMap<String, LinkQueue>,
+}
+
+/// Direction of a message on a link.
+#[derive(Clone, Copy, Debug)]
+enum Direction {
+    Request,
+    Reply,
+}
+
+/// A link transmits one message at a time in each direction, a message
+/// has to wait until the ones queued before it have been sent.
+#[derive(Default)]
+struct LinkQueue {
+    request_busy_until: Option<Instant>,
+    reply_busy_until: Option<Instant>,
 }
 
 struct NetworkCore {
@@ -42,6 +65,7 @@ struct NetworkCore {
     long_reordering: AtomicBool,
     endpoints: Mutex<Endpoints>,
     count: AtomicUsize,
+    metrics: Metrics,
     sender: UnboundedSender<Rpc>,
     poller: ThreadPool,
     worker: ThreadPool,
@@ -70,8 +94,12 @@ impl Network {
                     enabled: HashMap::new(),
                     servers: HashMap::new(),
                     connections: HashMap::new(),
+                    bandwidth: HashMap::new(),
+                    default_bandwidth: None,
+                    queues: HashMap::new(),
                 }),
                 count: AtomicUsize::new(0),
+                metrics: Metrics::default(),
                 poller: ThreadPool::builder().pool_size(2).create().unwrap(),
                 worker: ThreadPool::new().unwrap(),
                 sender,
@@ -85,12 +113,25 @@ impl Network {
         let network = self.clone();
         self.core.poller.spawn_ok(async move {
             while let Some(mut rpc) = incoming.next().await {
-                let resp = rpc.take_resp_sender().unwrap();
+                let mut resp = rpc.take_resp_sender().unwrap();
+                let ctx = rpc.ctx.clone();
                 let net = network.clone();
                 network.core.poller.spawn_ok(async move {
-                    let res = net.process_rpc(rpc).await;
+                    let mut process = Box::pin(net.process_rpc(rpc)).fuse();
+                    let res = select! {
+                        res = process => Some(res),
+                        _ = resp.cancellation().fuse() => None,
+                    };
+                    let res = match res {
+                        Some(res) => res,
+                        None => {
+                            // The caller has given up, let the handler know.
+                            ctx.cancel();
+                            process.await
+                        }
+                    };
                     if let Err(e) = resp.send(res) {
-                        error!("fail to send resp: {:?}", e);
+                        debug!("fail to send resp: {:?}", e);
                     }
                 })
             }
@@ -114,11 +155,15 @@ impl Network {
         let mut eps = self.core.endpoints.lock().unwrap();
         eps.enabled.insert(name.clone(), false);
         eps.connections.insert(name.clone(), None);
+        // a new Client does not wait for the messages of the one it replaces
+        eps.queues.remove(&name);
         Client {
             name,
             sender,
             worker: self.core.worker.clone(),
             hooks: Arc::new(Mutex::new(None)),
+            options: CallOptions::default(),
+            metadata: Arc::default(),
         }
     }
 
@@ -153,6 +198,61 @@ impl Network {
         self.core.long_delays.store(yes, Ordering::Release);
     }
 
+    /// Caps the bandwidth of a Client's link in bytes per second, `None`
+    /// falls back to the default bandwidth.
+    ///
+    /// Requests and replies are queued separately, each one is delayed by
+    /// its encoded size divided by the bandwidth plus the time spent
+    /// waiting for the messages queued before it. Messages sent after the
+    /// change do not queue behind the ones sent before it.
+    pub fn set_bandwidth(&self, client_name: &str, bytes_per_sec: Option<u64>) {
+        let mut eps = self.core.endpoints.lock().unwrap();
+        eps.queues.remove(client_name);
+        match bytes_per_sec {
+            Some(bw) => {
+                assert!(bw > 0, "bandwidth must be positive");
+                eps.bandwidth.insert(client_name.to_owned(), bw);
+            }
+            None => {
+                eps.bandwidth.remove(client_name);
+            }
+        }
+    }
+
+    /// Caps the bandwidth of every link without an explicit cap,
+    /// `None` means unlimited.
+    pub fn set_default_bandwidth(&self, bytes_per_sec: Option<u64>) {
+        assert_ne!(bytes_per_sec, Some(0), "bandwidth must be positive");
+        let mut eps = self.core.endpoints.lock().unwrap();
+        eps.default_bandwidth = bytes_per_sec;
+        let Endpoints {
+            bandwidth, queues, ..
+        } = &mut *eps;
+        queues.retain(|client_name, _| bandwidth.contains_key(client_name));
+    }
+
+    /// Reserves the link for a message of `len` bytes, returns how long
+    /// the message takes to arrive, or `None` if the link is unlimited.
+    fn transmit(&self, client_name: &str, dir: Direction, len: usize) -> Option<Duration> {
+        let mut eps = self.core.endpoints.lock().unwrap();
+        let bw = eps
+            .bandwidth
+            .get(client_name)
+            .copied()
+            .or(eps.default_bandwidth)?;
+        let queue = eps.queues.entry(client_name.to_owned()).or_default();
+        let busy_until = match dir {
+            Direction::Request => &mut queue.request_busy_until,
+            Direction::Reply => &mut queue.reply_busy_until,
+        };
+
+        let now = Instant::now();
+        let start = busy_until.map_or(now, |t| t.max(now));
+        let done = start + Duration::from_secs_f64(len as f64 / bw as f64);
+        *busy_until = Some(done);
+        Some(done - now)
+    }
+
     pub fn count(&self, server_name: &str) -> usize {
         let eps = self.core.endpoints.lock().unwrap();
         eps.servers[server_name].as_ref().unwrap().count()
@@ -162,6 +262,16 @@ impl Network {
         self.core.count.load(Ordering::Relaxed)
     }
 
+    /// Total bytes of requests and replies sent through the network.
+    pub fn total_bytes(&self) -> u64 {
+        self.core.metrics.total().bytes()
+    }
+
+    /// Per-method and per-endpoint statistics of RPCs sent through the network.
+    pub fn metrics(&self) -> &Metrics {
+        &self.core.metrics
+    }
+
     fn end_info(&self, client_name: &str) -> EndInfo {
         let eps = self.core.endpoints.lock().unwrap();
         let mut server = None;
@@ -179,15 +289,37 @@ impl Network {
     fn is_server_dead(&self, client_name: &str, server_name: &str, server_id: usize) -> bool {
         let eps = self.core.endpoints.lock().unwrap();
         !eps.enabled[client_name]
-            || eps.servers.get(server_name).map_or(true, |o| {
-                o.as_ref().map(|s| s.core.id != server_id).unwrap_or(true)
-            })
+            || eps
+                .servers
+                .get(server_name)
+                .is_none_or(|o| o.as_ref().map(|s| s.core.id != server_id).unwrap_or(true))
     }
 
     async fn process_rpc(&self, rpc: Rpc) -> Result<Vec<u8>> {
         self.core.count.fetch_add(1, Ordering::Relaxed);
-        let network = self.clone();
         let end_info = self.end_info(&rpc.client_name);
+        let key = MetricsKey {
+            fq_name: rpc.fq_name,
+            client: rpc.client_name.clone(),
+            server: end_info
+                .server
+                .as_ref()
+                .map(|s| s.name().to_owned())
+                .unwrap_or_default(),
+        };
+        let req_len = rpc.req.as_ref().map_or(0, Vec::len);
+
+        let start = Instant::now();
+        let res = self.deliver(rpc, end_info).await;
+        self.core
+            .metrics
+            .record(key, start.elapsed(), req_len, &res);
+        res
+    }
+
+    async fn deliver(&self, rpc: Rpc, end_info: EndInfo) -> Result<Vec<u8>> {
+        let network = self.clone();
+        let ctx = rpc.ctx.clone();
         // debug!("{:?} process with {:?}", rpc, end_info);
         let EndInfo {
             enabled,
@@ -208,7 +340,7 @@ impl Network {
 
                 if !reliable && (thread_rng().gen::<u64>() % 1000) < 100 {
                     // drop the request, return as if timeout
-                    Delay::new(Duration::from_secs(short_delay.unwrap())).await;
+                    sleep(Duration::from_secs(short_delay.unwrap()), &ctx).await?;
                     return Err(Error::Timeout);
                 }
 
@@ -246,7 +378,7 @@ impl Network {
                 };
 
                 debug!("{:?} delay {}ms then timeout", rpc, ms);
-                Delay::new(Duration::from_millis(ms)).await;
+                sleep(Duration::from_millis(ms), &ctx).await?;
                 Err(Error::Timeout)
             }
         }
@@ -278,8 +410,9 @@ async fn process_rpc(
     server: Server,
 ) -> Result<Vec<u8>> {
     // Dispatch ===============================================================
+    let ctx = rpc.ctx.clone();
     if let Some(delay) = delay {
-        Delay::new(Duration::from_millis(delay)).await;
+        sleep(Duration::from_millis(delay), &ctx).await?;
     }
     // We has finished the delay, take it out to prevent polling
     // twice.
@@ -287,9 +420,15 @@ async fn process_rpc(
 
     let fq_name = rpc.fq_name;
     let req = rpc.req.take().unwrap();
+    if let Some(d) = network.transmit(&rpc.client_name, Direction::Request, req.len()) {
+        sleep(d, &ctx).await?;
+    }
     if let Some(hooks) = rpc.hooks.lock().unwrap().as_ref() {
         hooks.before_dispatch(fq_name, &req)?;
     }
+    if rpc.stream.is_some() {
+        return open_stream(drop_reply, rpc, req, network, server).await;
+    }
 
     // Execute the request (call the RPC handler) in a separate thread so that
     // we can periodically check if the server has been killed and the RPC
@@ -299,15 +438,32 @@ async fn process_rpc(
     // this is needed to avoid situation in which a client gets a positive reply
     // to an Append, but the server persisted the update into the old Persister.
     // config.go is careful to call DeleteServer() before superseding the Persister.
+    //
+    // If the caller gives up, the handler keeps running in the background so
+    // that it can observe the cancellation through its `CallContext`.
+    let mut handler = server
+        .dispatch_with_context(fq_name, req, ctx.clone())
+        .fuse();
     let resp = select! {
-        res = server.dispatch(fq_name, &req).fuse() => res,
+        res = handler => Some(res),
         _ = server_dead(
             Duration::from_millis(100),
             network.clone(),
             &rpc.client_name,
             &server.core.name,
             server.core.id,
-        ).fuse() => Err(Error::Stopped),
+        ).fuse() => Some(Err(Error::Stopped)),
+        _ = ctx.cancelled().fuse() => None,
+    };
+    let resp = match resp {
+        // A handler that observed the cancellation may finish at the same
+        // time, the caller has given up anyway.
+        Some(_) if ctx.is_cancelled() => return Err(cancelled_error(&ctx)),
+        Some(resp) => resp,
+        None => {
+            network.spawn(handler.map(|_| ()));
+            return Err(cancelled_error(&ctx));
+        }
     };
 
     let resp = if let Some(hooks) = rpc.hooks.lock().unwrap().as_ref() {
@@ -327,17 +483,135 @@ async fn process_rpc(
         // drop the reply, return as if timeout.
         return Err(Error::Timeout);
     }
+    if let Some(d) = network.transmit(client_name, Direction::Reply, resp.len()) {
+        sleep(d, &ctx).await?;
+    }
 
     // Reordering =============================================================
     if let Some(reordering) = long_reordering {
         debug!("{:?} next long reordering {}ms", rpc, reordering);
-        Delay::new(Duration::from_millis(reordering)).await;
+        sleep(Duration::from_millis(reordering), &ctx).await?;
         Ok(resp)
     } else {
         Ok(resp)
     }
 }
 
+/// Starts a streaming call, the messages are forwarded to the caller in
+/// the background once the handler has accepted the call.
+async fn open_stream(
+    drop_reply: bool,
+    mut rpc: Rpc,
+    req: Vec<u8>,
+    network: Network,
+    server: Server,
+) -> Result<Vec<u8>> {
+    let ctx = rpc.ctx.clone();
+    let mut setup = server
+        .dispatch_stream_with_context(rpc.fq_name, req, ctx.clone())
+        .fuse();
+    let items = select! {
+        res = setup => res?,
+        _ = server_dead(
+            Duration::from_millis(100),
+            network.clone(),
+            &rpc.client_name,
+            &server.core.name,
+            server.core.id,
+        ).fuse() => return Err(Error::Stopped),
+        _ = ctx.cancelled().fuse() => return Err(cancelled_error(&ctx)),
+    };
+
+    if network.is_server_dead(&rpc.client_name, &server.core.name, server.core.id) {
+        return Err(Error::Stopped);
+    }
+    if drop_reply {
+        // drop the reply, return as if timeout.
+        return Err(Error::Timeout);
+    }
+    let sender = rpc.stream.take().unwrap();
+    network.spawn(forward_stream(items, sender, rpc, network.clone(), server));
+    Ok(vec![])
+}
+
+/// Forwards the messages of a streaming reply until the stream ends, fails,
+/// or the caller gives up.
+///
+/// Every message goes through the reply hooks and the link like a unary
+/// reply does, the stream fails with `Error::Stopped` once the server is
+/// killed or the link is disabled.
+async fn forward_stream(
+    mut items: RpcStream<Vec<u8>>,
+    sender: UnboundedSender<Result<Vec<u8>>>,
+    rpc: Rpc,
+    network: Network,
+    server: Server,
+) {
+    let ctx = rpc.ctx.clone();
+    let client_name = &rpc.client_name;
+    let server_name = &server.core.name;
+    let server_id = server.core.id;
+    let key = MetricsKey {
+        fq_name: rpc.fq_name,
+        client: client_name.clone(),
+        server: server_name.clone(),
+    };
+    loop {
+        let item = select! {
+            item = items.next().fuse() => item,
+            _ = server_dead(
+                Duration::from_millis(100),
+                network.clone(),
+                client_name,
+                server_name,
+                server_id,
+            ).fuse() => Some(Err(Error::Stopped)),
+            _ = ctx.cancelled().fuse() => Some(Err(cancelled_error(&ctx))),
+        };
+        let mut item = match item {
+            Some(item) => item,
+            None => return,
+        };
+        if let Some(hooks) = rpc.hooks.lock().unwrap().as_ref() {
+            item = hooks.after_dispatch(rpc.fq_name, item);
+        }
+        if let Ok(msg) = &item {
+            if network.is_server_dead(client_name, server_name, server_id) {
+                item = Err(Error::Stopped);
+            } else {
+                network.core.metrics.record_stream_item(&key, msg.len());
+                if let Some(d) = network.transmit(client_name, Direction::Reply, msg.len()) {
+                    if let Err(e) = sleep(d, &ctx).await {
+                        item = Err(e);
+                    }
+                }
+            }
+        }
+
+        let end = item.is_err();
+        if sender.unbounded_send(item).is_err() || end {
+            return;
+        }
+    }
+}
+
+/// Sleeps for `d`, or fails early if the caller gives up on the call.
+async fn sleep(d: Duration, ctx: &CallContext) -> Result<()> {
+    select! {
+        _ = Delay::new(d).fuse() => Ok(()),
+        _ = ctx.cancelled().fuse() => Err(cancelled_error(ctx)),
+    }
+}
+
+fn cancelled_error(ctx: &CallContext) -> Error {
+    if ctx.deadline_exceeded() {
+        Error::DeadlineExceeded
+    } else {
+        // Nobody is waiting for the reply.
+        Error::Stopped
+    }
+}
+
 /// Checks if the specified server killed.
 ///
 /// It will return when the server is killed.
@@ -4,23 +4,53 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use futures::future::{self, BoxFuture};
+use futures::stream::{BoxStream, StreamExt};
 
+use crate::context::CallContext;
 use crate::error::{Error, Result};
+use crate::interceptor::{Interceptor, Next, ServerCall};
 
 static ID_ALLOC: AtomicUsize = AtomicUsize::new(0);
 
 pub type RpcFuture<T> = BoxFuture<'static, T>;
 
-pub type Handler = dyn FnOnce(&[u8]) -> RpcFuture<Result<Vec<u8>>>;
+/// Messages of a streaming reply, the stream ends after the first error.
+pub type RpcStream<T> = BoxStream<'static, Result<T>>;
+
+pub type Handler = dyn FnOnce(&[u8]) -> RpcFuture<Result<Vec<u8>>> + Send;
+
+pub type StreamHandler = dyn FnOnce(&[u8]) -> RpcFuture<Result<RpcStream<Vec<u8>>>> + Send;
 
 pub trait HandlerFactory: Sync + Send + 'static {
     fn handler(&self, name: &'static str) -> Box<Handler>;
+
+    /// Handler of a method that replies with a stream of messages.
+    fn stream_handler(&self, name: &'static str) -> Box<StreamHandler> {
+        Box::new(move |_| {
+            Box::pin(future::err(Error::Unimplemented(format!(
+                "{} is not a streaming method",
+                name
+            ))))
+        })
+    }
+}
+
+/// Encodes the messages of a streaming reply, used by `service!`.
+#[doc(hidden)]
+pub fn encode_stream<T: labcodec::Message + 'static>(items: RpcStream<T>) -> RpcStream<Vec<u8>> {
+    Box::pin(items.map(|item| {
+        let item = item?;
+        let mut buf = vec![];
+        labcodec::encode(&item, &mut buf).map_err(Error::Encode)?;
+        Ok(buf)
+    }))
 }
 
 pub struct ServerBuilder {
     name: String,
     // Service name -> service methods
     pub(crate) services: HashMap<&'static str, Box<dyn HandlerFactory>>,
+    interceptors: Vec<Arc<dyn Interceptor>>,
 }
 
 impl ServerBuilder {
@@ -28,9 +58,16 @@ impl ServerBuilder {
         ServerBuilder {
             name,
             services: HashMap::new(),
+            interceptors: vec![],
         }
     }
 
+    /// Appends an interceptor to the chain every call goes through, the
+    /// first one added sees a call first.
+    pub fn add_interceptor(&mut self, interceptor: Arc<dyn Interceptor>) {
+        self.interceptors.push(interceptor);
+    }
+
     pub fn add_service(
         &mut self,
         service_name: &'static str,
@@ -53,6 +90,7 @@ impl ServerBuilder {
             core: Arc::new(ServerCore {
                 name: self.name,
                 services: self.services,
+                interceptors: self.interceptors.into(),
                 id: ID_ALLOC.fetch_add(1, Ordering::Relaxed),
                 count: AtomicUsize::new(0),
             }),
@@ -65,6 +103,7 @@ pub(crate) struct ServerCore {
     pub(crate) id: usize,
 
     pub(crate) services: HashMap<&'static str, Box<dyn HandlerFactory>>,
+    interceptors: Arc<[Arc<dyn Interceptor>]>,
     pub(crate) count: AtomicUsize,
 }
 
@@ -82,35 +121,76 @@ impl Server {
         &self.core.name
     }
 
+    #[cfg(test)]
     pub(crate) fn dispatch(&self, fq_name: &'static str, req: &[u8]) -> RpcFuture<Result<Vec<u8>>> {
-        self.core.count.fetch_add(1, Ordering::Relaxed);
-        let mut names = fq_name.split('.');
-        let service_name = match names.next() {
-            Some(n) => n,
-            None => {
-                return Box::pin(future::err(Error::Unimplemented(format!(
-                    "unknown {}",
-                    fq_name
-                ))));
+        self.dispatch_with_context(fq_name, req.to_vec(), CallContext::default())
+    }
+
+    /// Dispatches a request through the interceptors, the handler can see
+    /// `ctx` through `CallContext::current`.
+    pub(crate) fn dispatch_with_context(
+        &self,
+        fq_name: &'static str,
+        req: Vec<u8>,
+        ctx: CallContext,
+    ) -> RpcFuture<Result<Vec<u8>>> {
+        match self.factory(fq_name) {
+            Ok((factory, method_name)) => {
+                let handle = factory.handler(method_name);
+                let next = Next::new(
+                    self.core.interceptors.clone(),
+                    Box::new(move |call: ServerCall| handle(&call.req)),
+                );
+                let call = ServerCall {
+                    fq_name,
+                    req,
+                    ctx: ctx.clone(),
+                };
+                Box::pin(ctx.scope(next.run(call)))
             }
-        };
-        let method_name = match names.next() {
-            Some(n) => n,
-            None => {
-                return Box::pin(future::err(Error::Unimplemented(format!(
-                    "unknown {}",
-                    fq_name
-                ))));
+            Err(e) => Box::pin(future::err(e)),
+        }
+    }
+
+    /// Dispatches a request to a streaming method, the handler can see `ctx`
+    /// through `CallContext::current` while the reply stream is polled too.
+    pub(crate) fn dispatch_stream_with_context(
+        &self,
+        fq_name: &'static str,
+        req: Vec<u8>,
+        ctx: CallContext,
+    ) -> RpcFuture<Result<RpcStream<Vec<u8>>>> {
+        match self.factory(fq_name) {
+            Ok((factory, method_name)) => {
+                let handle = factory.stream_handler(method_name);
+                let next = Next::new(
+                    self.core.interceptors.clone(),
+                    Box::new(move |call: ServerCall| handle(&call.req)),
+                );
+                let call = ServerCall {
+                    fq_name,
+                    req,
+                    ctx: ctx.clone(),
+                };
+                let setup = ctx.clone().scope(next.run(call));
+                Box::pin(async move {
+                    let items = setup.await?;
+                    Ok(Box::pin(ctx.scope(items)) as RpcStream<Vec<u8>>)
+                })
             }
-        };
-        if let Some(factory) = self.core.services.get(service_name) {
-            let handle = factory.handler(method_name);
-            handle(req)
-        } else {
-            Box::pin(future::err(Error::Unimplemented(format!(
-                "unknown {}",
-                fq_name
-            ))))
+            Err(e) => Box::pin(future::err(e)),
+        }
+    }
+
+    fn factory(&self, fq_name: &'static str) -> Result<(&dyn HandlerFactory, &'static str)> {
+        self.core.count.fetch_add(1, Ordering::Relaxed);
+        let mut names = fq_name.split('.');
+        let unknown = || Error::Unimplemented(format!("unknown {}", fq_name));
+        let service_name = names.next().ok_or_else(unknown)?;
+        let method_name = names.next().ok_or_else(unknown)?;
+        match self.core.services.get(service_name) {
+            Some(factory) => Ok((factory.as_ref(), method_name)),
+            None => Err(unknown()),
         }
     }
 }
@@ -0,0 +1,27 @@
+use std::fs;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use linearizability::check_events;
+use linearizability::history::LogFormat;
+use linearizability::models::KvModel;
+
+fn bench_kv(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kv");
+    // A single check of c50 is slow.
+    group.sample_size(10);
+    for (name, linearizable) in [("c50-ok", true), ("c50-bad", false)] {
+        let log = fs::read(format!("test_data/{}.txt", name)).unwrap();
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || KvModel::read_log(&log[..]).unwrap(),
+                |events| assert_eq!(check_events(KvModel {}, events), linearizable),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_kv);
+criterion_main!(benches);
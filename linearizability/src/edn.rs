@@ -0,0 +1,272 @@
+use std::fmt::{self, Display};
+
+/// The subset of EDN used by history logs: `{:process 0, :type :ok, :f
+/// :read, :value [1 "a" nil]}`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edn {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    /// Written as a string, printable ASCII is kept as is and other bytes
+    /// are escaped as `\xNN`.
+    Bytes(Vec<u8>),
+    Keyword(String),
+    Vector(Vec<Edn>),
+    /// Keeps the order of the entries, keys are keywords.
+    Map(Vec<(String, Edn)>),
+}
+
+impl Edn {
+    pub fn str(s: &str) -> Edn {
+        Edn::Bytes(s.as_bytes().to_vec())
+    }
+
+    pub fn keyword(k: &str) -> Edn {
+        Edn::Keyword(k.to_owned())
+    }
+
+    pub fn as_int(&self) -> Result<i64, String> {
+        match self {
+            Edn::Int(i) => Ok(*i),
+            _ => Err(format!("expected an integer, got {}", self)),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&[u8], String> {
+        match self {
+            Edn::Bytes(b) => Ok(b),
+            _ => Err(format!("expected a string, got {}", self)),
+        }
+    }
+
+    pub fn as_string(&self) -> Result<String, String> {
+        String::from_utf8(self.as_bytes()?.to_vec()).map_err(|e| e.to_string())
+    }
+
+    pub fn as_keyword(&self) -> Result<&str, String> {
+        match self {
+            Edn::Keyword(k) => Ok(k),
+            _ => Err(format!("expected a keyword, got {}", self)),
+        }
+    }
+
+    pub fn as_vector(&self) -> Result<&[Edn], String> {
+        match self {
+            Edn::Vector(v) => Ok(v),
+            _ => Err(format!("expected a vector, got {}", self)),
+        }
+    }
+
+    /// Looks `key` up in a map, a missing key is `nil`.
+    pub fn get(&self, key: &str) -> &Edn {
+        match self {
+            Edn::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .unwrap_or(&Edn::Nil),
+            _ => &Edn::Nil,
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Edn, String> {
+        let mut parser = Parser {
+            s: s.as_bytes(),
+            pos: 0,
+        };
+        let edn = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != s.len() {
+            return Err(format!("trailing characters at {}", parser.pos));
+        }
+        Ok(edn)
+    }
+}
+
+impl Display for Edn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edn::Nil => write!(f, "nil"),
+            Edn::Bool(b) => write!(f, "{}", b),
+            Edn::Int(i) => write!(f, "{}", i),
+            Edn::Bytes(bytes) => {
+                write!(f, "\"")?;
+                for &b in bytes {
+                    match b {
+                        b'"' => write!(f, "\\\"")?,
+                        b'\\' => write!(f, "\\\\")?,
+                        0x20..=0x7e => write!(f, "{}", b as char)?,
+                        _ => write!(f, "\\x{:02x}", b)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Edn::Keyword(k) => write!(f, ":{}", k),
+            Edn::Vector(v) => {
+                write!(f, "[")?;
+                for (i, e) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                write!(f, "]")
+            }
+            Edn::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, ":{} {}", k, v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    // Commas are whitespace in EDN.
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_ascii_whitespace() || c == b',') {
+            self.pos += 1;
+        }
+    }
+
+    fn error<T>(&self, what: &str) -> Result<T, String> {
+        Err(format!("{} at {}", what, self.pos))
+    }
+
+    fn value(&mut self) -> Result<Edn, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.map(),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut v = vec![];
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        return Ok(Edn::Vector(v));
+                    }
+                    v.push(self.value()?);
+                }
+            }
+            Some(b'"') => self.string(),
+            Some(b':') => {
+                self.pos += 1;
+                Ok(Edn::Keyword(self.symbol()?))
+            }
+            Some(c) if c == b'-' || c.is_ascii_digit() => {
+                let s = self.symbol()?;
+                s.parse()
+                    .map(Edn::Int)
+                    .map_err(|_| format!("bad integer {:?}", s))
+            }
+            Some(_) => match self.symbol()?.as_str() {
+                "nil" => Ok(Edn::Nil),
+                "true" => Ok(Edn::Bool(true)),
+                "false" => Ok(Edn::Bool(false)),
+                s => Err(format!("unknown symbol {:?}", s)),
+            },
+            None => self.error("unexpected end"),
+        }
+    }
+
+    fn map(&mut self) -> Result<Edn, String> {
+        self.pos += 1;
+        let mut entries = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some(b'}') {
+                self.pos += 1;
+                return Ok(Edn::Map(entries));
+            }
+            let key = match self.value()? {
+                Edn::Keyword(k) => k,
+                _ => return self.error("expected a keyword"),
+            };
+            entries.push((key, self.value()?));
+        }
+    }
+
+    fn symbol(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || b"-_?!*+./".contains(&c))
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return self.error("expected a symbol");
+        }
+        Ok(String::from_utf8_lossy(&self.s[start..self.pos]).into_owned())
+    }
+
+    fn string(&mut self) -> Result<Edn, String> {
+        self.pos += 1;
+        let mut bytes = vec![];
+        loop {
+            match self.next() {
+                Some(b'"') => return Ok(Edn::Bytes(bytes)),
+                Some(b'\\') => match self.next() {
+                    Some(b'x') => {
+                        let hex = self.s.get(self.pos..self.pos + 2).unwrap_or_default();
+                        let hex = std::str::from_utf8(hex).map_err(|e| e.to_string())?;
+                        bytes.push(u8::from_str_radix(hex, 16).map_err(|e| e.to_string())?);
+                        self.pos += 2;
+                    }
+                    Some(b'n') => bytes.push(b'\n'),
+                    Some(b't') => bytes.push(b'\t'),
+                    Some(c @ b'"') | Some(c @ b'\\') => bytes.push(c),
+                    _ => return self.error("bad escape"),
+                },
+                Some(c) => bytes.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Edn;
+
+    #[test]
+    fn test_edn() {
+        let s = r#"{:process 3, :type :ok, :f :txn, :value [[:r "k\"\\" nil] [:w "j" "\x00\xff"] -7 true]}"#;
+        let edn = Edn::parse(s).unwrap();
+        assert_eq!(edn.get("process").as_int(), Ok(3));
+        assert_eq!(edn.get("type").as_keyword(), Ok("ok"));
+        assert_eq!(edn.get("missing"), &Edn::Nil);
+        let value = edn.get("value").as_vector().unwrap();
+        assert_eq!(
+            value[0].as_vector().unwrap()[1].as_bytes(),
+            Ok(&b"k\"\\"[..])
+        );
+        assert_eq!(value[1].as_vector().unwrap()[2], Edn::Bytes(vec![0, 0xff]));
+        assert_eq!(value[2], Edn::Int(-7));
+        assert_eq!(value[3], Edn::Bool(true));
+        assert_eq!(edn.to_string(), s);
+
+        assert!(Edn::parse("{:a 1").is_err());
+        assert!(Edn::parse("[1] 2").is_err());
+        assert!(Edn::parse("{1 2}").is_err());
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::edn::Edn;
+use crate::model::{Event, EventKind, Events, Model, Operation, Operations, Value};
+
+/// Collects the operations of many concurrent clients into one history,
+/// timestamped with a clock shared by all of them.
+///
+/// Clones share the history, so a recorder can be handed to every client
+/// thread and taken back once they are done.
+pub struct Recorder<I: Debug, O: Debug> {
+    start: Instant,
+    history: Arc<Mutex<Operations<I, O>>>,
+}
+
+impl<I: Debug, O: Debug> Clone for Recorder<I, O> {
+    fn clone(&self) -> Self {
+        Recorder {
+            start: self.start,
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl<I: Debug, O: Debug> Default for Recorder<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Debug, O: Debug> Recorder<I, O> {
+    pub fn new() -> Self {
+        Recorder {
+            start: Instant::now(),
+            history: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Nanoseconds since the recorder was created.
+    pub fn now(&self) -> i64 {
+        self.start.elapsed().as_nanos() as i64
+    }
+
+    /// Starts an operation of `client_id`. It is recorded once the returned
+    /// call is finished, or as pending if the call is dropped first.
+    pub fn call(&self, client_id: usize, input: I) -> Call<I, O> {
+        Call {
+            recorder: self.clone(),
+            client_id,
+            input: Some(input),
+            call: self.now(),
+        }
+    }
+
+    /// Records `f` as an operation of `client_id` that always completes.
+    pub fn record<F>(&self, client_id: usize, input: I, f: F) -> O
+    where
+        O: Clone,
+        F: FnOnce(&I) -> O,
+    {
+        let call = self.call(client_id, input);
+        let output = f(call.input());
+        call.finish(output.clone());
+        output
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes the operations recorded so far, in completion order.
+    pub fn take(&self) -> Operations<I, O> {
+        mem::take(&mut *self.history.lock().unwrap())
+    }
+
+    fn push(&self, op: Operation<I, O>) {
+        self.history.lock().unwrap().push(op);
+    }
+}
+
+/// An operation in progress, see `Recorder::call`.
+pub struct Call<I: Debug, O: Debug> {
+    recorder: Recorder<I, O>,
+    client_id: usize,
+    // Taken when the call is recorded.
+    input: Option<I>,
+    call: i64,
+}
+
+impl<I: Debug, O: Debug> Call<I, O> {
+    pub fn input(&self) -> &I {
+        self.input.as_ref().unwrap()
+    }
+
+    /// For operations whose input is only known as they go, like
+    /// transactions.
+    pub fn input_mut(&mut self) -> &mut I {
+        self.input.as_mut().unwrap()
+    }
+
+    /// Records the operation as completed with `output`.
+    pub fn finish(mut self, output: O) {
+        self.record(Some(output));
+    }
+
+    /// Records the operation as pending, it may or may not have taken effect.
+    pub fn unknown(mut self) {
+        self.record(None);
+    }
+
+    /// Leaves the operation out of the history, for one known to have had
+    /// no effect, like a transaction that was never committed.
+    pub fn discard(mut self) {
+        self.input = None;
+    }
+
+    fn record(&mut self, output: Option<O>) {
+        if let Some(input) = self.input.take() {
+            // The finish time of a pending operation is ignored by the
+            // checker, it is kept to place the operation in a log.
+            self.recorder.push(Operation {
+                client_id: self.client_id,
+                input,
+                call: self.call,
+                output,
+                finish: self.recorder.now(),
+            });
+        }
+    }
+}
+
+impl<I: Debug, O: Debug> Drop for Call<I, O> {
+    fn drop(&mut self) {
+        self.record(None);
+    }
+}
+
+/// Converts an event history, like one read from a log, to operations using
+/// the positions of the events as times. Operations that never returned are
+/// pending.
+pub fn operations_from_events<I: Debug, O: Debug>(events: Events<I, O>) -> Operations<I, O> {
+    let mut ops: Vec<Option<Operation<I, O>>> = vec![];
+    let mut index = HashMap::new();
+    for (time, event) in events.into_iter().enumerate() {
+        match (event.kind, event.value) {
+            (EventKind::CallEvent, Value::Input(input)) => {
+                index.insert(event.id, ops.len());
+                ops.push(Some(Operation::pending(
+                    event.client_id,
+                    input,
+                    time as i64,
+                )));
+            }
+            (EventKind::ReturnEvent, Value::Output(output)) => {
+                let op = ops[index[&event.id]].as_mut().unwrap();
+                op.output = Some(output);
+                op.finish = time as i64;
+            }
+            (EventKind::ReturnEvent, _) => {}
+            (EventKind::CallEvent, _) => panic!("call event without input"),
+        }
+    }
+    ops.into_iter().map(Option::unwrap).collect()
+}
+
+/// The type of a completion in a log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogType {
+    Ok,
+    /// The operation completed without taking effect, like an aborted
+    /// transaction or a failed compare-and-set.
+    Fail,
+}
+
+/// Models whose histories can be saved as Jepsen style logs, one EDN map
+/// per event like the files in `test_data`:
+///
+/// ```text
+/// {:process 0, :type :invoke, :f :put, :key "0", :value "x 0 0 y"}
+/// {:process 0, :type :ok, :f :put, :key "0", :value "x 0 0 y"}
+/// ```
+///
+/// Operations with an unknown outcome complete with `:type :info`.
+pub trait LogFormat: Model {
+    /// The entries after `:type` of an invocation, starting with `:f`.
+    fn encode_call(input: &Self::Input) -> Vec<(&'static str, Edn)>;
+
+    /// The type and entries after `:type` of a completion.
+    fn encode_return(
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (LogType, Vec<(&'static str, Edn)>);
+
+    fn decode_call(event: &Edn) -> Result<Self::Input, String>;
+
+    fn decode_return(kind: LogType, event: &Edn) -> Result<Self::Output, String>;
+
+    /// Writes a history as a log in call and return order.
+    fn write_log<W: Write>(
+        mut w: W,
+        history: &Operations<Self::Input, Self::Output>,
+    ) -> io::Result<()> {
+        let line = |client_id: usize, kind: &str, entries: Vec<(&str, Edn)>| {
+            let mut map = vec![
+                ("process".to_owned(), Edn::Int(client_id as i64)),
+                ("type".to_owned(), Edn::keyword(kind)),
+            ];
+            map.extend(entries.into_iter().map(|(k, v)| (k.to_owned(), v)));
+            Edn::Map(map).to_string()
+        };
+        // Calls come before returns with the same timestamp, the sort is
+        // stable.
+        let mut lines = vec![];
+        for op in history {
+            let call = Self::encode_call(&op.input);
+            lines.push((op.call, line(op.client_id, "invoke", call)));
+        }
+        for op in history {
+            let (kind, entries) = match &op.output {
+                Some(output) => match Self::encode_return(&op.input, output) {
+                    (LogType::Ok, entries) => ("ok", entries),
+                    (LogType::Fail, entries) => ("fail", entries),
+                },
+                None => ("info", Self::encode_call(&op.input)),
+            };
+            lines.push((op.finish, line(op.client_id, kind, entries)));
+        }
+        lines.sort_by_key(|(time, _)| *time);
+        for (_, line) in lines {
+            writeln!(w, "{}", line)?;
+        }
+        w.flush()
+    }
+
+    /// Reads a log, invocations that never complete are pending.
+    fn read_log<R: BufRead>(r: R) -> io::Result<Events<Self::Input, Self::Output>> {
+        let mut events = vec![];
+        let mut id = 0;
+        // Operation in progress of each process.
+        let mut processes: HashMap<usize, usize> = HashMap::new();
+        for (n, line) in r.lines().enumerate() {
+            let line = line?;
+            let error = |msg: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", n + 1, msg),
+                )
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = Edn::parse(&line).map_err(error)?;
+            let client_id = event.get("process").as_int().map_err(error)? as usize;
+            let kind = event.get("type").as_keyword().map_err(error)?;
+            if kind == "invoke" {
+                let input = Self::decode_call(&event).map_err(error)?;
+                if processes.insert(client_id, id).is_some() {
+                    return Err(error(format!("process {} is already busy", client_id)));
+                }
+                events.push(Event {
+                    client_id,
+                    kind: EventKind::CallEvent,
+                    value: Value::Input(input),
+                    id,
+                });
+                id += 1;
+                continue;
+            }
+            let value = match kind {
+                "ok" => Value::Output(Self::decode_return(LogType::Ok, &event).map_err(error)?),
+                "fail" => Value::Output(Self::decode_return(LogType::Fail, &event).map_err(error)?),
+                "info" => Value::None,
+                _ => return Err(error(format!("unknown type {:?}", kind))),
+            };
+            let call = processes
+                .remove(&client_id)
+                .ok_or_else(|| error(format!("process {} is idle", client_id)))?;
+            events.push(Event {
+                client_id,
+                kind: EventKind::ReturnEvent,
+                value,
+                id: call,
+            });
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::thread;
+
+    use super::*;
+    use crate::models::{KvInput, KvModel, KvOutput, Op, TxnInput, TxnModel, TxnOp, TxnOutput};
+    use crate::{check_events, check_operations};
+
+    #[test]
+    fn test_recorder() {
+        let recorder: Recorder<KvInput, KvOutput> = Recorder::new();
+        let threads: Vec<_> = (0..4)
+            .map(|client_id| {
+                let recorder = recorder.clone();
+                thread::spawn(move || {
+                    let input = KvInput {
+                        op: Op::Append,
+                        key: "x".to_owned(),
+                        value: client_id.to_string(),
+                    };
+                    recorder.record(client_id, input, |_| KvOutput::default());
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        // Dropped before finishing.
+        let call = recorder.call(
+            4,
+            KvInput {
+                op: Op::Put,
+                key: "x".to_owned(),
+                value: "4".to_owned(),
+            },
+        );
+        drop(call);
+        // Left out.
+        recorder
+            .call(
+                6,
+                KvInput {
+                    op: Op::Put,
+                    key: "y".to_owned(),
+                    value: "6".to_owned(),
+                },
+            )
+            .discard();
+        recorder.record(
+            5,
+            KvInput {
+                op: Op::Get,
+                key: "y".to_owned(),
+                value: String::new(),
+            },
+            |_| KvOutput::default(),
+        );
+
+        assert_eq!(recorder.len(), 6);
+        let history = recorder.take();
+        assert!(recorder.is_empty());
+        assert_eq!(history.iter().filter(|op| op.is_pending()).count(), 1);
+        for op in &history {
+            assert!(op.call <= op.finish);
+        }
+        assert!(check_operations(KvModel {}, history));
+    }
+
+    #[test]
+    fn test_kv_log() {
+        for (log, ok) in [("c10-ok", true), ("c10-bad", false)] {
+            let file = File::open(format!("../linearizability/test_data/{}.txt", log)).unwrap();
+            let events = KvModel::read_log(BufReader::new(file)).unwrap();
+            let n = events.len();
+            let history = operations_from_events(events);
+
+            let mut log = vec![];
+            KvModel::write_log(&mut log, &history).unwrap();
+            let events = KvModel::read_log(&log[..]).unwrap();
+            assert_eq!(events.len(), n);
+            assert_eq!(check_events(KvModel {}, events), ok);
+            assert_eq!(check_operations(KvModel {}, history), ok);
+        }
+
+        let log = "{:process 0, :type :invoke, :f :put, :key \"x\", :value \"1\"}\n\
+                   {:process 1, :type :invoke, :f :get, :key \"x\", :value nil}\n\
+                   {:process 0, :type :info, :f :put, :key \"x\", :value \"1\"}\n\
+                   {:process 1, :type :ok, :f :get, :key \"x\", :value \"1\"}\n";
+        let history = operations_from_events(KvModel::read_log(log.as_bytes()).unwrap());
+        assert!(history[0].is_pending());
+        assert_eq!(history[1].output.as_ref().unwrap().value, "1");
+        assert!(check_operations(KvModel {}, history));
+
+        // A return without a call.
+        let log = "{:process 0, :type :ok, :f :get, :key \"x\", :value nil}";
+        match KvModel::read_log(log.as_bytes()) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("parsed a return without a call"),
+        }
+    }
+
+    #[test]
+    fn test_txn_log() {
+        let recorder: Recorder<TxnInput, TxnOutput> = Recorder::new();
+        let mut call = recorder.call(0, TxnInput::default());
+        call.input_mut()
+            .ops
+            .push(TxnOp::Write(b"k\"\\".to_vec(), vec![0, 0xff]));
+        call.finish(TxnOutput {
+            reads: vec![],
+            committed: true,
+        });
+        recorder.record(
+            1,
+            TxnInput {
+                ops: vec![
+                    TxnOp::Read(b"k\"\\".to_vec()),
+                    TxnOp::Write(b"j".to_vec(), vec![]),
+                ],
+            },
+            |_| TxnOutput {
+                reads: vec![vec![0, 0xff]],
+                committed: false,
+            },
+        );
+        recorder.call(2, TxnInput::default()).unknown();
+        let history = recorder.take();
+
+        let mut log = vec![];
+        TxnModel::write_log(&mut log, &history).unwrap();
+        let text = String::from_utf8(log.clone()).unwrap();
+        assert!(text.contains(r#":type :ok, :f :txn, :value [[:w "k\"\\" "\x00\xff"]]"#));
+        assert!(text.contains(":type :fail"));
+        assert!(text.contains(":type :info"));
+
+        let reloaded = operations_from_events(TxnModel::read_log(&log[..]).unwrap());
+        assert_eq!(reloaded.len(), 3);
+        for (a, b) in history.iter().zip(&reloaded) {
+            assert_eq!(a.input.ops, b.input.ops);
+            assert_eq!(a.is_pending(), b.is_pending());
+            if let (Some(a), Some(b)) = (&a.output, &b.output) {
+                assert_eq!(a.reads, b.reads);
+                assert_eq!(a.committed, b.committed);
+            }
+        }
+        assert!(check_operations(TxnModel {}, reloaded));
+    }
+}
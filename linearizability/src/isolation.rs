@@ -0,0 +1,536 @@
+//! Checks histories of key-value transactions for the anomalies of Adya's
+//! "Weak Consistency" thesis, which serializability and snapshot isolation
+//! are defined by, instead of looking for a linearization.
+//!
+//! Transactions are the nodes of a dependency graph. `Ti -ww-> Tj` if `Tj`
+//! overwrites a version `Ti` wrote, `Ti -wr-> Tj` if `Tj` reads a version
+//! `Ti` wrote and `Ti -rw-> Tj` if `Tj` overwrites a version `Ti` read.
+//! Stores do not tell in which order versions were installed, so it is
+//! inferred: the initial, missing, version comes before any other and a
+//! transaction that reads a key and then writes it installs the version
+//! right after the one it read. Other orders are not guessed, so the
+//! checker may miss anomalies but never reports false ones. Real-time
+//! order is not taken into account either, `TxnModel` checks that.
+//!
+//! Reads are attributed to writes by value, so each value must be written
+//! to a key at most once, and the empty value stands for a missing key.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{self, Display};
+
+use crate::model::Operations;
+use crate::models::{TxnInput, TxnOp, TxnOutput};
+
+/// Isolation levels from the weakest to the strongest, each one proscribes
+/// the anomalies the weaker ones do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IsolationLevel {
+    /// PL-1, proscribes G0.
+    ReadUncommitted,
+    /// PL-2, proscribes G1.
+    ReadCommitted,
+    /// PL-2+, proscribes G-single. Snapshot isolation is stronger but
+    /// allows the same cycles.
+    SnapshotIsolation,
+    /// PL-3, proscribes G2.
+    Serializable,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// Write cycle, a cycle of ww dependencies.
+    G0,
+    /// Aborted read, a committed transaction read a version written by an
+    /// aborted one.
+    G1a,
+    /// Intermediate read, a committed transaction read a version that its
+    /// writer overwrote itself.
+    G1b,
+    /// Circular information flow, a cycle of ww and wr dependencies.
+    G1c,
+    /// Read skew, a cycle with exactly one rw dependency.
+    GSingle,
+    /// Anti-dependency cycle, a cycle with one or more rw dependencies.
+    /// Only looked for without G-single, so it has at least two, like
+    /// write skew.
+    G2,
+}
+
+impl AnomalyKind {
+    /// The weakest isolation level that proscribes this anomaly.
+    pub fn violates(self) -> IsolationLevel {
+        match self {
+            AnomalyKind::G0 => IsolationLevel::ReadUncommitted,
+            AnomalyKind::G1a | AnomalyKind::G1b | AnomalyKind::G1c => IsolationLevel::ReadCommitted,
+            AnomalyKind::GSingle => IsolationLevel::SnapshotIsolation,
+            AnomalyKind::G2 => IsolationLevel::Serializable,
+        }
+    }
+}
+
+impl Display for AnomalyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AnomalyKind::G0 => "G0",
+            AnomalyKind::G1a => "G1a",
+            AnomalyKind::G1b => "G1b",
+            AnomalyKind::G1c => "G1c",
+            AnomalyKind::GSingle => "G-single",
+            AnomalyKind::G2 => "G2",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DependencyKind {
+    WW,
+    WR,
+    RW,
+}
+
+/// An edge of the dependency graph, transactions are identified by their
+/// index in the history.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dependency {
+    pub from: usize,
+    pub to: usize,
+    pub kind: DependencyKind,
+    pub key: Vec<u8>,
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            DependencyKind::WW => "ww",
+            DependencyKind::WR => "wr",
+            DependencyKind::RW => "rw",
+        };
+        write!(f, "-{}({})->", kind, String::from_utf8_lossy(&self.key))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    pub kind: AnomalyKind,
+    /// The cycle, starting and ending at `dependencies[0].from`. For G1a
+    /// and G1b, the single wr dependency of the bad read instead.
+    pub dependencies: Vec<Dependency>,
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: T{}", self.kind, self.dependencies[0].from)?;
+        for dep in &self.dependencies {
+            write!(f, " {} T{}", dep, dep.to)?;
+        }
+        Ok(())
+    }
+}
+
+/// The weakest isolation level violated by any of `anomalies`.
+pub fn violated_level(anomalies: &[Anomaly]) -> Option<IsolationLevel> {
+    anomalies.iter().map(|a| a.kind.violates()).min()
+}
+
+struct Write {
+    txn: usize,
+    // Whether the transaction wrote the key again afterwards.
+    overwritten: bool,
+}
+
+/// Finds the anomalies of `history`, at most one cycle of each kind and
+/// every G1a and G1b read. Pending transactions are taken as committed if
+/// any of their writes was read, and ignored otherwise.
+///
+/// Fails if a value is written twice to the same key or a value nobody
+/// wrote is read, since reads cannot be told apart then.
+pub fn check(history: &Operations<TxnInput, TxnOutput>) -> Result<Vec<Anomaly>, String> {
+    let mut writes: HashMap<(&[u8], &[u8]), Write> = HashMap::new();
+    for (txn, op) in history.iter().enumerate() {
+        let mut last: HashMap<&[u8], (&[u8], &[u8])> = HashMap::new();
+        for step in &op.input.ops {
+            if let TxnOp::Write(key, value) = step {
+                if writes.contains_key(&(&key[..], &value[..])) {
+                    return Err(format!(
+                        "{:?} written twice to {:?}",
+                        String::from_utf8_lossy(value),
+                        String::from_utf8_lossy(key)
+                    ));
+                }
+                if let Some(prev) = last.insert(key, (key, value)) {
+                    writes.get_mut(&prev).unwrap().overwritten = true;
+                }
+                writes.insert(
+                    (key, value),
+                    Write {
+                        txn,
+                        overwritten: false,
+                    },
+                );
+            }
+        }
+    }
+
+    // The reads of committed transactions that do not follow their own
+    // write of the key.
+    let mut reads = vec![];
+    for (txn, op) in history.iter().enumerate() {
+        let output = match &op.output {
+            Some(output) if output.committed => output,
+            _ => continue,
+        };
+        let mut written = HashSet::new();
+        let mut values = output.reads.iter();
+        for step in &op.input.ops {
+            match step {
+                TxnOp::Read(key) => {
+                    let value = values.next().ok_or("fewer values than reads")?;
+                    if !written.contains(key) {
+                        reads.push((txn, &key[..], &value[..]));
+                    }
+                }
+                TxnOp::Write(key, _) => {
+                    written.insert(key);
+                }
+            }
+        }
+    }
+
+    let mut anomalies = vec![];
+    let mut committed: Vec<_> = history
+        .iter()
+        .map(|op| op.output.as_ref().is_some_and(|o| o.committed))
+        .collect();
+    let mut deps = vec![];
+    // Readers of each version, the initial version of a key is empty.
+    let mut readers: HashMap<(&[u8], &[u8]), Vec<usize>> = HashMap::new();
+    for &(reader, key, value) in &reads {
+        readers.entry((key, value)).or_default().push(reader);
+        if value.is_empty() {
+            continue;
+        }
+        let write = writes.get(&(key, value)).ok_or_else(|| {
+            format!(
+                "T{} read {:?} from {:?}, which was never written",
+                reader,
+                String::from_utf8_lossy(value),
+                String::from_utf8_lossy(key)
+            )
+        })?;
+        if write.txn == reader {
+            continue;
+        }
+        let dep = Dependency {
+            from: write.txn,
+            to: reader,
+            kind: DependencyKind::WR,
+            key: key.to_vec(),
+        };
+        match &history[write.txn].output {
+            // Aborted transactions are not part of the graph.
+            Some(output) if !output.committed => {
+                anomalies.push(Anomaly {
+                    kind: AnomalyKind::G1a,
+                    dependencies: vec![dep],
+                });
+                continue;
+            }
+            None => committed[write.txn] = true,
+            _ => {}
+        }
+        if write.overwritten {
+            anomalies.push(Anomaly {
+                kind: AnomalyKind::G1b,
+                dependencies: vec![dep.clone()],
+            });
+        }
+        deps.push(dep);
+    }
+
+    // The versions each transaction installed right after the one it read.
+    let mut next: HashMap<(usize, &[u8]), &[u8]> = HashMap::new();
+    for &(txn, key, value) in &reads {
+        next.entry((txn, key)).or_insert(value);
+    }
+    for (txn, op) in history.iter().enumerate() {
+        if !committed[txn] {
+            continue;
+        }
+        for step in &op.input.ops {
+            let key = match step {
+                TxnOp::Write(key, _) => &key[..],
+                TxnOp::Read(_) => continue,
+            };
+            let mut previous = vec![&[][..]];
+            if let Some(&value) = next.get(&(txn, key)) {
+                if !value.is_empty() {
+                    previous.push(value);
+                    if let Some(write) = writes.get(&(key, value)) {
+                        if write.txn != txn && committed[write.txn] {
+                            deps.push(Dependency {
+                                from: write.txn,
+                                to: txn,
+                                kind: DependencyKind::WW,
+                                key: key.to_vec(),
+                            });
+                        }
+                    }
+                }
+            }
+            for value in previous {
+                for &reader in readers.get(&(key, value)).into_iter().flatten() {
+                    if reader != txn {
+                        deps.push(Dependency {
+                            from: reader,
+                            to: txn,
+                            kind: DependencyKind::RW,
+                            key: key.to_vec(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    deps.dedup();
+
+    let graph = Graph::new(history.len(), deps);
+    let ww = [DependencyKind::WW];
+    let ww_wr = [DependencyKind::WW, DependencyKind::WR];
+    let all = [DependencyKind::WW, DependencyKind::WR, DependencyKind::RW];
+    for (kind, first, rest) in [
+        (AnomalyKind::G0, DependencyKind::WW, &ww[..]),
+        (AnomalyKind::G1c, DependencyKind::WR, &ww_wr[..]),
+        (AnomalyKind::GSingle, DependencyKind::RW, &ww_wr[..]),
+        (AnomalyKind::G2, DependencyKind::RW, &all[..]),
+    ] {
+        // Without a G-single cycle, every cycle through an rw dependency
+        // has at least two of them.
+        if kind == AnomalyKind::G2 && anomalies.iter().any(|a| a.kind == AnomalyKind::GSingle) {
+            continue;
+        }
+        if let Some(dependencies) = graph.cycle(first, rest) {
+            anomalies.push(Anomaly { kind, dependencies });
+        }
+    }
+    Ok(anomalies)
+}
+
+struct Graph {
+    edges: Vec<Vec<Dependency>>,
+}
+
+impl Graph {
+    fn new(n: usize, deps: Vec<Dependency>) -> Graph {
+        let mut edges = vec![vec![]; n];
+        for dep in deps {
+            edges[dep.from].push(dep);
+        }
+        Graph { edges }
+    }
+
+    // A cycle made of a `first` dependency followed by `rest` ones.
+    fn cycle(&self, first: DependencyKind, rest: &[DependencyKind]) -> Option<Vec<Dependency>> {
+        for dep in self.edges.iter().flatten().filter(|d| d.kind == first) {
+            if let Some(mut path) = self.path(dep.to, dep.from, rest) {
+                path.insert(0, dep.clone());
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    // The shortest path, found breadth-first.
+    fn path(&self, from: usize, to: usize, kinds: &[DependencyKind]) -> Option<Vec<Dependency>> {
+        let mut parents: HashMap<usize, Option<&Dependency>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        parents.insert(from, None);
+        queue.push_back(from);
+        while let Some(txn) = queue.pop_front() {
+            if txn == to {
+                let mut path = vec![];
+                let mut node = txn;
+                while let Some(Some(dep)) = parents.get(&node) {
+                    path.push((*dep).clone());
+                    node = dep.from;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for dep in &self.edges[txn] {
+                if kinds.contains(&dep.kind) && !parents.contains_key(&dep.to) {
+                    parents.insert(dep.to, Some(dep));
+                    queue.push_back(dep.to);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, violated_level, AnomalyKind, DependencyKind, IsolationLevel};
+    use crate::model::{Operation, Operations};
+    use crate::models::{TxnInput, TxnOp, TxnOutput};
+
+    enum Step {
+        R(&'static str, &'static str),
+        W(&'static str, &'static str),
+    }
+    use Step::{R, W};
+
+    fn txn(steps: Vec<Step>, committed: bool) -> Operation<TxnInput, TxnOutput> {
+        let mut ops = vec![];
+        let mut reads = vec![];
+        for step in steps {
+            match step {
+                R(k, v) => {
+                    ops.push(TxnOp::Read(k.into()));
+                    reads.push(v.into());
+                }
+                W(k, v) => ops.push(TxnOp::Write(k.into(), v.into())),
+            }
+        }
+        Operation {
+            client_id: 0,
+            input: TxnInput { ops },
+            call: 0,
+            output: Some(TxnOutput { reads, committed }),
+            finish: 0,
+        }
+    }
+
+    fn kinds(history: &Operations<TxnInput, TxnOutput>) -> Vec<AnomalyKind> {
+        check(history)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_serializable() {
+        let history = vec![
+            txn(vec![R("x", ""), W("x", "1")], true),
+            txn(vec![R("x", "1"), W("x", "2"), W("y", "2")], true),
+            txn(vec![R("x", "2"), R("y", "2")], true),
+            // Aborted, and not read.
+            txn(vec![R("x", "2"), W("x", "3")], false),
+            // Reads its own write.
+            txn(vec![W("y", "4"), R("y", "4")], true),
+        ];
+        assert_eq!(kinds(&history), vec![]);
+    }
+
+    #[test]
+    fn test_g1() {
+        let history = vec![
+            txn(vec![W("x", "1"), W("x", "2")], true),
+            txn(vec![W("y", "1")], false),
+            txn(vec![R("x", "1"), R("y", "1")], true),
+        ];
+        assert_eq!(kinds(&history), vec![AnomalyKind::G1b, AnomalyKind::G1a]);
+
+        // Each transaction sees the other's write.
+        let history = vec![
+            txn(vec![R("y", "1"), W("x", "1")], true),
+            txn(vec![R("x", "1"), W("y", "1")], true),
+        ];
+        let anomalies = check(&history).unwrap();
+        assert_eq!(anomalies[0].kind, AnomalyKind::G1c);
+        assert_eq!(anomalies[0].to_string(), "G1c: T0 -wr(x)-> T1 -wr(y)-> T0");
+        assert_eq!(
+            violated_level(&anomalies),
+            Some(IsolationLevel::ReadCommitted)
+        );
+    }
+
+    #[test]
+    fn test_g0() {
+        // Both read-modify-write x and y, in different orders.
+        let history = vec![
+            txn(
+                vec![R("x", ""), W("x", "1"), R("y", "2"), W("y", "1")],
+                true,
+            ),
+            txn(
+                vec![R("x", "1"), W("x", "2"), R("y", ""), W("y", "2")],
+                true,
+            ),
+        ];
+        let anomalies = check(&history).unwrap();
+        assert_eq!(anomalies[0].kind, AnomalyKind::G0);
+        assert!(anomalies[0]
+            .dependencies
+            .iter()
+            .all(|d| d.kind == DependencyKind::WW));
+        assert_eq!(
+            violated_level(&anomalies),
+            Some(IsolationLevel::ReadUncommitted)
+        );
+    }
+
+    #[test]
+    fn test_g_single() {
+        // T2 sees T1's write to y but not to x.
+        let history = vec![
+            txn(vec![W("x", "0"), W("y", "0")], true),
+            txn(
+                vec![R("x", "0"), W("x", "1"), R("y", "0"), W("y", "1")],
+                true,
+            ),
+            txn(vec![R("x", "0"), R("y", "1")], true),
+        ];
+        let anomalies = check(&history).unwrap();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(
+            anomalies[0].to_string(),
+            "G-single: T2 -rw(x)-> T1 -wr(y)-> T2"
+        );
+        assert_eq!(
+            violated_level(&anomalies),
+            Some(IsolationLevel::SnapshotIsolation)
+        );
+    }
+
+    #[test]
+    fn test_g2() {
+        let write_skew = vec![
+            txn(vec![R("x", ""), R("y", ""), W("x", "1")], true),
+            txn(vec![R("x", ""), R("y", ""), W("y", "1")], true),
+        ];
+        let anomalies = check(&write_skew).unwrap();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].to_string(), "G2: T0 -rw(y)-> T1 -rw(x)-> T0");
+        assert_eq!(
+            violated_level(&anomalies),
+            Some(IsolationLevel::Serializable)
+        );
+    }
+
+    #[test]
+    fn test_pending() {
+        let mut history = vec![txn(vec![R("x", ""), R("y", "")], true)];
+        history.push(Operation::pending(
+            1,
+            TxnInput {
+                ops: vec![TxnOp::Write("x".into(), "1".into())],
+            },
+            0,
+        ));
+        // T1 committed since T2 read its write, T2 saw y before T0 read it.
+        history.push(txn(vec![R("x", "1"), W("y", "2")], true));
+        assert_eq!(kinds(&history), vec![]);
+        history.push(txn(vec![R("y", "2"), R("x", "")], true));
+        assert_eq!(kinds(&history), vec![AnomalyKind::GSingle]);
+    }
+
+    #[test]
+    fn test_unchecked() {
+        let twice = vec![txn(vec![W("x", "1")], true), txn(vec![W("x", "1")], true)];
+        assert!(check(&twice).is_err());
+        let garbage = vec![txn(vec![R("x", "1")], true)];
+        assert!(check(&garbage).is_err());
+    }
+}
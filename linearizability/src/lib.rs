@@ -1,19 +1,27 @@
 mod bitset;
+pub mod edn;
+pub mod history;
+pub mod isolation;
 pub mod model;
 pub mod models;
+mod result;
+pub mod sequential;
+pub mod staleness;
+pub mod visualize;
 
-use std::cell::{Ref, RefCell};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
-use std::rc::Rc;
+use std::hash::Hash;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
 use crate::bitset::Bitset;
 use crate::model::{Event, EventKind, Events, Model, Operations, Value};
+pub use crate::result::{CheckOutcome, CheckResult, OperationInfo, PartitionResult};
 
 enum EntryKind {
     CallEntry,
@@ -36,92 +44,159 @@ fn make_entries<I: Debug, O: Debug>(history: Operations<I, O>) -> Vec<Entry<Valu
             id,
             time: elem.call,
         });
+        // A pending operation returns after every other operation.
+        let (value, time) = match elem.output {
+            Some(output) => (Value::Output(output), elem.finish),
+            None => (Value::None, i64::MAX),
+        };
         entries.push(Entry {
             kind: EntryKind::ReturnEntry,
-            value: Value::Output(elem.output),
+            value,
             id,
-            time: elem.finish,
+            time,
         })
     }
     entries.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
     entries
 }
 
-struct LinkedNodes<T: Debug> {
-    head: Option<LinkNode<T>>,
+fn describe_operations<I: Debug, O: Debug>(history: &Operations<I, O>) -> Vec<OperationInfo> {
+    history
+        .iter()
+        .enumerate()
+        .map(|(id, op)| OperationInfo {
+            id,
+            client_id: op.client_id,
+            input: format!("{:?}", op.input),
+            output: match &op.output {
+                Some(output) => format!("{:?}", output),
+                None => "?".to_owned(),
+            },
+            call: op.call,
+            finish: if op.is_pending() { i64::MAX } else { op.finish },
+            pending: op.is_pending(),
+        })
+        .collect()
 }
 
-impl<T: Debug> LinkedNodes<T> {
-    pub fn new() -> Self {
-        LinkedNodes { head: None }
+// Takes renumbered events, uses the positions of the events as time.
+fn describe_events<I: Debug, O: Debug>(history: &Events<I, O>) -> Vec<OperationInfo> {
+    let mut ops: Vec<Option<OperationInfo>> = vec![];
+    for (i, event) in history.iter().enumerate() {
+        if ops.len() <= event.id {
+            ops.resize(event.id + 1, None);
+        }
+        let op = ops[event.id].get_or_insert_with(|| OperationInfo {
+            id: event.id,
+            client_id: event.client_id,
+            input: String::new(),
+            output: "?".to_owned(),
+            call: -1,
+            finish: i64::MAX,
+            pending: true,
+        });
+        match (&event.kind, &event.value) {
+            (EventKind::CallEvent, value) => {
+                op.input = format!("{:?}", value.input());
+                op.call = i as i64;
+            }
+            (EventKind::ReturnEvent, Value::None) => {}
+            (EventKind::ReturnEvent, value) => {
+                op.output = format!("{:?}", value.output());
+                op.finish = i as i64;
+                op.pending = false;
+            }
+        }
     }
+    ops.into_iter().map(Option::unwrap).collect()
+}
 
-    pub fn head(&self) -> Option<LinkNode<T>> {
-        self.head.clone()
-    }
+// The entries of a history as a doubly linked list over a vector, index 0
+// is a sentinel head. The search takes an operation out by unlinking its
+// call and return entries and puts it back in constant time.
+struct List<T> {
+    nodes: Vec<Node<T>>,
+}
+
+struct Node<T> {
+    value: T,
+    // The return entry of a call entry.
+    matched: Option<usize>,
+    id: usize,
+    prev: usize,
+    next: Option<usize>,
+}
 
-    pub fn from_entries(entries: Vec<Entry<T>>) -> Self {
-        let mut matches: HashMap<usize, LinkNode<T>> = HashMap::new();
-        let mut nodes = Self::new();
-
-        for entry in entries.into_iter().rev() {
-            nodes.push_front(match entry.kind {
-                EntryKind::CallEntry => Rc::new(RefCell::new(Node {
-                    value: entry.value,
-                    matched: matches.get(&entry.id).cloned(),
-                    id: entry.id,
-                    next: None,
-                    prev: None,
-                })),
+impl<I: Debug, O: Debug> List<Value<I, O>> {
+    fn from_entries(entries: Vec<Entry<Value<I, O>>>) -> Self {
+        let mut nodes = vec![Node {
+            value: Value::None,
+            matched: None,
+            id: usize::MAX,
+            prev: 0,
+            next: None,
+        }];
+        let mut calls = vec![];
+        let mut returns = HashMap::new();
+        for entry in entries {
+            let index = nodes.len();
+            match entry.kind {
+                EntryKind::CallEntry => calls.push(index),
                 EntryKind::ReturnEntry => {
-                    let node = Rc::new(RefCell::new(Node {
-                        value: entry.value,
-                        matched: None,
-                        id: entry.id,
-                        next: None,
-                        prev: None,
-                    }));
-                    matches.insert(entry.id, node.clone());
-                    node
+                    returns.insert(entry.id, index);
                 }
-            })
+            }
+            nodes[index - 1].next = Some(index);
+            nodes.push(Node {
+                value: entry.value,
+                matched: None,
+                id: entry.id,
+                prev: index - 1,
+                next: None,
+            });
+        }
+        for call in calls {
+            nodes[call].matched = returns.get(&nodes[call].id).copied();
         }
+        List { nodes }
+    }
+}
 
-        nodes
+impl<T> List<T> {
+    fn operations(&self) -> usize {
+        (self.nodes.len() - 1) / 2
     }
 
-    pub fn len(&self) -> usize {
-        let mut len = 0;
-        let mut entry = self.head.clone();
-        while let Some(e) = entry {
-            entry = e.borrow().next.clone();
-            len += 1;
+    fn head(&self) -> Option<usize> {
+        self.nodes[0].next
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let Node { prev, next, .. } = self.nodes[index];
+        self.nodes[prev].next = next;
+        if let Some(next) = next {
+            self.nodes[next].prev = prev;
         }
-        len
     }
 
-    pub fn push_front(&mut self, new_head: LinkNode<T>) {
-        match self.head.take() {
-            Some(old_head) => {
-                old_head.borrow_mut().prev = Some(new_head.clone());
-                new_head.borrow_mut().next = Some(old_head);
-                self.head = Some(new_head);
-            }
-            None => {
-                self.head = Some(new_head);
-            }
+    fn relink(&mut self, index: usize) {
+        let Node { prev, next, .. } = self.nodes[index];
+        self.nodes[prev].next = Some(index);
+        if let Some(next) = next {
+            self.nodes[next].prev = index;
         }
     }
-}
 
-type LinkNode<T> = Rc<RefCell<Node<T>>>;
+    // Takes the operation of a call entry out.
+    fn lift(&mut self, call: usize) {
+        self.unlink(call);
+        self.unlink(self.nodes[call].matched.unwrap());
+    }
 
-struct Node<T: Debug> {
-    pub value: T,
-    pub matched: Option<LinkNode<T>>,
-    pub id: usize,
-    pub next: Option<LinkNode<T>>,
-    pub prev: Option<LinkNode<T>>,
+    fn unlift(&mut self, call: usize) {
+        self.relink(self.nodes[call].matched.unwrap());
+        self.relink(call);
+    }
 }
 
 fn renumber<T>(events: Vec<Event<T>>) -> Vec<Event<T>> {
@@ -130,6 +205,7 @@ fn renumber<T>(events: Vec<Event<T>>) -> Vec<Event<T>> {
     let mut id: usize = 0;
     for event in events {
         e.push(Event {
+            client_id: event.client_id,
             kind: event.kind,
             value: event.value,
             id: *m.entry(event.id).or_insert_with(|| {
@@ -141,253 +217,434 @@ fn renumber<T>(events: Vec<Event<T>>) -> Vec<Event<T>> {
     e
 }
 
-fn convert_entries<T>(events: Vec<Event<T>>) -> Vec<Entry<T>> {
+fn convert_entries<I: Debug, O: Debug>(events: Events<I, O>) -> Vec<Entry<Value<I, O>>> {
     let mut entries = Vec::new();
+    let mut returned = vec![];
+    let mut calls = vec![];
     for event in events {
-        entries.push(match event.kind {
-            EventKind::CallEvent => Entry {
-                kind: EntryKind::CallEntry,
-                value: event.value,
-                id: event.id,
-                time: -1,
-            },
-            EventKind::ReturnEvent => Entry {
+        let kind = match event.kind {
+            EventKind::CallEvent => {
+                calls.push(event.id);
+                EntryKind::CallEntry
+            }
+            // Pending, returns after every other operation below.
+            EventKind::ReturnEvent if matches!(event.value, Value::None) => continue,
+            EventKind::ReturnEvent => {
+                returned.push(event.id);
+                EntryKind::ReturnEntry
+            }
+        };
+        entries.push(Entry {
+            kind,
+            value: event.value,
+            id: event.id,
+            time: -1,
+        });
+    }
+    returned.sort_unstable();
+    for id in calls {
+        if returned.binary_search(&id).is_err() {
+            entries.push(Entry {
                 kind: EntryKind::ReturnEntry,
-                value: event.value,
-                id: event.id,
+                value: Value::None,
+                id,
                 time: -1,
-            },
-        })
+            });
+        }
     }
     entries
 }
 
-struct CacheEntry<T> {
-    linearized: Bitset,
-    state: T,
-}
-
-fn cache_contains<M: Model>(
-    model: &M,
-    cache: &HashMap<u64, Vec<CacheEntry<M::State>>>,
-    entry: &CacheEntry<M::State>,
-) -> bool {
-    if cache.contains_key(&entry.linearized.hash()) {
-        for elem in &cache[&entry.linearized.hash()] {
-            if entry.linearized.equals(&elem.linearized) && model.equal(&entry.state, &elem.state) {
-                return true;
-            }
+// Numbers the distinct states reached, so that the search and its cache
+// deal with ids instead of cloning and comparing states.
+struct States<S> {
+    ids: HashMap<S, usize>,
+    states: Vec<S>,
+}
+
+impl<S: Clone + Eq + Hash> States<S> {
+    fn new() -> Self {
+        States {
+            ids: HashMap::new(),
+            states: vec![],
         }
     }
-    false
-}
 
-struct CallsEntry<V: Debug, T> {
-    entry: Option<LinkNode<V>>,
-    state: T,
+    fn intern(&mut self, state: S) -> usize {
+        if let Some(&id) = self.ids.get(&state) {
+            return id;
+        }
+        let id = self.states.len();
+        self.states.push(state.clone());
+        self.ids.insert(state, id);
+        id
+    }
+
+    fn get(&self, id: usize) -> &S {
+        &self.states[id]
+    }
 }
 
-fn lift<T: Debug>(entry: &LinkNode<T>) {
-    let prev = Ref::map(entry.borrow(), |e| e.prev.as_ref().unwrap());
-    prev.borrow_mut().next = entry.borrow().next.clone();
-    let next = Ref::map(entry.borrow(), |e| e.next.as_ref().unwrap());
-    next.borrow_mut().prev = entry.borrow().prev.clone();
+/// The longest linearizable prefix found so far.
+struct Prefix<S> {
+    ids: Vec<usize>,
+    // state after each operation of the first prefix found
+    steps: Vec<S>,
+    states: Vec<S>,
+}
 
-    let matched = Ref::map(entry.borrow(), |e| e.matched.as_ref().unwrap());
-    let matched_prev = Ref::map(matched.borrow(), |e| e.prev.as_ref().unwrap());
-    matched_prev.borrow_mut().next = matched.borrow().next.clone();
-    if matched.borrow().next.is_some() {
-        let matched_next = Ref::map(matched.borrow(), |e| e.next.as_ref().unwrap());
-        matched_next.borrow_mut().prev = matched.borrow().prev.clone();
-    }
+// Like `Prefix`, with interned states.
+#[derive(Default)]
+struct Longest {
+    ids: Vec<usize>,
+    steps: Vec<usize>,
+    states: Vec<usize>,
 }
 
-fn unlift<T: Debug>(entry: &LinkNode<T>) {
-    {
-        let matched = Ref::map(entry.borrow(), |e| e.matched.as_ref().unwrap());
-        let matched_prev = Ref::map(matched.borrow(), |e| e.prev.as_ref().unwrap());
-        matched_prev.borrow_mut().next = Some(matched.clone());
-        if matched.borrow().next.is_some() {
-            let matched_next = Ref::map(matched.borrow(), |e| e.next.as_ref().unwrap());
-            matched_next.borrow_mut().prev = Some(matched.clone());
+impl Longest {
+    fn prefix<S: Clone + Eq + Hash>(self, states: &States<S>) -> Prefix<S> {
+        let get = |ids: Vec<usize>| ids.into_iter().map(|id| states.get(id).clone()).collect();
+        Prefix {
+            ids: self.ids,
+            steps: get(self.steps),
+            states: get(self.states),
         }
     }
-
-    let prev = Ref::map(entry.borrow(), |e| e.prev.as_ref().unwrap());
-    prev.borrow_mut().next = Some(entry.clone());
-    let next = Ref::map(entry.borrow(), |e| e.next.as_ref().unwrap());
-    next.borrow_mut().prev = Some(entry.clone());
 }
 
-fn check_single<M: Model>(
+// The search for a linearization of one partition, it can be stopped
+// after any number of steps and resumed on another thread.
+struct Search<M: Model> {
     model: M,
-    mut subhistory: LinkedNodes<Value<M::Input, M::Output>>,
-    kill: Arc<AtomicBool>,
-) -> bool {
-    let n = subhistory.len() / 2;
-    let mut linearized = Bitset::new(n);
-    let mut cache = HashMap::new();
-    let mut calls = vec![];
+    list: List<Value<M::Input, M::Output>>,
+    linearized: Bitset,
+    cache: HashSet<(Bitset, usize)>,
+    // The call entries linearized so far and the state before each.
+    calls: Vec<(usize, usize)>,
+    states: States<M::State>,
+    state: usize,
+    entry: Option<usize>,
+    longest: Longest,
+}
 
-    let mut state = model.init();
-    subhistory.push_front(Rc::new(RefCell::new(Node {
-        value: Value::None,
-        matched: None,
-        id: usize::max_value(),
-        prev: None,
-        next: None,
-    })));
-    let head_entry = subhistory.head().unwrap();
-    let mut entry = head_entry.borrow().next.clone();
-    while head_entry.borrow().next.is_some() {
-        if kill.load(Ordering::SeqCst) {
-            return false;
+impl<M: Model> Search<M> {
+    fn new(model: M, list: List<Value<M::Input, M::Output>>) -> Self {
+        let mut states = States::new();
+        let state = states.intern(model.init());
+        Search {
+            linearized: Bitset::new(list.operations()),
+            entry: list.head(),
+            cache: HashSet::new(),
+            calls: vec![],
+            longest: Longest {
+                states: vec![state],
+                ..Longest::default()
+            },
+            model,
+            list,
+            states,
+            state,
         }
-        let matched = entry.as_ref().unwrap().borrow().matched.clone();
-        entry = if let Some(matching) = matched {
-            // the return entry
-            let res = model.step(
-                &state,
-                entry.as_ref().unwrap().borrow().value.input(),
-                matching.borrow().value.output(),
-            );
-            match res {
-                (true, new_state) => {
-                    let mut new_linearized = linearized.clone();
-                    new_linearized.set(entry.as_ref().unwrap().borrow().id);
-                    let new_cache_entry = CacheEntry {
-                        linearized: new_linearized.clone(),
-                        state: new_state.clone(),
+    }
+
+    // Takes up to `steps` steps, returns `None` if the search is not over.
+    fn run(&mut self, steps: usize) -> Option<CheckOutcome> {
+        for _ in 0..steps {
+            let entry = match self.entry {
+                Some(entry) => entry,
+                None => return Some(CheckOutcome::Ok),
+            };
+            let node = &self.list.nodes[entry];
+            let (id, next) = (node.id, node.next);
+            let ret = match node.matched {
+                Some(ret) => ret,
+                // Only pending operations are left, they never took effect.
+                None if matches!(node.value, Value::None) => return Some(CheckOutcome::Ok),
+                None => {
+                    let (call, state) = match self.calls.pop() {
+                        Some(top) => top,
+                        None => return Some(CheckOutcome::Illegal),
                     };
-                    if !cache_contains(&model, &cache, &new_cache_entry) {
-                        let hash = new_linearized.hash();
-                        cache.entry(hash).or_default().push(new_cache_entry);
-                        calls.push(CallsEntry {
-                            entry: entry.clone(),
-                            state,
-                        });
-                        state = new_state;
-                        linearized.set(entry.as_ref().unwrap().borrow().id);
-                        lift(entry.as_ref().unwrap());
-                        head_entry.borrow().next.clone()
-                    } else {
-                        entry.as_ref().unwrap().borrow().next.clone()
-                    }
+                    self.state = state;
+                    self.linearized.clear(self.list.nodes[call].id);
+                    self.list.unlift(call);
+                    self.entry = self.list.nodes[call].next;
+                    continue;
                 }
-                (false, _) => entry.as_ref().unwrap().borrow().next.clone(),
+            };
+            let input = node.value.input();
+            let state = self.states.get(self.state);
+            let (ok, new_state) = match &self.list.nodes[ret].value {
+                Value::None => self.model.step_pending(state, input),
+                output => self.model.step(state, input, output.output()),
+            };
+            self.entry = next;
+            if !ok {
+                continue;
             }
-        } else {
-            if calls.is_empty() {
-                return false;
+            let new_state = self.states.intern(new_state);
+            self.linearized.set(id);
+            if !self.cache.insert((self.linearized.clone(), new_state)) {
+                self.linearized.clear(id);
+                continue;
             }
-            let calls_top = calls.pop().unwrap();
-            entry = calls_top.entry;
-            state = calls_top.state;
-            linearized.clear(entry.as_ref().unwrap().borrow().id);
-            unlift(entry.as_ref().unwrap());
-            entry.as_ref().unwrap().borrow().next.clone()
+            self.calls.push((entry, self.state));
+            self.state = new_state;
+            self.list.lift(entry);
+            self.entry = self.list.head();
+            self.update_longest();
         }
+        None
+    }
+
+    fn update_longest(&mut self) {
+        let (longest, nodes) = (&mut self.longest, &self.list.nodes);
+        if self.calls.len() > longest.ids.len() {
+            longest.ids = self.calls.iter().map(|&(call, _)| nodes[call].id).collect();
+            longest.steps = self.calls[1..]
+                .iter()
+                .map(|&(_, state)| state)
+                .chain(Some(self.state))
+                .collect();
+            longest.states = vec![self.state];
+        } else if self.calls.len() == longest.ids.len() && !longest.states.contains(&self.state) {
+            longest.states.push(self.state);
+        }
+    }
+
+    fn prefix(self) -> Prefix<M::State> {
+        self.longest.prefix(&self.states)
+    }
+}
+
+fn partition_result<S>(
+    outcome: CheckOutcome,
+    operations: Vec<OperationInfo>,
+    prefix: Prefix<S>,
+) -> PartitionResult<S> {
+    // Pending operations left out of a linearization never took effect.
+    let mut unlinearized: Vec<usize> = operations
+        .iter()
+        .filter(|op| !(outcome == CheckOutcome::Ok && op.pending))
+        .map(|op| op.id)
+        .filter(|id| !prefix.ids.contains(id))
+        .collect();
+    unlinearized.sort_by_key(|id| operations[*id].call);
+    PartitionResult {
+        outcome,
+        operations,
+        linearized: prefix.ids,
+        steps: prefix.steps,
+        unlinearized,
+        states: prefix.states,
     }
-    true
 }
 
 pub fn check_operations<M: Model>(model: M, history: Operations<M::Input, M::Output>) -> bool {
-    check_operations_timeout(model, history, Duration::new(0, 0))
+    check_operations_timeout(model, history, Duration::new(0, 0)) == CheckOutcome::Ok
 }
 
 // timeout = 0 means no timeout
-// if this operation times out, then a false positive is possible
 pub fn check_operations_timeout<M: Model>(
     model: M,
     history: Operations<M::Input, M::Output>,
     timeout: Duration,
-) -> bool {
-    let partitions = model.partition(history);
+) -> CheckOutcome {
+    check_operations_partitions(model, history, timeout, true).outcome
+}
 
-    let (tx, rx) = channel();
-    let mut handles = vec![];
-    let kill = Arc::new(AtomicBool::new(false));
-    let count = partitions.len();
-    for subhistory in partitions {
-        let tx = tx.clone();
-        let kill = Arc::clone(&kill);
-        let m = model.clone();
-        let handle = thread::spawn(move || {
-            let l = LinkedNodes::from_entries(make_entries(subhistory));
-            let _ = tx.send(check_single(m, l, kill));
-        });
-        handles.push(handle);
-    }
+/// Like `check_operations_timeout`, but checks every partition even if one
+/// of them fails, and reports where each failing partition got stuck.
+pub fn check_operations_verbose<M: Model>(
+    model: M,
+    history: Operations<M::Input, M::Output>,
+    timeout: Duration,
+) -> CheckResult<M::State> {
+    check_operations_partitions(model, history, timeout, false)
+}
 
-    let res = wait_res(rx, kill, count, timeout);
-    for handle in handles {
-        handle.join().unwrap();
-    }
-    res
+fn check_operations_partitions<M: Model>(
+    model: M,
+    history: Operations<M::Input, M::Output>,
+    timeout: Duration,
+    stop_on_failure: bool,
+) -> CheckResult<M::State> {
+    let partitions = model.partition(history);
+    check_partitions(model, partitions, timeout, stop_on_failure, |subhistory| {
+        let ops = describe_operations(&subhistory);
+        (ops, List::from_entries(make_entries(subhistory)))
+    })
 }
 
 pub fn check_events<M: Model>(model: M, history: Events<M::Input, M::Output>) -> bool {
-    check_events_timeout(model, history, Duration::new(0, 0))
+    check_events_timeout(model, history, Duration::new(0, 0)) == CheckOutcome::Ok
 }
 
 // timeout = 0 means no timeout
-// if this operation times out, then a false positive is possible
 pub fn check_events_timeout<M: Model>(
     model: M,
     history: Events<M::Input, M::Output>,
     timeout: Duration,
-) -> bool {
+) -> CheckOutcome {
+    check_events_partitions(model, history, timeout, true).outcome
+}
+
+/// Like `check_events_timeout`, but checks every partition even if one of
+/// them fails, and reports where each failing partition got stuck.
+pub fn check_events_verbose<M: Model>(
+    model: M,
+    history: Events<M::Input, M::Output>,
+    timeout: Duration,
+) -> CheckResult<M::State> {
+    check_events_partitions(model, history, timeout, false)
+}
+
+fn check_events_partitions<M: Model>(
+    model: M,
+    history: Events<M::Input, M::Output>,
+    timeout: Duration,
+    stop_on_failure: bool,
+) -> CheckResult<M::State> {
     let partitions = model.partition_event(history);
+    check_partitions(model, partitions, timeout, stop_on_failure, |subhistory| {
+        let subhistory = renumber(subhistory);
+        let ops = describe_events(&subhistory);
+        (ops, List::from_entries(convert_entries(subhistory)))
+    })
+}
+
+type Subhistory<M> = List<Value<<M as Model>::Input, <M as Model>::Output>>;
+
+// A work-stealing pool with a thread per CPU. It is not rayon's global
+// pool so that checks started from that pool's threads cannot wait on
+// partitions queued behind them.
+fn pool() -> &'static ThreadPool {
+    static POOL: OnceLock<ThreadPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        ThreadPoolBuilder::new()
+            .thread_name(|i| format!("linearizability-{}", i))
+            .build()
+            .unwrap()
+    })
+}
 
+// Steps a search takes before the other partitions get a turn.
+const SLICE: usize = 10_000;
+
+struct Task<M: Model> {
+    index: usize,
+    operations: Vec<OperationInfo>,
+    search: Search<M>,
+    kill: Arc<AtomicBool>,
+    tx: Sender<(usize, PartitionResult<M::State>)>,
+}
+
+type Queue<M> = Arc<Mutex<VecDeque<Task<M>>>>;
+
+// Runs a slice of the search at the front of the queue and queues it again
+// if it is not over. There is a job on the pool for each queued search, so
+// the searches take turns however many threads there are, and a partition
+// that is hard to check does not hold up the others.
+fn run_slice<M: Model>(queue: Queue<M>) {
+    let mut task = queue.lock().unwrap().pop_front().unwrap();
+    let outcome = if task.kill.load(Ordering::SeqCst) {
+        Some(CheckOutcome::Unknown)
+    } else {
+        task.search.run(SLICE)
+    };
+    match outcome {
+        Some(outcome) => {
+            let result = partition_result(outcome, task.operations, task.search.prefix());
+            let _ = task.tx.send((task.index, result));
+        }
+        None => {
+            queue.lock().unwrap().push_back(task);
+            pool().spawn(move || run_slice(queue));
+        }
+    }
+}
+
+fn check_partitions<M, H, F>(
+    model: M,
+    partitions: Vec<H>,
+    timeout: Duration,
+    stop_on_failure: bool,
+    prepare: F,
+) -> CheckResult<M::State>
+where
+    M: Model,
+    F: Fn(H) -> (Vec<OperationInfo>, Subhistory<M>),
+{
     let (tx, rx) = channel();
-    let mut handles = vec![];
     let kill = Arc::new(AtomicBool::new(false));
     let count = partitions.len();
-    for subhistory in partitions {
-        let tx = tx.clone();
-        let kill = Arc::clone(&kill);
-        let m = model.clone();
-        let handle = thread::spawn(move || {
-            let l = LinkedNodes::from_entries(convert_entries(renumber(subhistory)));
-            let _ = tx.send(check_single(m, l, kill));
+    let queue: Queue<M> = Arc::default();
+    for (index, subhistory) in partitions.into_iter().enumerate() {
+        let (operations, list) = prepare(subhistory);
+        queue.lock().unwrap().push_back(Task {
+            index,
+            operations,
+            search: Search::new(model.clone(), list),
+            kill: Arc::clone(&kill),
+            tx: tx.clone(),
         });
-        handles.push(handle);
     }
-
-    let res = wait_res(rx, kill, count, timeout);
-    for handle in handles {
-        handle.join().unwrap();
+    for _ in 0..count {
+        let queue = Arc::clone(&queue);
+        pool().spawn(move || run_slice(queue));
+    }
+    drop(tx);
+
+    let mut results = wait_res(&rx, &kill, count, timeout, stop_on_failure);
+    // Partitions stopped early report what they had found so far, the
+    // channel closes once every check has returned.
+    results.extend(rx.iter());
+    results.sort_by_key(|(i, _)| *i);
+    let partitions: Vec<_> = results.into_iter().map(|(_, p)| p).collect();
+    let outcome = partitions
+        .iter()
+        .map(|p| p.outcome)
+        .max()
+        .unwrap_or(CheckOutcome::Ok);
+    CheckResult {
+        outcome,
+        partitions,
     }
-    res
 }
 
-fn wait_res(
-    rx: Receiver<bool>,
-    kill: Arc<AtomicBool>,
+// Collects results until all partitions are checked, a partition fails if
+// `stop_on_failure` is set, or it times out, then stops the other checks.
+fn wait_res<S>(
+    rx: &Receiver<(usize, PartitionResult<S>)>,
+    kill: &AtomicBool,
     mut count: usize,
     timeout: Duration,
-) -> bool {
-    let mut ok = true;
-    loop {
-        match if timeout.as_secs() == 0 && timeout.subsec_nanos() == 0 {
-            rx.recv().map_err(From::from)
-        } else {
-            rx.recv_timeout(timeout)
-        } {
+    stop_on_failure: bool,
+) -> Vec<(usize, PartitionResult<S>)> {
+    let deadline = if timeout.as_secs() == 0 && timeout.subsec_nanos() == 0 {
+        None
+    } else {
+        Some(Instant::now() + timeout)
+    };
+    let mut results = vec![];
+    while count > 0 {
+        let res = match deadline {
+            None => rx.recv().map_err(From::from),
+            Some(d) => rx.recv_timeout(d.saturating_duration_since(Instant::now())),
+        };
+        match res {
             Ok(res) => {
-                ok = ok && res;
-                if !ok {
-                    kill.store(true, Ordering::SeqCst);
+                let failed = res.1.outcome == CheckOutcome::Illegal;
+                results.push(res);
+                if failed && stop_on_failure {
                     break;
                 }
                 count -= 1;
-                if count == 0 {
-                    break;
-                }
             }
             Err(RecvTimeoutError::Timeout) => break,
             Err(e) => panic!("recv err: {}", e),
         }
     }
-    ok
+    kill.store(true, Ordering::SeqCst);
+    results
 }
@@ -1,6 +1,6 @@
-use std::cmp::PartialEq;
 use std::fmt::Debug;
 use std::fmt::Display;
+use std::hash::Hash;
 use std::marker::Send;
 
 #[derive(Debug)]
@@ -30,18 +30,42 @@ impl<I: Debug, O: Debug> Value<I, O> {
 
 #[derive(Debug)]
 pub struct Operation<I: Debug, O: Debug> {
+    pub client_id: usize,
     pub input: I,
     pub call: i64, // invocation time
-    pub output: O,
+    // `None` if the outcome is unknown, e.g. the client gave up waiting for
+    // the reply. Such an operation may take effect at any point after its
+    // call, or never, and its `finish` is ignored.
+    pub output: Option<O>,
     pub finish: i64, // response time
 }
 
+impl<I: Debug, O: Debug> Operation<I, O> {
+    /// An operation that never returned.
+    pub fn pending(client_id: usize, input: I, call: i64) -> Self {
+        Operation {
+            client_id,
+            input,
+            call,
+            output: None,
+            finish: i64::MAX,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.output.is_none()
+    }
+}
+
 pub enum EventKind {
     CallEvent,
     ReturnEvent,
 }
 
+// A call without a return event, or whose return event holds
+// `Value::None`, is pending like an `Operation` without output.
 pub struct Event<T> {
+    pub client_id: usize,
     pub kind: EventKind,
     pub value: T,
     pub id: usize,
@@ -50,8 +74,14 @@ pub struct Event<T> {
 pub type Operations<I, O> = Vec<Operation<I, O>>;
 pub type Events<I, O> = Vec<Event<Value<I, O>>>;
 
+/// A sequential specification that histories are checked against.
+///
+/// States must implement `Clone + Display + Eq + Hash + Send + 'static`.
+/// The checker interns them and compares them with `Eq`, so `Hash` has to
+/// agree with it. Models no longer implement an `equal` method; one that
+/// did derives `Eq` and `Hash` on its state instead.
 pub trait Model: Clone + Send + 'static {
-    type State: Clone + Display + PartialEq;
+    type State: Clone + Display + Eq + Hash + Send + 'static;
     type Input: Send + Debug + 'static;
     type Output: Send + Debug + 'static;
 
@@ -86,9 +116,11 @@ pub trait Model: Clone + Send + 'static {
         output: &Self::Output,
     ) -> (bool, Self::State);
 
-    // Equality on states. If you are using a simple data type for states,
-    // you can use the `ShallowEqual` function implemented below.
-    fn equal(&self, state1: &Self::State, state2: &Self::State) -> bool {
-        state1 == state2
+    // Step function for an operation whose output is unknown. Returns
+    // whether the operation could take effect in this state and the new
+    // state, the checker also tries leaving the operation out. By default
+    // pending operations never take effect.
+    fn step_pending(&self, state: &Self::State, _input: &Self::Input) -> (bool, Self::State) {
+        (false, state.clone())
     }
 }
@@ -0,0 +1,135 @@
+use super::{partition_by_key, partition_events_by_key};
+use crate::edn::Edn;
+use crate::history::{LogFormat, LogType};
+use crate::model::{Events, Model, Operations};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CounterOp {
+    Add(u64),
+    Read,
+}
+
+#[derive(Clone, Debug)]
+pub struct CounterInput {
+    pub key: String,
+    pub op: CounterOp,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CounterOutput {
+    /// The value read.
+    pub value: u64,
+}
+
+/// Counters that only grow, starting at 0.
+#[derive(Clone, Default)]
+pub struct CounterModel {}
+
+impl Model for CounterModel {
+    type State = u64;
+    type Input = CounterInput;
+    type Output = CounterOutput;
+
+    fn partition(
+        &self,
+        history: Operations<Self::Input, Self::Output>,
+    ) -> Vec<Operations<Self::Input, Self::Output>> {
+        partition_by_key(history, |input| &input.key)
+    }
+
+    fn partition_event(
+        &self,
+        history: Events<Self::Input, Self::Output>,
+    ) -> Vec<Events<Self::Input, Self::Output>> {
+        partition_events_by_key(history, |input| &input.key)
+    }
+
+    fn init(&self) -> Self::State {
+        0
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        match input.op {
+            CounterOp::Add(n) => (true, state + n),
+            CounterOp::Read => (output.value == *state, *state),
+        }
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        match input.op {
+            CounterOp::Add(n) => (true, state + n),
+            CounterOp::Read => (true, *state),
+        }
+    }
+}
+
+impl LogFormat for CounterModel {
+    fn encode_call(input: &CounterInput) -> Vec<(&'static str, Edn)> {
+        let (f, value) = match input.op {
+            CounterOp::Add(n) => ("add", Edn::Int(n as i64)),
+            CounterOp::Read => ("read", Edn::Nil),
+        };
+        vec![
+            ("f", Edn::keyword(f)),
+            ("key", Edn::str(&input.key)),
+            ("value", value),
+        ]
+    }
+
+    fn encode_return(
+        input: &CounterInput,
+        output: &CounterOutput,
+    ) -> (LogType, Vec<(&'static str, Edn)>) {
+        let mut entries = Self::encode_call(input);
+        if input.op == CounterOp::Read {
+            entries[2].1 = Edn::Int(output.value as i64);
+        }
+        (LogType::Ok, entries)
+    }
+
+    fn decode_call(event: &Edn) -> Result<CounterInput, String> {
+        let op = match event.get("f").as_keyword()? {
+            "add" => CounterOp::Add(non_negative(event.get("value"))?),
+            "read" => CounterOp::Read,
+            f => return Err(format!("unknown function {:?}", f)),
+        };
+        Ok(CounterInput {
+            key: event.get("key").as_string()?,
+            op,
+        })
+    }
+
+    fn decode_return(_: LogType, event: &Edn) -> Result<CounterOutput, String> {
+        match event.get("f").as_keyword()? {
+            "read" => Ok(CounterOutput {
+                value: non_negative(event.get("value"))?,
+            }),
+            _ => Ok(CounterOutput::default()),
+        }
+    }
+}
+
+fn non_negative(value: &Edn) -> Result<u64, String> {
+    let n = value.as_int()?;
+    if n < 0 {
+        return Err(format!("negative value {}", n));
+    }
+    Ok(n as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::check_fixture;
+    use super::CounterModel;
+
+    #[test]
+    fn test_counter() {
+        assert!(check_fixture(CounterModel {}, "counter-ok"));
+        assert!(!check_fixture(CounterModel {}, "counter-bad"));
+    }
+}
@@ -0,0 +1,378 @@
+use super::{partition_by_key, partition_events_by_key};
+use crate::edn::Edn;
+use crate::history::{LogFormat, LogType};
+use crate::model::{Events, Model, Operations};
+
+#[derive(Clone, Debug)]
+pub enum Op {
+    Get,
+    Put,
+    Append,
+}
+
+#[derive(Clone, Debug)]
+pub struct KvInput {
+    pub op: Op,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct KvOutput {
+    pub value: String,
+}
+
+#[derive(Clone, Default)]
+pub struct KvModel {}
+
+impl Model for KvModel {
+    type State = String;
+    type Input = KvInput;
+    type Output = KvOutput;
+
+    fn partition(
+        &self,
+        history: Operations<Self::Input, Self::Output>,
+    ) -> Vec<Operations<Self::Input, Self::Output>> {
+        partition_by_key(history, |input| &input.key)
+    }
+
+    fn partition_event(
+        &self,
+        history: Events<Self::Input, Self::Output>,
+    ) -> Vec<Events<Self::Input, Self::Output>> {
+        partition_events_by_key(history, |input| &input.key)
+    }
+
+    fn init(&self) -> Self::State {
+        // note: we are modeling a single key's value here;
+        // we're partitioning by key, so this is okay
+        "".to_string()
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        match input.op {
+            Op::Get => (&output.value == state, state.clone()),
+            Op::Put => (true, input.value.clone()),
+            Op::Append => (true, state.clone() + &input.value),
+        }
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        match input.op {
+            Op::Get => (true, state.clone()),
+            Op::Put => (true, input.value.clone()),
+            Op::Append => (true, state.clone() + &input.value),
+        }
+    }
+}
+
+impl LogFormat for KvModel {
+    fn encode_call(input: &KvInput) -> Vec<(&'static str, Edn)> {
+        let (f, value) = match input.op {
+            Op::Get => ("get", Edn::Nil),
+            Op::Put => ("put", Edn::str(&input.value)),
+            Op::Append => ("append", Edn::str(&input.value)),
+        };
+        vec![
+            ("f", Edn::keyword(f)),
+            ("key", Edn::str(&input.key)),
+            ("value", value),
+        ]
+    }
+
+    fn encode_return(input: &KvInput, output: &KvOutput) -> (LogType, Vec<(&'static str, Edn)>) {
+        let mut entries = Self::encode_call(input);
+        if let Op::Get = input.op {
+            entries[2].1 = Edn::str(&output.value);
+        }
+        (LogType::Ok, entries)
+    }
+
+    fn decode_call(event: &Edn) -> Result<KvInput, String> {
+        let op = match event.get("f").as_keyword()? {
+            "get" => Op::Get,
+            "put" => Op::Put,
+            "append" => Op::Append,
+            f => return Err(format!("unknown function {:?}", f)),
+        };
+        let value = match op {
+            Op::Get => String::new(),
+            _ => event.get("value").as_string()?,
+        };
+        Ok(KvInput {
+            op,
+            key: event.get("key").as_string()?,
+            value,
+        })
+    }
+
+    fn decode_return(_: LogType, event: &Edn) -> Result<KvOutput, String> {
+        match event.get("f").as_keyword()? {
+            "get" => Ok(KvOutput {
+                value: event.get("value").as_string()?,
+            }),
+            _ => Ok(KvOutput::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufReader, Result};
+    use std::thread;
+
+    use std::time::Duration;
+
+    use super::{KvInput, KvModel, KvOutput, Op};
+    use crate::history::LogFormat;
+    use crate::model::{Event, EventKind, Events, Model, Operation, Value};
+    use crate::visualize::render_html;
+    use crate::{
+        check_events, check_events_timeout, check_events_verbose, check_operations_verbose,
+        CheckOutcome,
+    };
+
+    fn check_kv(log_name: String, correct: bool) {
+        let model = KvModel {};
+
+        let file_name = format!("../linearizability/test_data/{}.txt", &log_name);
+        let events = match parse_kv_log(&file_name) {
+            Ok(events) => events,
+            Err(e) => panic!("parse kv log {} failed: {}", &file_name, e),
+        };
+        assert_eq!(check_events(model, events), correct);
+    }
+
+    fn parse_kv_log(file_name: &str) -> Result<Events<KvInput, KvOutput>> {
+        KvModel::read_log(BufReader::new(File::open(file_name)?))
+    }
+
+    #[test]
+    fn test_kv_1client_ok() {
+        check_kv("c01-ok".to_string(), true)
+    }
+
+    #[test]
+    fn test_kv_1client_bad() {
+        check_kv("c01-bad".to_string(), false)
+    }
+
+    #[test]
+    fn test_kv_10client_ok() {
+        check_kv("c10-ok".to_string(), true)
+    }
+
+    #[test]
+    fn test_kv_10client_bad() {
+        check_kv("c10-bad".to_string(), false)
+    }
+
+    #[test]
+    fn test_kv_50client_ok() {
+        check_kv("c50-ok".to_string(), true)
+    }
+
+    #[test]
+    fn test_kv_50client_bad() {
+        check_kv("c50-bad".to_string(), false)
+    }
+
+    #[test]
+    fn test_kv_counterexample() {
+        let op = |op, key: &str, value: &str, output: &str, call, finish| Operation {
+            client_id: 0,
+            input: KvInput {
+                op,
+                key: key.to_owned(),
+                value: value.to_owned(),
+            },
+            call,
+            output: Some(KvOutput {
+                value: output.to_owned(),
+            }),
+            finish,
+        };
+        let history = vec![
+            op(Op::Put, "x", "1", "", 0, 10),
+            op(Op::Put, "y", "1", "", 0, 10),
+            op(Op::Get, "x", "", "1", 20, 30),
+            op(Op::Get, "x", "", "2", 40, 50),
+        ];
+        let res = check_operations_verbose(KvModel {}, history, Duration::new(0, 0));
+        assert_eq!(res.outcome, CheckOutcome::Illegal);
+        assert_eq!(res.partitions.len(), 2);
+        let failures: Vec<_> = res.failures().collect();
+        assert_eq!(failures.len(), 1);
+        let p = failures[0];
+        assert_eq!(p.linearized, vec![0, 1]);
+        assert_eq!(p.unlinearized, vec![2]);
+        assert_eq!(p.operation(2).output, r#"KvOutput { value: "2" }"#);
+        assert_eq!(p.states, vec!["1".to_owned()]);
+    }
+
+    #[test]
+    fn test_kv_bad_report() {
+        let events = parse_kv_log("../linearizability/test_data/c10-bad.txt").unwrap();
+        let res = check_events_verbose(KvModel {}, events, Duration::new(0, 0));
+        assert_eq!(res.outcome, CheckOutcome::Illegal);
+        let p = res.failures().next().unwrap();
+        assert!(p.linearized.len() < p.operations.len());
+        assert!(!p.unlinearized.is_empty());
+        assert!(!p.states.is_empty());
+        for id in &p.unlinearized {
+            assert!(!p.linearized.contains(id));
+        }
+        assert!(res.to_string().starts_with("not linearizable"));
+
+        let html = render_html(&res);
+        assert!(html.contains("<h1>History is not linearizable</h1>"));
+        assert_eq!(html.matches("<svg").count(), res.partitions.len());
+        let ops: usize = res.partitions.iter().map(|p| p.operations.len()).sum();
+        assert_eq!(html.matches("<rect").count(), ops);
+        assert_eq!(
+            html.matches("class=\"failed\"").count(),
+            res.failures().map(|p| p.unlinearized.len()).sum::<usize>()
+        );
+
+        let events = parse_kv_log("../linearizability/test_data/c10-ok.txt").unwrap();
+        let res = check_events_verbose(KvModel {}, events, Duration::new(0, 0));
+        assert!(res.is_ok());
+        for p in &res.partitions {
+            assert_eq!(p.linearized.len(), p.operations.len());
+            assert!(p.unlinearized.is_empty());
+        }
+    }
+
+    // KvModel taking a millisecond per step, so that no partition of a long
+    // history can be checked before a short timeout, however fast the
+    // search is.
+    #[derive(Clone)]
+    struct SlowKvModel;
+
+    impl Model for SlowKvModel {
+        type State = String;
+        type Input = KvInput;
+        type Output = KvOutput;
+
+        fn partition_event(
+            &self,
+            history: Events<KvInput, KvOutput>,
+        ) -> Vec<Events<KvInput, KvOutput>> {
+            KvModel {}.partition_event(history)
+        }
+
+        fn init(&self) -> String {
+            KvModel {}.init()
+        }
+
+        fn step(&self, state: &String, input: &KvInput, output: &KvOutput) -> (bool, String) {
+            thread::sleep(Duration::from_millis(1));
+            KvModel {}.step(state, input, output)
+        }
+    }
+
+    #[test]
+    fn test_kv_timeout() {
+        let events = parse_kv_log("../linearizability/test_data/c50-ok.txt").unwrap();
+        let res = check_events_verbose(SlowKvModel, events, Duration::from_millis(10));
+        assert_eq!(res.outcome, CheckOutcome::Unknown);
+        assert_eq!(res.failures().count(), 0);
+        let p = res.timed_out().next().unwrap();
+        assert!(p.linearized.len() < p.operations.len());
+        assert!(res.to_string().starts_with("unknown"));
+        assert_eq!(
+            res.partitions.len(),
+            res.timed_out().count()
+                + res
+                    .partitions
+                    .iter()
+                    .filter(|p| p.outcome == CheckOutcome::Ok)
+                    .count()
+        );
+
+        // A failure is reported even if other partitions time out.
+        let events = parse_kv_log("../linearizability/test_data/c01-bad.txt").unwrap();
+        let outcome = check_events_timeout(KvModel {}, events, Duration::from_secs(10));
+        assert_eq!(outcome, CheckOutcome::Illegal);
+    }
+
+    #[test]
+    fn test_kv_pending() {
+        let input = |op, value: &str| KvInput {
+            op,
+            key: "x".to_owned(),
+            value: value.to_owned(),
+        };
+        let check = |got: &str| {
+            let history = vec![
+                Operation {
+                    client_id: 0,
+                    input: input(Op::Put, "1"),
+                    call: 0,
+                    output: Some(KvOutput::default()),
+                    finish: 10,
+                },
+                Operation::pending(1, input(Op::Append, "2"), 5),
+                Operation {
+                    client_id: 2,
+                    input: input(Op::Get, ""),
+                    call: 20,
+                    output: Some(KvOutput {
+                        value: got.to_owned(),
+                    }),
+                    finish: 30,
+                },
+            ];
+            check_operations_verbose(KvModel {}, history, Duration::new(0, 0))
+        };
+
+        // The append took effect before the get.
+        let res = check("12");
+        assert!(res.is_ok());
+        assert_eq!(res.partitions[0].linearized, vec![0, 1, 2]);
+        // The append took effect after the get, or never.
+        let res = check("1");
+        assert!(res.is_ok());
+        assert!(res.partitions[0].unlinearized.is_empty());
+        let res = check("2");
+        assert_eq!(res.outcome, CheckOutcome::Illegal);
+        assert!(res.partitions[0].operation(1).pending);
+
+        // The same history as events, the append never returns.
+        let event = |kind, value, id| Event {
+            client_id: id,
+            kind,
+            value,
+            id,
+        };
+        let events = vec![
+            event(EventKind::CallEvent, Value::Input(input(Op::Put, "1")), 0),
+            event(
+                EventKind::CallEvent,
+                Value::Input(input(Op::Append, "2")),
+                1,
+            ),
+            event(
+                EventKind::ReturnEvent,
+                Value::Output(KvOutput::default()),
+                0,
+            ),
+            event(EventKind::CallEvent, Value::Input(input(Op::Get, "")), 2),
+            event(
+                EventKind::ReturnEvent,
+                Value::Output(KvOutput {
+                    value: "12".to_owned(),
+                }),
+                2,
+            ),
+        ];
+        assert!(check_events(KvModel {}, events));
+    }
+}
@@ -0,0 +1,74 @@
+//! Models of common objects. Models of keyed objects partition histories by
+//! key, the others model a single object.
+
+mod counter;
+mod kv;
+mod mutex;
+mod queue;
+mod register;
+mod set;
+mod txn;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use crate::model::{EventKind, Events, Operations};
+
+pub use self::counter::{CounterInput, CounterModel, CounterOp, CounterOutput};
+pub use self::kv::{KvInput, KvModel, KvOutput, Op};
+pub use self::mutex::{MutexInput, MutexModel, MutexOp, MutexOutput};
+pub use self::queue::{QueueModel, QueueOp, QueueOutput, QueueState};
+pub use self::register::{
+    CasInput, CasOp, CasOutput, CasRegisterModel, RegisterInput, RegisterModel, RegisterOp,
+    RegisterOutput,
+};
+pub use self::set::{SetModel, SetOp, SetOutput, SetState};
+pub use self::txn::{TxnInput, TxnModel, TxnOp, TxnOutput, TxnState};
+
+fn partition_by_key<I, O, F>(history: Operations<I, O>, key: F) -> Vec<Operations<I, O>>
+where
+    I: Debug,
+    O: Debug,
+    F: Fn(&I) -> &str,
+{
+    let mut m: HashMap<String, Operations<I, O>> = HashMap::new();
+    for op in history {
+        m.entry(key(&op.input).to_owned()).or_default().push(op);
+    }
+    m.into_values().collect()
+}
+
+fn partition_events_by_key<I, O, F>(history: Events<I, O>, key: F) -> Vec<Events<I, O>>
+where
+    I: Debug,
+    O: Debug,
+    F: Fn(&I) -> &str,
+{
+    let mut m: HashMap<String, Events<I, O>> = HashMap::new();
+    let mut matched: HashMap<usize, String> = HashMap::new();
+    for event in history {
+        let k = match event.kind {
+            EventKind::CallEvent => {
+                let k = key(event.value.input()).to_owned();
+                matched.insert(event.id, k.clone());
+                k
+            }
+            EventKind::ReturnEvent => matched[&event.id].clone(),
+        };
+        m.entry(k).or_default().push(event);
+    }
+    m.into_values().collect()
+}
+
+#[cfg(test)]
+fn check_fixture<M: crate::history::LogFormat>(model: M, name: &str) -> bool {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let file_name = format!("../linearizability/test_data/{}.txt", name);
+    let file = File::open(&file_name).unwrap();
+    match M::read_log(BufReader::new(file)) {
+        Ok(events) => crate::check_events(model, events),
+        Err(e) => panic!("parse {} failed: {}", file_name, e),
+    }
+}
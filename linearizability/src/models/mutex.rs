@@ -0,0 +1,125 @@
+use super::{partition_by_key, partition_events_by_key};
+use crate::edn::Edn;
+use crate::history::{LogFormat, LogType};
+use crate::model::{Events, Model, Operations};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MutexOp {
+    /// Takes the lock if it is free, fails otherwise.
+    Acquire,
+    /// Frees the lock if it is held, fails otherwise.
+    Release,
+}
+
+#[derive(Clone, Debug)]
+pub struct MutexInput {
+    pub key: String,
+    pub op: MutexOp,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MutexOutput {
+    pub ok: bool,
+}
+
+/// Locks with try-lock semantics, free initially. The state is whether the
+/// lock is held, the model does not track who holds it.
+#[derive(Clone, Default)]
+pub struct MutexModel {}
+
+impl Model for MutexModel {
+    type State = bool;
+    type Input = MutexInput;
+    type Output = MutexOutput;
+
+    fn partition(
+        &self,
+        history: Operations<Self::Input, Self::Output>,
+    ) -> Vec<Operations<Self::Input, Self::Output>> {
+        partition_by_key(history, |input| &input.key)
+    }
+
+    fn partition_event(
+        &self,
+        history: Events<Self::Input, Self::Output>,
+    ) -> Vec<Events<Self::Input, Self::Output>> {
+        partition_events_by_key(history, |input| &input.key)
+    }
+
+    fn init(&self) -> Self::State {
+        false
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        let held = *state;
+        match (&input.op, output.ok) {
+            (MutexOp::Acquire, true) => (!held, true),
+            (MutexOp::Release, true) => (held, false),
+            (MutexOp::Acquire, false) => (held, held),
+            (MutexOp::Release, false) => (!held, held),
+        }
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        // Failing has no effect, so it is the same as leaving the
+        // operation out.
+        self.step(state, input, &MutexOutput { ok: true })
+    }
+}
+
+impl LogFormat for MutexModel {
+    fn encode_call(input: &MutexInput) -> Vec<(&'static str, Edn)> {
+        let f = match input.op {
+            MutexOp::Acquire => "acquire",
+            MutexOp::Release => "release",
+        };
+        vec![("f", Edn::keyword(f)), ("key", Edn::str(&input.key))]
+    }
+
+    fn encode_return(
+        input: &MutexInput,
+        output: &MutexOutput,
+    ) -> (LogType, Vec<(&'static str, Edn)>) {
+        let kind = if output.ok {
+            LogType::Ok
+        } else {
+            LogType::Fail
+        };
+        (kind, Self::encode_call(input))
+    }
+
+    fn decode_call(event: &Edn) -> Result<MutexInput, String> {
+        let op = match event.get("f").as_keyword()? {
+            "acquire" => MutexOp::Acquire,
+            "release" => MutexOp::Release,
+            f => return Err(format!("unknown function {:?}", f)),
+        };
+        Ok(MutexInput {
+            key: event.get("key").as_string()?,
+            op,
+        })
+    }
+
+    fn decode_return(kind: LogType, _: &Edn) -> Result<MutexOutput, String> {
+        Ok(MutexOutput {
+            ok: kind == LogType::Ok,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::check_fixture;
+    use super::MutexModel;
+
+    #[test]
+    fn test_mutex() {
+        assert!(check_fixture(MutexModel {}, "mutex-ok"));
+        assert!(!check_fixture(MutexModel {}, "mutex-bad"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+
+use crate::edn::Edn;
+use crate::history::{LogFormat, LogType};
+use crate::model::Model;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueueOp {
+    Enqueue(i64),
+    Dequeue,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct QueueOutput {
+    /// The value dequeued, `None` if the queue was empty.
+    pub value: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct QueueState(pub VecDeque<i64>);
+
+impl Display for QueueState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<_> = self.0.iter().map(i64::to_string).collect();
+        write!(f, "[{}]", values.join(" "))
+    }
+}
+
+/// A FIFO queue, empty initially.
+#[derive(Clone, Default)]
+pub struct QueueModel {}
+
+impl Model for QueueModel {
+    type State = QueueState;
+    type Input = QueueOp;
+    type Output = QueueOutput;
+
+    fn init(&self) -> Self::State {
+        QueueState::default()
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        let mut next = state.clone();
+        match input {
+            QueueOp::Enqueue(v) => {
+                next.0.push_back(*v);
+                (true, next)
+            }
+            QueueOp::Dequeue => {
+                let value = next.0.pop_front();
+                (value == output.value, next)
+            }
+        }
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        let mut next = state.clone();
+        match input {
+            QueueOp::Enqueue(v) => next.0.push_back(*v),
+            QueueOp::Dequeue => {
+                next.0.pop_front();
+            }
+        }
+        (true, next)
+    }
+}
+
+// Like Jepsen, a dequeue from an empty queue fails.
+impl LogFormat for QueueModel {
+    fn encode_call(input: &QueueOp) -> Vec<(&'static str, Edn)> {
+        match input {
+            QueueOp::Enqueue(v) => vec![("f", Edn::keyword("enqueue")), ("value", Edn::Int(*v))],
+            QueueOp::Dequeue => vec![("f", Edn::keyword("dequeue")), ("value", Edn::Nil)],
+        }
+    }
+
+    fn encode_return(input: &QueueOp, output: &QueueOutput) -> (LogType, Vec<(&'static str, Edn)>) {
+        let mut entries = Self::encode_call(input);
+        match (input, output.value) {
+            (QueueOp::Dequeue, Some(v)) => entries[1].1 = Edn::Int(v),
+            (QueueOp::Dequeue, None) => return (LogType::Fail, entries),
+            _ => {}
+        }
+        (LogType::Ok, entries)
+    }
+
+    fn decode_call(event: &Edn) -> Result<QueueOp, String> {
+        match event.get("f").as_keyword()? {
+            "enqueue" => Ok(QueueOp::Enqueue(event.get("value").as_int()?)),
+            "dequeue" => Ok(QueueOp::Dequeue),
+            f => Err(format!("unknown function {:?}", f)),
+        }
+    }
+
+    fn decode_return(kind: LogType, event: &Edn) -> Result<QueueOutput, String> {
+        match (event.get("f").as_keyword()?, kind) {
+            ("dequeue", LogType::Ok) => Ok(QueueOutput {
+                value: Some(event.get("value").as_int()?),
+            }),
+            _ => Ok(QueueOutput::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::check_fixture;
+    use super::QueueModel;
+
+    #[test]
+    fn test_queue() {
+        assert!(check_fixture(QueueModel {}, "queue-ok"));
+        assert!(!check_fixture(QueueModel {}, "queue-bad"));
+    }
+}
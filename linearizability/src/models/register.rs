@@ -0,0 +1,260 @@
+use super::{partition_by_key, partition_events_by_key};
+use crate::edn::Edn;
+use crate::history::{LogFormat, LogType};
+use crate::model::{Events, Model, Operations};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegisterOp {
+    Read,
+    Write(i64),
+}
+
+#[derive(Clone, Debug)]
+pub struct RegisterInput {
+    pub key: String,
+    pub op: RegisterOp,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RegisterOutput {
+    /// The value read.
+    pub value: i64,
+}
+
+/// Read/write registers holding 0 initially.
+#[derive(Clone, Default)]
+pub struct RegisterModel {}
+
+impl Model for RegisterModel {
+    type State = i64;
+    type Input = RegisterInput;
+    type Output = RegisterOutput;
+
+    fn partition(
+        &self,
+        history: Operations<Self::Input, Self::Output>,
+    ) -> Vec<Operations<Self::Input, Self::Output>> {
+        partition_by_key(history, |input| &input.key)
+    }
+
+    fn partition_event(
+        &self,
+        history: Events<Self::Input, Self::Output>,
+    ) -> Vec<Events<Self::Input, Self::Output>> {
+        partition_events_by_key(history, |input| &input.key)
+    }
+
+    fn init(&self) -> Self::State {
+        0
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        match input.op {
+            RegisterOp::Read => (output.value == *state, *state),
+            RegisterOp::Write(v) => (true, v),
+        }
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        match input.op {
+            RegisterOp::Read => (true, *state),
+            RegisterOp::Write(v) => (true, v),
+        }
+    }
+}
+
+impl LogFormat for RegisterModel {
+    fn encode_call(input: &RegisterInput) -> Vec<(&'static str, Edn)> {
+        let (f, value) = match input.op {
+            RegisterOp::Read => ("read", Edn::Nil),
+            RegisterOp::Write(v) => ("write", Edn::Int(v)),
+        };
+        vec![
+            ("f", Edn::keyword(f)),
+            ("key", Edn::str(&input.key)),
+            ("value", value),
+        ]
+    }
+
+    fn encode_return(
+        input: &RegisterInput,
+        output: &RegisterOutput,
+    ) -> (LogType, Vec<(&'static str, Edn)>) {
+        let mut entries = Self::encode_call(input);
+        if input.op == RegisterOp::Read {
+            entries[2].1 = Edn::Int(output.value);
+        }
+        (LogType::Ok, entries)
+    }
+
+    fn decode_call(event: &Edn) -> Result<RegisterInput, String> {
+        let op = match event.get("f").as_keyword()? {
+            "read" => RegisterOp::Read,
+            "write" => RegisterOp::Write(event.get("value").as_int()?),
+            f => return Err(format!("unknown function {:?}", f)),
+        };
+        Ok(RegisterInput {
+            key: event.get("key").as_string()?,
+            op,
+        })
+    }
+
+    fn decode_return(_: LogType, event: &Edn) -> Result<RegisterOutput, String> {
+        match event.get("f").as_keyword()? {
+            "read" => Ok(RegisterOutput {
+                value: event.get("value").as_int()?,
+            }),
+            _ => Ok(RegisterOutput::default()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CasOp {
+    Read,
+    Write(i64),
+    /// Sets the register to the second value if it holds the first one.
+    Cas(i64, i64),
+}
+
+#[derive(Clone, Debug)]
+pub struct CasInput {
+    pub key: String,
+    pub op: CasOp,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CasOutput {
+    /// The value read.
+    pub value: i64,
+    /// Whether a compare-and-set succeeded.
+    pub ok: bool,
+}
+
+/// Registers holding 0 initially, with compare-and-set.
+#[derive(Clone, Default)]
+pub struct CasRegisterModel {}
+
+impl Model for CasRegisterModel {
+    type State = i64;
+    type Input = CasInput;
+    type Output = CasOutput;
+
+    fn partition(
+        &self,
+        history: Operations<Self::Input, Self::Output>,
+    ) -> Vec<Operations<Self::Input, Self::Output>> {
+        partition_by_key(history, |input| &input.key)
+    }
+
+    fn partition_event(
+        &self,
+        history: Events<Self::Input, Self::Output>,
+    ) -> Vec<Events<Self::Input, Self::Output>> {
+        partition_events_by_key(history, |input| &input.key)
+    }
+
+    fn init(&self) -> Self::State {
+        0
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        match input.op {
+            CasOp::Read => (output.value == *state, *state),
+            CasOp::Write(v) => (true, v),
+            CasOp::Cas(from, to) if output.ok => (*state == from, to),
+            CasOp::Cas(from, _) => (*state != from, *state),
+        }
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        match input.op {
+            CasOp::Read => (true, *state),
+            CasOp::Write(v) => (true, v),
+            // A failed compare-and-set has no effect.
+            CasOp::Cas(from, to) => (*state == from, to),
+        }
+    }
+}
+
+impl LogFormat for CasRegisterModel {
+    fn encode_call(input: &CasInput) -> Vec<(&'static str, Edn)> {
+        let (f, value) = match input.op {
+            CasOp::Read => ("read", Edn::Nil),
+            CasOp::Write(v) => ("write", Edn::Int(v)),
+            CasOp::Cas(from, to) => ("cas", Edn::Vector(vec![Edn::Int(from), Edn::Int(to)])),
+        };
+        vec![
+            ("f", Edn::keyword(f)),
+            ("key", Edn::str(&input.key)),
+            ("value", value),
+        ]
+    }
+
+    fn encode_return(input: &CasInput, output: &CasOutput) -> (LogType, Vec<(&'static str, Edn)>) {
+        let mut entries = Self::encode_call(input);
+        match input.op {
+            CasOp::Read => entries[2].1 = Edn::Int(output.value),
+            CasOp::Cas(..) if !output.ok => return (LogType::Fail, entries),
+            _ => {}
+        }
+        (LogType::Ok, entries)
+    }
+
+    fn decode_call(event: &Edn) -> Result<CasInput, String> {
+        let op = match event.get("f").as_keyword()? {
+            "read" => CasOp::Read,
+            "write" => CasOp::Write(event.get("value").as_int()?),
+            "cas" => match event.get("value").as_vector()? {
+                [from, to] => CasOp::Cas(from.as_int()?, to.as_int()?),
+                _ => return Err(format!("bad cas {}", event.get("value"))),
+            },
+            f => return Err(format!("unknown function {:?}", f)),
+        };
+        Ok(CasInput {
+            key: event.get("key").as_string()?,
+            op,
+        })
+    }
+
+    fn decode_return(kind: LogType, event: &Edn) -> Result<CasOutput, String> {
+        match event.get("f").as_keyword()? {
+            "read" => Ok(CasOutput {
+                value: event.get("value").as_int()?,
+                ok: true,
+            }),
+            _ => Ok(CasOutput {
+                value: 0,
+                ok: kind == LogType::Ok,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::check_fixture;
+    use super::{CasRegisterModel, RegisterModel};
+
+    #[test]
+    fn test_register() {
+        assert!(check_fixture(RegisterModel {}, "register-ok"));
+        assert!(!check_fixture(RegisterModel {}, "register-bad"));
+    }
+
+    #[test]
+    fn test_cas_register() {
+        assert!(check_fixture(CasRegisterModel {}, "cas-register-ok"));
+        assert!(!check_fixture(CasRegisterModel {}, "cas-register-bad"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+use crate::edn::Edn;
+use crate::history::{LogFormat, LogType};
+use crate::model::Model;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SetOp {
+    Add(i64),
+    Remove(i64),
+    Read,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SetOutput {
+    /// The elements read, in any order.
+    pub values: Vec<i64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SetState(pub BTreeSet<i64>);
+
+impl Display for SetState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<_> = self.0.iter().map(i64::to_string).collect();
+        write!(f, "#{{{}}}", values.join(" "))
+    }
+}
+
+/// A set, empty initially. Reads return every element so histories cannot
+/// be partitioned by element.
+#[derive(Clone, Default)]
+pub struct SetModel {}
+
+impl Model for SetModel {
+    type State = SetState;
+    type Input = SetOp;
+    type Output = SetOutput;
+
+    fn init(&self) -> Self::State {
+        SetState::default()
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        match input {
+            SetOp::Read => {
+                let read: BTreeSet<_> = output.values.iter().copied().collect();
+                (
+                    read.len() == output.values.len() && read == state.0,
+                    state.clone(),
+                )
+            }
+            _ => self.step_pending(state, input),
+        }
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        let mut next = state.clone();
+        match input {
+            SetOp::Add(v) => {
+                next.0.insert(*v);
+            }
+            SetOp::Remove(v) => {
+                next.0.remove(v);
+            }
+            SetOp::Read => {}
+        }
+        (true, next)
+    }
+}
+
+impl LogFormat for SetModel {
+    fn encode_call(input: &SetOp) -> Vec<(&'static str, Edn)> {
+        let (f, value) = match input {
+            SetOp::Add(v) => ("add", Edn::Int(*v)),
+            SetOp::Remove(v) => ("remove", Edn::Int(*v)),
+            SetOp::Read => ("read", Edn::Nil),
+        };
+        vec![("f", Edn::keyword(f)), ("value", value)]
+    }
+
+    fn encode_return(input: &SetOp, output: &SetOutput) -> (LogType, Vec<(&'static str, Edn)>) {
+        let mut entries = Self::encode_call(input);
+        if *input == SetOp::Read {
+            entries[1].1 = Edn::Vector(output.values.iter().map(|v| Edn::Int(*v)).collect());
+        }
+        (LogType::Ok, entries)
+    }
+
+    fn decode_call(event: &Edn) -> Result<SetOp, String> {
+        match event.get("f").as_keyword()? {
+            "add" => Ok(SetOp::Add(event.get("value").as_int()?)),
+            "remove" => Ok(SetOp::Remove(event.get("value").as_int()?)),
+            "read" => Ok(SetOp::Read),
+            f => Err(format!("unknown function {:?}", f)),
+        }
+    }
+
+    fn decode_return(_: LogType, event: &Edn) -> Result<SetOutput, String> {
+        match event.get("f").as_keyword()? {
+            "read" => Ok(SetOutput {
+                values: event
+                    .get("value")
+                    .as_vector()?
+                    .iter()
+                    .map(Edn::as_int)
+                    .collect::<Result<_, _>>()?,
+            }),
+            _ => Ok(SetOutput::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::check_fixture;
+    use super::SetModel;
+
+    #[test]
+    fn test_set() {
+        assert!(check_fixture(SetModel {}, "set-ok"));
+        assert!(!check_fixture(SetModel {}, "set-bad"));
+    }
+}
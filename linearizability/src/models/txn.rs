@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use crate::edn::Edn;
+use crate::history::{LogFormat, LogType};
+use crate::model::Model;
+
+/// One step of a transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxnOp {
+    Read(Vec<u8>),
+    Write(Vec<u8>, Vec<u8>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TxnInput {
+    pub ops: Vec<TxnOp>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TxnOutput {
+    /// Values returned by the reads, in order, empty for a missing key.
+    pub reads: Vec<Vec<u8>>,
+    /// Aborted transactions have no effect and their reads are not checked.
+    pub committed: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TxnState(pub BTreeMap<Vec<u8>, Vec<u8>>);
+
+impl Display for TxnState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kvs: Vec<_> = self
+            .0
+            .iter()
+            .map(|(k, v)| {
+                format!(
+                    "{}: {}",
+                    String::from_utf8_lossy(k),
+                    String::from_utf8_lossy(v)
+                )
+            })
+            .collect();
+        write!(f, "{{{}}}", kvs.join(", "))
+    }
+}
+
+/// Key-value transactions that take effect atomically, a history is
+/// linearizable under this model if it is strictly serializable.
+///
+/// Snapshot isolation allows anomalies like write skew that this model
+/// rejects, so only histories of workloads that cannot produce them can be
+/// checked against a snapshot isolated store. `crate::isolation` checks
+/// histories for the anomalies each isolation level proscribes instead.
+#[derive(Clone, Default)]
+pub struct TxnModel {}
+
+impl Model for TxnModel {
+    type State = TxnState;
+    type Input = TxnInput;
+    type Output = TxnOutput;
+
+    fn init(&self) -> Self::State {
+        TxnState::default()
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        if !output.committed {
+            return (true, state.clone());
+        }
+        let mut next = state.clone();
+        let mut reads = output.reads.iter();
+        for op in &input.ops {
+            match op {
+                TxnOp::Read(key) => {
+                    // A transaction reads its own writes.
+                    let value = next.0.get(key).map(Vec::as_slice).unwrap_or_default();
+                    if reads.next().map(Vec::as_slice) != Some(value) {
+                        return (false, state.clone());
+                    }
+                }
+                TxnOp::Write(key, value) => {
+                    next.0.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        (reads.next().is_none(), next)
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        let mut next = state.clone();
+        for op in &input.ops {
+            if let TxnOp::Write(key, value) = op {
+                next.0.insert(key.clone(), value.clone());
+            }
+        }
+        (true, next)
+    }
+}
+
+impl LogFormat for TxnModel {
+    // A transaction is a vector of reads and writes:
+    // `[[:r "x" "1"] [:w "y" "2"]]`, reads are `nil` until it commits.
+    fn encode_call(input: &TxnInput) -> Vec<(&'static str, Edn)> {
+        encode(input, None)
+    }
+
+    fn encode_return(input: &TxnInput, output: &TxnOutput) -> (LogType, Vec<(&'static str, Edn)>) {
+        let kind = if output.committed {
+            LogType::Ok
+        } else {
+            LogType::Fail
+        };
+        (kind, encode(input, Some(output)))
+    }
+
+    fn decode_call(event: &Edn) -> Result<TxnInput, String> {
+        decode(event).map(|(input, _)| input)
+    }
+
+    fn decode_return(kind: LogType, event: &Edn) -> Result<TxnOutput, String> {
+        let (_, reads) = decode(event)?;
+        Ok(TxnOutput {
+            reads,
+            committed: kind == LogType::Ok,
+        })
+    }
+}
+
+fn encode(input: &TxnInput, output: Option<&TxnOutput>) -> Vec<(&'static str, Edn)> {
+    let mut reads = output.map(|output| output.reads.iter());
+    let ops = input
+        .ops
+        .iter()
+        .map(|op| match op {
+            TxnOp::Read(key) => {
+                let value = match reads.as_mut().and_then(Iterator::next) {
+                    Some(value) => Edn::Bytes(value.clone()),
+                    None => Edn::Nil,
+                };
+                Edn::Vector(vec![Edn::keyword("r"), Edn::Bytes(key.clone()), value])
+            }
+            TxnOp::Write(key, value) => Edn::Vector(vec![
+                Edn::keyword("w"),
+                Edn::Bytes(key.clone()),
+                Edn::Bytes(value.clone()),
+            ]),
+        })
+        .collect();
+    vec![("f", Edn::keyword("txn")), ("value", Edn::Vector(ops))]
+}
+
+// The steps of a transaction and the values read.
+fn decode(event: &Edn) -> Result<(TxnInput, Vec<Vec<u8>>), String> {
+    if event.get("f").as_keyword()? != "txn" {
+        return Err(format!("unknown function {}", event.get("f")));
+    }
+    let mut ops = vec![];
+    let mut reads = vec![];
+    for op in event.get("value").as_vector()? {
+        let op = op.as_vector()?;
+        if op.len() != 3 {
+            return Err(format!("bad step {}", Edn::Vector(op.to_vec())));
+        }
+        let key = op[1].as_bytes()?.to_vec();
+        let value = match &op[2] {
+            Edn::Nil => vec![],
+            value => value.as_bytes()?.to_vec(),
+        };
+        match op[0].as_keyword()? {
+            "r" => {
+                ops.push(TxnOp::Read(key));
+                reads.push(value);
+            }
+            "w" => ops.push(TxnOp::Write(key, value)),
+            f => return Err(format!("unknown step {:?}", f)),
+        }
+    }
+    Ok((TxnInput { ops }, reads))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TxnInput, TxnModel, TxnOp, TxnOutput, TxnState};
+    use crate::check_operations;
+    use crate::model::{Model, Operation};
+
+    fn r(k: &str) -> TxnOp {
+        TxnOp::Read(k.as_bytes().to_vec())
+    }
+
+    fn w(k: &str, v: &str) -> TxnOp {
+        TxnOp::Write(k.as_bytes().to_vec(), v.as_bytes().to_vec())
+    }
+
+    fn state(kvs: &[(&str, &str)]) -> TxnState {
+        TxnState(
+            kvs.iter()
+                .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    fn output(reads: &[&str], committed: bool) -> TxnOutput {
+        TxnOutput {
+            reads: reads.iter().map(|r| r.as_bytes().to_vec()).collect(),
+            committed,
+        }
+    }
+
+    #[test]
+    fn test_step() {
+        let model = TxnModel {};
+        let s0 = state(&[("x", "1")]);
+        let input = TxnInput {
+            ops: vec![r("x"), r("y"), w("x", "2"), r("x"), w("y", "3")],
+        };
+
+        let (ok, s1) = model.step(&s0, &input, &output(&["1", "", "2"], true));
+        assert!(ok);
+        assert_eq!(s1, state(&[("x", "2"), ("y", "3")]));
+
+        // A read of a missing key is empty, and reads follow own writes.
+        let (ok, s) = model.step(&s0, &input, &output(&["1", "", "1"], true));
+        assert!(!ok);
+        assert_eq!(s, s0);
+        let (ok, _) = model.step(&s0, &input, &output(&["1", "0", "2"], true));
+        assert!(!ok);
+
+        // Every read returns exactly one value.
+        let (ok, _) = model.step(&s0, &input, &output(&["1", ""], true));
+        assert!(!ok);
+        let (ok, _) = model.step(&s0, &input, &output(&["1", "", "2", "2"], true));
+        assert!(!ok);
+
+        // An aborted transaction has no effect, whatever it read.
+        let (ok, s) = model.step(&s0, &input, &output(&["7"], false));
+        assert!(ok);
+        assert_eq!(s, s0);
+    }
+
+    #[test]
+    fn test_step_pending() {
+        let model = TxnModel {};
+        let s0 = state(&[("x", "1")]);
+        let input = TxnInput {
+            ops: vec![r("x"), w("x", "2"), w("y", "3"), w("x", "4")],
+        };
+        // Takes effect with its writes, the last one to a key wins, and its
+        // reads are unknown.
+        let (ok, s1) = model.step_pending(&s0, &input);
+        assert!(ok);
+        assert_eq!(s1, state(&[("x", "4"), ("y", "3")]));
+
+        let (ok, s) = model.step_pending(&s0, &TxnInput { ops: vec![r("y")] });
+        assert!(ok);
+        assert_eq!(s, s0);
+    }
+
+    #[test]
+    fn test_txn() {
+        let txn = |client_id, ops, reads: &[&str], committed, call, finish| Operation {
+            client_id,
+            input: TxnInput { ops },
+            call,
+            output: Some(TxnOutput {
+                reads: reads.iter().map(|r| r.as_bytes().to_vec()).collect(),
+                committed,
+            }),
+            finish,
+        };
+        let history = || {
+            vec![
+                txn(0, vec![w("x", "1"), w("y", "1")], &[], true, 0, 10),
+                // Aborted, its write is never seen.
+                txn(1, vec![w("x", "2")], &[], false, 0, 10),
+                txn(
+                    2,
+                    vec![r("x"), w("y", "2"), r("y")],
+                    &["1", "2"],
+                    true,
+                    20,
+                    30,
+                ),
+            ]
+        };
+        assert!(check_operations(TxnModel {}, history()));
+
+        // Reads both before and after the first transaction.
+        let mut bad = history();
+        bad.push(txn(3, vec![r("x"), r("y")], &["1", ""], true, 40, 50));
+        assert!(!check_operations(TxnModel {}, bad));
+
+        // The aborted write becomes visible.
+        let mut bad = history();
+        bad.push(txn(3, vec![r("x")], &["2"], true, 40, 50));
+        assert!(!check_operations(TxnModel {}, bad));
+
+        // A pending transaction may have committed.
+        let mut maybe = history();
+        maybe.push(Operation::pending(
+            3,
+            TxnInput {
+                ops: vec![w("x", "3")],
+            },
+            35,
+        ));
+        maybe.push(txn(4, vec![r("x")], &["3"], true, 40, 50));
+        assert!(check_operations(TxnModel {}, maybe));
+    }
+}
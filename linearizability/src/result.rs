@@ -0,0 +1,128 @@
+use std::fmt::{self, Display};
+
+/// An operation of a partition as seen by the checker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OperationInfo {
+    /// Index of the operation in its partition.
+    pub id: usize,
+    pub client_id: usize,
+    pub input: String,
+    pub output: String,
+    // For event histories, the positions of the call and return events.
+    pub call: i64,
+    /// `i64::MAX` if pending.
+    pub finish: i64,
+    /// Whether the outcome is unknown, `output` is `?` then.
+    pub pending: bool,
+}
+
+/// Whether a history, or a partition of it, is linearizable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckOutcome {
+    Ok,
+    /// The check timed out before finding a linearization or proving that
+    /// there is none.
+    Unknown,
+    Illegal,
+}
+
+/// Outcome of checking one partition of a history.
+#[derive(Clone, Debug)]
+pub struct PartitionResult<S> {
+    pub outcome: CheckOutcome,
+    pub operations: Vec<OperationInfo>,
+    /// Ids of the longest linearizable prefix found, in linearization order.
+    pub linearized: Vec<usize>,
+    /// Model state after each operation of `linearized`.
+    pub steps: Vec<S>,
+    /// Ids of the operations that could not be linearized after that
+    /// prefix, or were not tried yet if the check timed out, by call time.
+    pub unlinearized: Vec<usize>,
+    /// Distinct model states reached by the longest prefixes.
+    pub states: Vec<S>,
+}
+
+impl<S> PartitionResult<S> {
+    pub fn operation(&self, id: usize) -> &OperationInfo {
+        &self.operations[id]
+    }
+}
+
+/// Outcome of checking a whole history.
+#[derive(Clone, Debug)]
+pub struct CheckResult<S> {
+    /// `Illegal` if any partition is, otherwise `Unknown` if any partition
+    /// timed out.
+    pub outcome: CheckOutcome,
+    /// One entry per partition from `Model::partition`.
+    pub partitions: Vec<PartitionResult<S>>,
+}
+
+impl<S> CheckResult<S> {
+    pub fn is_ok(&self) -> bool {
+        self.outcome == CheckOutcome::Ok
+    }
+
+    /// Partitions that are not linearizable.
+    pub fn failures(&self) -> impl Iterator<Item = &PartitionResult<S>> {
+        self.partitions
+            .iter()
+            .filter(|p| p.outcome == CheckOutcome::Illegal)
+    }
+
+    /// Partitions whose check timed out.
+    pub fn timed_out(&self) -> impl Iterator<Item = &PartitionResult<S>> {
+        self.partitions
+            .iter()
+            .filter(|p| p.outcome == CheckOutcome::Unknown)
+    }
+}
+
+impl<S: Display> Display for CheckResult<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.outcome {
+            CheckOutcome::Ok => return write!(f, "linearizable"),
+            CheckOutcome::Unknown => writeln!(f, "unknown, the check timed out")?,
+            CheckOutcome::Illegal => writeln!(f, "not linearizable")?,
+        }
+        for (i, p) in self.partitions.iter().enumerate() {
+            let verdict = match p.outcome {
+                CheckOutcome::Ok => continue,
+                CheckOutcome::Unknown => "timed out",
+                CheckOutcome::Illegal => "illegal",
+            };
+            writeln!(
+                f,
+                "partition {} {}: linearized {} of {} operations",
+                i,
+                verdict,
+                p.linearized.len(),
+                p.operations.len()
+            )?;
+            if p.outcome == CheckOutcome::Unknown {
+                continue;
+            }
+            for id in &p.linearized {
+                writeln!(f, "  ok   {}", describe(p.operation(*id)))?;
+            }
+            for id in &p.unlinearized {
+                writeln!(f, "  FAIL {}", describe(p.operation(*id)))?;
+            }
+            let states: Vec<_> = p
+                .states
+                .iter()
+                .map(|s| format!("{:?}", s.to_string()))
+                .collect();
+            writeln!(f, "  candidate states: [{}]", states.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn describe(op: &OperationInfo) -> String {
+    if op.pending {
+        format!("[{}, ?] {} -> ?", op.call, op.input)
+    } else {
+        format!("[{}, {}] {} -> {}", op.call, op.finish, op.input, op.output)
+    }
+}
@@ -0,0 +1,254 @@
+//! Checks histories for sequential consistency: the operations must take
+//! effect in some order that keeps the order of each client's operations,
+//! but not the real-time order across clients.
+//!
+//! Unlike linearizability, sequential consistency is not compositional:
+//! clients may see the updates of different partitions in orders that no
+//! single order explains. So the history is searched as a whole, for one
+//! order of the operations of all partitions, keeping a state per partition
+//! of the model.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::model::{Model, Operations};
+use crate::CheckOutcome;
+
+pub fn check_operations<M: Model>(model: M, history: Operations<M::Input, M::Output>) -> bool {
+    check_operations_timeout(model, history, Duration::new(0, 0)) == CheckOutcome::Ok
+}
+
+// timeout = 0 means no timeout
+pub fn check_operations_timeout<M: Model>(
+    model: M,
+    history: Operations<M::Input, M::Output>,
+    timeout: Duration,
+) -> CheckOutcome {
+    let deadline = if timeout.as_secs() == 0 && timeout.subsec_nanos() == 0 {
+        None
+    } else {
+        Some(Instant::now() + timeout)
+    };
+
+    // The operations of each client with their partitions, in the order
+    // they were called.
+    let partitions = model.partition(history);
+    let mut history: Vec<_> = partitions
+        .iter()
+        .enumerate()
+        .flat_map(|(p, ops)| ops.iter().map(move |op| (p, op)))
+        .collect();
+    history.sort_by_key(|(_, op)| op.call);
+    let mut clients: HashMap<_, Vec<_>> = HashMap::new();
+    for (p, op) in history {
+        clients.entry(op.client_id).or_default().push((p, op));
+    }
+    let clients: Vec<_> = clients.into_values().collect();
+
+    // Depth-first search over how many operations of each client took
+    // effect, and the resulting state.
+    let mut states = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut positions = vec![0; clients.len()];
+    let init = vec![model.init(); partitions.len()];
+    states.insert(init.clone(), 0);
+    let mut stack = vec![Frame {
+        state: init,
+        next: 0,
+        client: None,
+    }];
+    let mut steps = 0u64;
+    while let Some(frame) = stack.last_mut() {
+        steps += 1;
+        if steps.is_multiple_of(10_000) && deadline.is_some_and(|d| Instant::now() > d) {
+            return CheckOutcome::Unknown;
+        }
+        if positions
+            .iter()
+            .zip(&clients)
+            .all(|(&pos, ops)| pos == ops.len())
+        {
+            return CheckOutcome::Ok;
+        }
+
+        // Each client's next operation either takes effect, or is left out
+        // if its outcome is unknown.
+        let mut child = None;
+        while frame.next < 2 * clients.len() && child.is_none() {
+            let (c, skip) = (frame.next / 2, frame.next % 2 == 1);
+            frame.next += 1;
+            let (p, op) = match clients[c].get(positions[c]) {
+                Some(&(p, op)) => (p, op),
+                None => continue,
+            };
+            let state = &frame.state[p];
+            let (ok, state) = match (&op.output, skip) {
+                (Some(output), false) => model.step(state, &op.input, output),
+                (Some(_), true) => continue,
+                (None, false) => model.step_pending(state, &op.input),
+                (None, true) => (true, state.clone()),
+            };
+            if !ok {
+                continue;
+            }
+            let mut next = frame.state.clone();
+            next[p] = state;
+            positions[c] += 1;
+            let count = states.len();
+            let id = *states.entry(next.clone()).or_insert(count);
+            if seen.insert((positions.clone(), id)) {
+                child = Some(Frame {
+                    state: next,
+                    next: 0,
+                    client: Some(c),
+                });
+            } else {
+                positions[c] -= 1;
+            }
+        }
+        match child {
+            Some(child) => stack.push(child),
+            None => {
+                if let Some(c) = stack.pop().unwrap().client {
+                    positions[c] -= 1;
+                }
+            }
+        }
+    }
+    CheckOutcome::Illegal
+}
+
+struct Frame<S> {
+    // The state of each partition.
+    state: Vec<S>,
+    // The next alternative to try, two per client.
+    next: usize,
+    // The client whose operation led to this state.
+    client: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_operations;
+    use crate::check_operations as check_linearizable;
+    use crate::model::Operation;
+    use crate::models::{KvInput, KvModel, KvOutput, Op, TxnInput, TxnModel, TxnOp, TxnOutput};
+
+    fn op(
+        client_id: usize,
+        op: Op,
+        key: &str,
+        value: &str,
+        call: i64,
+        finish: i64,
+    ) -> Operation<KvInput, KvOutput> {
+        let (input, output) = match op {
+            Op::Get => ("", value),
+            _ => (value, ""),
+        };
+        Operation {
+            client_id,
+            input: KvInput {
+                op,
+                key: key.to_owned(),
+                value: input.to_owned(),
+            },
+            call,
+            output: Some(KvOutput {
+                value: output.to_owned(),
+            }),
+            finish,
+        }
+    }
+
+    #[test]
+    fn test_sequential() {
+        // Client 1 reads after the write returned, but may be ordered
+        // before it.
+        let history = || {
+            vec![
+                op(0, Op::Put, "x", "1", 0, 10),
+                op(1, Op::Get, "x", "", 20, 30),
+                op(1, Op::Get, "x", "1", 40, 50),
+            ]
+        };
+        assert!(!check_linearizable(KvModel {}, history()));
+        assert!(check_operations(KvModel {}, history()));
+
+        // Client 1 sees the writes in the wrong order.
+        let history = vec![
+            op(0, Op::Put, "x", "1", 0, 10),
+            op(0, Op::Put, "x", "2", 20, 30),
+            op(1, Op::Get, "x", "2", 0, 10),
+            op(1, Op::Get, "x", "1", 20, 30),
+        ];
+        assert!(!check_operations(KvModel {}, history));
+    }
+
+    #[test]
+    fn test_sequential_keys() {
+        let txn = |client_id, op: TxnOp, read: Option<&str>, call| Operation {
+            client_id,
+            input: TxnInput { ops: vec![op] },
+            call,
+            output: Some(TxnOutput {
+                reads: read.into_iter().map(|r| r.as_bytes().to_vec()).collect(),
+                committed: true,
+            }),
+            finish: call + 10,
+        };
+        let r = |k: &str| TxnOp::Read(k.as_bytes().to_vec());
+        let w = |k: &str| TxnOp::Write(k.as_bytes().to_vec(), b"1".to_vec());
+
+        // The clients see the writes to x and y in different orders.
+        let history = vec![
+            txn(0, w("x"), None, 0),
+            txn(1, w("y"), None, 0),
+            txn(2, r("x"), Some("1"), 20),
+            txn(2, r("y"), Some(""), 40),
+            txn(3, r("y"), Some("1"), 20),
+            txn(3, r("x"), Some(""), 40),
+        ];
+        assert!(!check_operations(TxnModel {}, history));
+
+        // The same history, partitioned by key.
+        let history = vec![
+            op(0, Op::Put, "x", "1", 0, 10),
+            op(1, Op::Put, "y", "1", 0, 10),
+            op(2, Op::Get, "x", "1", 20, 30),
+            op(2, Op::Get, "y", "", 40, 50),
+            op(3, Op::Get, "y", "1", 20, 30),
+            op(3, Op::Get, "x", "", 40, 50),
+        ];
+        assert!(!check_operations(KvModel {}, history));
+    }
+
+    #[test]
+    fn test_sequential_pending() {
+        let mut history = vec![op(1, Op::Get, "x", "1", 20, 30)];
+        history.push(Operation::pending(
+            0,
+            KvInput {
+                op: Op::Put,
+                key: "x".to_owned(),
+                value: "1".to_owned(),
+            },
+            0,
+        ));
+        assert!(check_operations(KvModel {}, history));
+
+        let history = vec![
+            Operation::pending(
+                0,
+                KvInput {
+                    op: Op::Put,
+                    key: "x".to_owned(),
+                    value: "1".to_owned(),
+                },
+                0,
+            ),
+            op(1, Op::Get, "x", "", 20, 30),
+        ];
+        assert!(check_operations(KvModel {}, history));
+    }
+}
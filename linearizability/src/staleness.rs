@@ -0,0 +1,294 @@
+//! Checks histories in which reads may return stale values, like reads
+//! served by followers, against the same models as linearizability.
+//!
+//! An operation that leaves the state as it is, like a read, may observe a
+//! version replaced not too long before, every other operation must be
+//! linearizable. The history is checked for linearizability under a model
+//! whose states keep the versions that can still be observed.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Display};
+use std::time::Duration;
+
+use crate::model::{Model, Operation, Operations};
+use crate::{check_partitions, describe_operations, make_entries, CheckOutcome, List};
+
+/// How stale a read may be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Staleness {
+    /// Reads may miss the last `k` versions.
+    Versions(usize),
+    /// Reads may observe a version that was current at most this long
+    /// before they returned. Operation times are taken as nanoseconds, as
+    /// `history::Recorder` records them, and a version counts as current
+    /// until the operation that replaced it returned.
+    Time(Duration),
+}
+
+pub fn check_operations<M: Model>(
+    model: M,
+    history: Operations<M::Input, M::Output>,
+    staleness: Staleness,
+) -> bool {
+    check_operations_timeout(model, history, staleness, Duration::new(0, 0)) == CheckOutcome::Ok
+}
+
+// timeout = 0 means no timeout
+pub fn check_operations_timeout<M: Model>(
+    model: M,
+    history: Operations<M::Input, M::Output>,
+    staleness: Staleness,
+    timeout: Duration,
+) -> CheckOutcome {
+    // The stale model cannot partition histories of its own inputs, so the
+    // history is partitioned beforehand.
+    let partitions = model.partition(history);
+    let model = StaleModel {
+        inner: model,
+        staleness,
+    };
+    check_partitions(model, partitions, timeout, true, |subhistory| {
+        let subhistory: Operations<_, _> = subhistory.into_iter().map(timed).collect();
+        let ops = describe_operations(&subhistory);
+        (ops, List::from_entries(make_entries(subhistory)))
+    })
+    .outcome
+}
+
+fn timed<I: Debug, O: Debug>(op: Operation<I, O>) -> Operation<Timed<I>, O> {
+    let finish = if op.is_pending() { i64::MAX } else { op.finish };
+    Operation {
+        client_id: op.client_id,
+        input: Timed {
+            input: op.input,
+            call: op.call,
+            finish,
+        },
+        call: op.call,
+        output: op.output,
+        finish: op.finish,
+    }
+}
+
+// An input with the times of its operation, which the model needs to tell
+// how stale a version is.
+struct Timed<I> {
+    input: I,
+    call: i64,
+    finish: i64,
+}
+
+impl<I: Debug> Debug for Timed<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.input.fmt(f)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct StaleState<S> {
+    current: S,
+    // The versions that can still be observed, the latest first, with the
+    // time they were replaced.
+    previous: VecDeque<(S, i64)>,
+}
+
+impl<S: Display> Display for StaleState<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.current.fmt(f)
+    }
+}
+
+#[derive(Clone)]
+struct StaleModel<M> {
+    inner: M,
+    staleness: Staleness,
+}
+
+impl<M: Model> StaleModel<M> {
+    fn advance(
+        &self,
+        state: &StaleState<M::State>,
+        next: M::State,
+        input: &Timed<M::Input>,
+    ) -> StaleState<M::State> {
+        let mut state = state.clone();
+        if next != state.current {
+            let replaced = std::mem::replace(&mut state.current, next);
+            state.previous.push_front((replaced, input.finish));
+        }
+        match self.staleness {
+            Staleness::Versions(k) => state.previous.truncate(k),
+            // Operations linearized after this one return after it was
+            // called, so they cannot observe older versions.
+            Staleness::Time(bound) => {
+                let oldest = input.call.saturating_sub(bound.as_nanos() as i64);
+                while matches!(state.previous.back(), Some((_, replaced)) if *replaced < oldest) {
+                    state.previous.pop_back();
+                }
+            }
+        }
+        state
+    }
+
+    fn observable(&self, replaced: i64, input: &Timed<M::Input>) -> bool {
+        match self.staleness {
+            Staleness::Versions(_) => true,
+            Staleness::Time(bound) => {
+                replaced >= input.finish.saturating_sub(bound.as_nanos() as i64)
+            }
+        }
+    }
+}
+
+impl<M: Model> Model for StaleModel<M> {
+    type State = StaleState<M::State>;
+    type Input = Timed<M::Input>;
+    type Output = M::Output;
+
+    fn init(&self) -> Self::State {
+        StaleState {
+            current: self.inner.init(),
+            previous: VecDeque::new(),
+        }
+    }
+
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: &Self::Output,
+    ) -> (bool, Self::State) {
+        let (ok, next) = self.inner.step(&state.current, &input.input, output);
+        if ok {
+            return (true, self.advance(state, next, input));
+        }
+        for (version, replaced) in &state.previous {
+            if !self.observable(*replaced, input) {
+                break;
+            }
+            match self.inner.step(version, &input.input, output) {
+                (true, next) if next == *version => {
+                    return (true, self.advance(state, state.current.clone(), input))
+                }
+                _ => {}
+            }
+        }
+        (false, state.clone())
+    }
+
+    fn step_pending(&self, state: &Self::State, input: &Self::Input) -> (bool, Self::State) {
+        let (ok, next) = self.inner.step_pending(&state.current, &input.input);
+        (ok, self.advance(state, next, input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{check_operations, Staleness};
+    use crate::model::Operation;
+    use crate::models::{KvInput, KvModel, KvOutput, Op};
+
+    fn op(
+        client_id: usize,
+        op: Op,
+        value: &str,
+        call: i64,
+        finish: i64,
+    ) -> Operation<KvInput, KvOutput> {
+        let (input, output) = match op {
+            Op::Get => ("", value),
+            _ => (value, ""),
+        };
+        Operation {
+            client_id,
+            input: KvInput {
+                op,
+                key: "x".to_owned(),
+                value: input.to_owned(),
+            },
+            call,
+            output: Some(KvOutput {
+                value: output.to_owned(),
+            }),
+            finish,
+        }
+    }
+
+    #[test]
+    fn test_versions() {
+        let history = |read: &str| {
+            vec![
+                op(0, Op::Put, "1", 0, 10),
+                op(0, Op::Put, "2", 20, 30),
+                op(1, Op::Get, read, 40, 50),
+                op(1, Op::Get, "2", 60, 70),
+            ]
+        };
+        assert!(check_operations(
+            KvModel {},
+            history("2"),
+            Staleness::Versions(0)
+        ));
+        assert!(!check_operations(
+            KvModel {},
+            history("1"),
+            Staleness::Versions(0)
+        ));
+        assert!(check_operations(
+            KvModel {},
+            history("1"),
+            Staleness::Versions(1)
+        ));
+        assert!(!check_operations(
+            KvModel {},
+            history(""),
+            Staleness::Versions(1)
+        ));
+        assert!(check_operations(
+            KvModel {},
+            history(""),
+            Staleness::Versions(2)
+        ));
+
+        // Only reads may be stale.
+        let mut appended = history("2");
+        appended.push(op(1, Op::Append, "3", 80, 90));
+        appended.push(op(1, Op::Get, "23", 100, 110));
+        assert!(check_operations(
+            KvModel {},
+            appended,
+            Staleness::Versions(1)
+        ));
+        let mut appended = history("2");
+        appended.push(op(1, Op::Append, "3", 80, 90));
+        appended.push(op(1, Op::Get, "13", 100, 110));
+        assert!(!check_operations(
+            KvModel {},
+            appended,
+            Staleness::Versions(1)
+        ));
+    }
+
+    #[test]
+    fn test_time() {
+        // "1" is replaced at 30 at the latest, the read returns at 100.
+        let history = || {
+            vec![
+                op(0, Op::Put, "1", 0, 10),
+                op(0, Op::Put, "2", 20, 30),
+                op(1, Op::Get, "1", 90, 100),
+            ]
+        };
+        let stale = |nanos| Staleness::Time(Duration::from_nanos(nanos));
+        assert!(!check_operations(KvModel {}, history(), stale(50)));
+        assert!(check_operations(KvModel {}, history(), stale(70)));
+
+        // A later write makes "1" too old for reads called after it.
+        let mut later = history();
+        later.push(op(0, Op::Put, "3", 200, 210));
+        later.push(op(1, Op::Get, "1", 220, 230));
+        assert!(!check_operations(KvModel {}, later, stale(70)));
+    }
+}
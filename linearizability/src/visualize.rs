@@ -0,0 +1,206 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Write as _};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::result::{CheckOutcome, CheckResult, OperationInfo, PartitionResult};
+
+const ROW_HEIGHT: i64 = 36;
+const BAR_HEIGHT: i64 = 24;
+const TICK_WIDTH: i64 = 36;
+const LEFT_MARGIN: i64 = 90;
+const TOP_MARGIN: i64 = 10;
+// Rough width of a character of the labels, to truncate them.
+const CHAR_WIDTH: i64 = 7;
+
+const STYLE: &str = "
+body { font-family: sans-serif; }
+svg text { font-family: monospace; font-size: 12px; }
+rect.linearized { fill: #b7e1cd; stroke: #57a773; }
+rect.failed { fill: #f4c7c3; stroke: #d9534f; }
+rect.pending { fill: #e0e0e0; stroke: #9e9e9e; }
+rect.indeterminate { stroke-dasharray: 4 2; }
+rect:hover { stroke-width: 2; }
+.states { font-family: monospace; }
+";
+
+/// Renders the per-client timelines of every partition to an HTML page,
+/// failing partitions first, then the ones that timed out.
+///
+/// Operations of the linearization found are numbered in linearization
+/// order, hovering an operation shows the model state after it. Operations
+/// with an unknown outcome run to the end of the timeline.
+pub fn render_html<S: Display>(res: &CheckResult<S>) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(
+        out,
+        "<title>linearizability check</title>\n<style>{}</style>",
+        STYLE
+    )
+    .unwrap();
+    out.push_str("</head>\n<body>\n");
+    let verdict = match res.outcome {
+        CheckOutcome::Ok => "linearizable",
+        CheckOutcome::Unknown => "unknown, the check timed out",
+        CheckOutcome::Illegal => "not linearizable",
+    };
+    writeln!(out, "<h1>History is {}</h1>", verdict).unwrap();
+
+    let mut partitions: Vec<_> = res.partitions.iter().enumerate().collect();
+    partitions.sort_by_key(|(_, p)| std::cmp::Reverse(p.outcome));
+    for (i, p) in partitions {
+        render_partition(&mut out, i, p);
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Writes the page rendered by `render_html` to `path`.
+pub fn write_html<S: Display, P: AsRef<Path>>(res: &CheckResult<S>, path: P) -> io::Result<()> {
+    fs::write(path, render_html(res))
+}
+
+fn render_partition<S: Display>(out: &mut String, index: usize, p: &PartitionResult<S>) {
+    match p.outcome {
+        CheckOutcome::Ok => writeln!(
+            out,
+            "<h2>Partition {}: linearizable, {} operations</h2>",
+            index,
+            p.operations.len()
+        )
+        .unwrap(),
+        CheckOutcome::Unknown => writeln!(
+            out,
+            "<h2>Partition {}: timed out, linearized {} of {} operations</h2>",
+            index,
+            p.linearized.len(),
+            p.operations.len()
+        )
+        .unwrap(),
+        CheckOutcome::Illegal => {
+            writeln!(
+                out,
+                "<h2>Partition {}: linearized {} of {} operations</h2>",
+                index,
+                p.linearized.len(),
+                p.operations.len()
+            )
+            .unwrap();
+            let states: Vec<_> = p.states.iter().map(|s| escape(&s.to_string())).collect();
+            writeln!(
+                out,
+                "<p class=\"states\">candidate states: [{}]</p>",
+                states.join(", ")
+            )
+            .unwrap();
+        }
+    }
+
+    // Only the order of call and return times matters, so operations are
+    // laid out by the rank of their times to keep the page readable.
+    let times: BTreeSet<i64> = p
+        .operations
+        .iter()
+        .flat_map(|op| vec![op.call, op.finish])
+        .collect();
+    let rank: HashMap<i64, i64> = times
+        .iter()
+        .enumerate()
+        .map(|(r, t)| (*t, r as i64))
+        .collect();
+    let clients: BTreeSet<usize> = p.operations.iter().map(|op| op.client_id).collect();
+    let row: HashMap<usize, i64> = clients
+        .iter()
+        .enumerate()
+        .map(|(r, c)| (*c, r as i64))
+        .collect();
+    let order: HashMap<usize, usize> = p
+        .linearized
+        .iter()
+        .enumerate()
+        .map(|(k, id)| (*id, k))
+        .collect();
+
+    let width = LEFT_MARGIN + (times.len() as i64 + 1) * TICK_WIDTH;
+    let height = TOP_MARGIN * 2 + clients.len() as i64 * ROW_HEIGHT;
+    writeln!(
+        out,
+        "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">",
+        width, height
+    )
+    .unwrap();
+    for (client, r) in &row {
+        writeln!(
+            out,
+            "<text x=\"4\" y=\"{}\">client {}</text>",
+            TOP_MARGIN + r * ROW_HEIGHT + BAR_HEIGHT / 2 + 4,
+            client
+        )
+        .unwrap();
+    }
+    for op in &p.operations {
+        let x = LEFT_MARGIN + rank[&op.call] * TICK_WIDTH;
+        let w = ((rank[&op.finish] - rank[&op.call]) * TICK_WIDTH).max(TICK_WIDTH / 2);
+        let y = TOP_MARGIN + row[&op.client_id] * ROW_HEIGHT;
+        let (class, label, title) = match order.get(&op.id) {
+            Some(k) => (
+                "linearized",
+                format!("#{} {}", k + 1, summary(op)),
+                format!("{}\nstate after: {}", summary(op), p.steps[*k]),
+            ),
+            None if p.outcome == CheckOutcome::Illegal => (
+                "failed",
+                summary(op),
+                format!("{}\nnot linearized", summary(op)),
+            ),
+            None => (
+                "pending",
+                summary(op),
+                format!("{}\nnot linearized yet", summary(op)),
+            ),
+        };
+        let class = if op.pending {
+            format!("{} indeterminate", class)
+        } else {
+            class.to_owned()
+        };
+        writeln!(
+            out,
+            "<g><title>{}</title><rect class=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"3\"/>\
+             <text x=\"{}\" y=\"{}\">{}</text></g>",
+            escape(&title),
+            class,
+            x,
+            y,
+            w,
+            BAR_HEIGHT,
+            x + 3,
+            y + BAR_HEIGHT / 2 + 4,
+            escape(&truncate(&label, (w / CHAR_WIDTH) as usize))
+        )
+        .unwrap();
+    }
+    out.push_str("</svg>\n");
+}
+
+fn summary(op: &OperationInfo) -> String {
+    format!("{} -> {}", op.input, op.output)
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    if s.chars().count() <= max_chars {
+        return s.to_owned();
+    }
+    let mut t: String = s.chars().take(max_chars.saturating_sub(1)).collect();
+    t.push('…');
+    t
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
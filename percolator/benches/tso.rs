@@ -0,0 +1,62 @@
+use std::thread;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use futures::executor::block_on;
+
+use labrpc::{Network, ServerBuilder};
+use percolator::client::Client;
+use percolator::msg::TimestampRequest;
+use percolator::service::{add_tso_service, TSOClient, TransactionClient};
+use percolator::TimestampOracle;
+
+// How many threads ask for a timestamp at the same time.
+const CONCURRENCY: usize = 8;
+
+fn bench_suit() -> (Network, TSOClient, Client) {
+    let net = Network::new();
+    let mut builder = ServerBuilder::new("tso_server".to_owned());
+    add_tso_service(TimestampOracle::default(), &mut builder).unwrap();
+    net.add_server(builder.build());
+
+    let tso_client = TSOClient::new(net.create_client("tso".to_owned()));
+    net.connect("tso", "tso_server");
+    net.enable("tso", true);
+    // never called, the bench only asks for timestamps
+    let txn_client = TransactionClient::new(net.create_client("txn".to_owned()));
+    let client = Client::new(tso_client.clone(), txn_client);
+    (net, tso_client, client)
+}
+
+fn bench_tso(c: &mut Criterion) {
+    let (_net, tso_client, client) = bench_suit();
+
+    c.bench_function("tso", |b| {
+        b.iter(|| {
+            thread::scope(|s| {
+                for _ in 0..CONCURRENCY {
+                    s.spawn(|| {
+                        black_box(block_on(async {
+                            tso_client
+                                .get_timestamp(&TimestampRequest { count: 1 })
+                                .await
+                                .unwrap()
+                        }));
+                    });
+                }
+            });
+        })
+    });
+
+    c.bench_function("tso batched", |b| {
+        b.iter(|| {
+            thread::scope(|s| {
+                for _ in 0..CONCURRENCY {
+                    s.spawn(|| black_box(client.get_timestamp().unwrap()));
+                }
+            });
+        })
+    });
+}
+
+criterion_group!(benches, bench_tso);
+criterion_main!(benches);
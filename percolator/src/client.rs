@@ -1,9 +1,18 @@
-use futures::executor::block_on;
-use futures::Future;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot;
+use futures::{future, Future};
 use labrpc::*;
+use rand::Rng;
 use tokio::runtime::Runtime;
-use tokio::time::{sleep, Duration};
 
+use crate::errors;
 use crate::msg::*;
 use crate::service::{TSOClient, TransactionClient};
 
@@ -17,6 +26,11 @@ use crate::service::{TSOClient, TransactionClient};
 const BACKOFF_TIME_MS: u64 = 100;
 // RETRY_TIMES is the maximum number of times a client attempts to send a request.
 const RETRY_TIMES: usize = 3;
+// REQUEST_TIMEOUT is how long a client waits for a peer of a replicated
+// server before it tries the next one, and FAILOVER_TIMEOUT how long it
+// tries in total, long enough for the peers to elect a new leader.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(1000);
+const FAILOVER_TIMEOUT: Duration = Duration::from_millis(5000);
 
 lazy_static::lazy_static! {
     static ref RT: Runtime = Runtime::new().unwrap();
@@ -25,7 +39,148 @@ lazy_static::lazy_static! {
 #[derive(Clone)]
 struct Txn {
     ts: u64,
-    writes: Vec<Write>,
+    // The last write to each key, the first key is the primary.
+    writes: BTreeMap<Vec<u8>, Write>,
+}
+
+// The peers of a server replicated by a raft group, and the one that last
+// led it. A single peer is a server of its own.
+#[derive(Clone)]
+struct Peers<C> {
+    peers: Vec<C>,
+    leader: Arc<AtomicUsize>,
+}
+
+// A response of a peer that may not lead its group.
+trait Reply {
+    fn wrong_leader(&self) -> bool;
+}
+
+macro_rules! impl_reply {
+    ($($resp:ty),*) => {
+        $(impl Reply for $resp {
+            fn wrong_leader(&self) -> bool {
+                self.wrong_leader
+            }
+        })*
+    };
+}
+
+impl_reply!(
+    TimestampResponse,
+    GetResponse,
+    ScanResponse,
+    PrewriteResponse,
+    CommitResponse,
+    CheckTxnStatusResponse,
+    ResolveLockResponse,
+    GcResponse
+);
+
+impl<C> Peers<C> {
+    fn new(peers: Vec<C>) -> Peers<C> {
+        Peers {
+            peers,
+            leader: Arc::default(),
+        }
+    }
+
+    // Sends the request to the peers in turn until one of them leads.
+    async fn call<T: Reply, F>(&self, request: impl Fn(&C) -> F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let n = self.peers.len();
+        let deadline = Instant::now() + FAILOVER_TIMEOUT;
+        let mut peer = self.leader.load(Ordering::Relaxed);
+        loop {
+            match request(&self.peers[peer]).await {
+                Ok(resp) if !resp.wrong_leader() => {
+                    self.leader.store(peer, Ordering::Relaxed);
+                    return Ok(resp);
+                }
+                // a single server retries on its own
+                Err(e) if n == 1 || Instant::now() >= deadline => return Err(e),
+                Ok(_) if Instant::now() >= deadline => {
+                    return Err(Error::Other("no leader".to_owned()))
+                }
+                _ => {}
+            }
+            peer = (peer + 1) % n;
+            if peer == self.leader.load(Ordering::Relaxed) {
+                // none of the peers leads, wait for an election
+                futures_timer::Delay::new(Duration::from_millis(BACKOFF_TIME_MS)).await;
+            }
+        }
+    }
+}
+
+impl Peers<TransactionClient> {
+    // A storage node whose requests are retried like `retry_options`, a
+    // group fails over to other peers instead.
+    fn with_retry(&self) -> Peers<TransactionClient> {
+        match self.peers.as_slice() {
+            [peer] => Peers {
+                peers: vec![peer.with_options(retry_options())],
+                leader: self.leader.clone(),
+            },
+            _ => self.clone(),
+        }
+    }
+}
+
+// Hands out timestamps to every clone of a client. The calls made while a
+// request is on the way are served by the next request, which asks for as
+// many timestamps at once.
+struct Tso {
+    peers: Peers<TSOClient>,
+    batch: Mutex<Batch>,
+}
+
+#[derive(Default)]
+struct Batch {
+    waiting: Vec<oneshot::Sender<Result<u64>>>,
+    in_flight: bool,
+}
+
+impl Tso {
+    async fn get_timestamp(self: &Arc<Self>) -> Result<u64> {
+        let (tx, rx) = oneshot::channel();
+        let idle = {
+            let mut batch = self.batch.lock().unwrap();
+            batch.waiting.push(tx);
+            !mem::replace(&mut batch.in_flight, true)
+        };
+        // the first caller sends the batch itself, the calls that arrive
+        // meanwhile go in the next one
+        if idle && self.dispatch().await {
+            RT.spawn(self.clone().dispatch_all());
+        }
+        rx.await.map_err(Error::Recv)?
+    }
+
+    // Requests timestamps for the waiting calls, returns whether more calls
+    // arrived meanwhile.
+    async fn dispatch(&self) -> bool {
+        let waiting = mem::take(&mut self.batch.lock().unwrap().waiting);
+        let res = self.request(waiting.len() as u32).await;
+        for (i, tx) in waiting.into_iter().enumerate() {
+            let _ = tx.send(res.clone().map(|ts| ts + i as u64));
+        }
+        let mut batch = self.batch.lock().unwrap();
+        batch.in_flight = !batch.waiting.is_empty();
+        batch.in_flight
+    }
+
+    async fn dispatch_all(self: Arc<Self>) {
+        while self.dispatch().await {}
+    }
+
+    async fn request(&self, count: u32) -> Result<u64> {
+        let req = TimestampRequest { count };
+        let resp = self.peers.call(|c| c.get_timestamp(&req)).await?;
+        Ok(resp.ts)
+    }
 }
 
 /// Client mainly has two purposes:
@@ -34,8 +189,10 @@ struct Txn {
 #[derive(Clone)]
 pub struct Client {
     // Your definitions here.
-    tso_client: TSOClient,
-    txn_client: TransactionClient,
+    tso: Arc<Tso>,
+    // The peers of each storage node, keys are spread over the nodes by
+    // hash.
+    nodes: Vec<Peers<TransactionClient>>,
     txn: Option<Txn>,
 }
 
@@ -43,24 +200,113 @@ impl Client {
     /// Creates a new Client.
     pub fn new(tso_client: TSOClient, txn_client: TransactionClient) -> Client {
         // Your code here.
+        Client::with_nodes(tso_client, vec![txn_client])
+    }
+
+    /// Creates a Client of a store whose keys are spread over several
+    /// storage nodes. Every client of the store must list them in the same
+    /// order.
+    pub fn with_nodes(tso_client: TSOClient, txn_clients: Vec<TransactionClient>) -> Client {
+        Client::with_tso_peers(vec![tso_client], txn_clients)
+    }
+
+    /// Creates a Client like `with_nodes`, of a TSO replicated by a raft
+    /// group of peers.
+    pub fn with_tso_peers(
+        tso_clients: Vec<TSOClient>,
+        txn_clients: Vec<TransactionClient>,
+    ) -> Client {
+        let groups = txn_clients.into_iter().map(|c| vec![c]).collect();
+        Client::with_groups(tso_clients, groups)
+    }
+
+    /// Creates a Client like `with_tso_peers`, of storage nodes replicated
+    /// by raft groups, given the peers of each node.
+    pub fn with_groups(tso_clients: Vec<TSOClient>, groups: Vec<Vec<TransactionClient>>) -> Client {
+        assert!(!tso_clients.is_empty(), "no tso peers");
+        assert!(!groups.is_empty(), "no storage nodes");
+        assert!(groups.iter().all(|g| !g.is_empty()), "no storage peers");
+        // a single oracle is retried, the peers of a replicated one in turn
+        let options = match tso_clients.len() {
+            1 => retry_options(),
+            _ => CallOptions::new().timeout(REQUEST_TIMEOUT),
+        };
+        let tso = Tso {
+            peers: Peers::new(
+                tso_clients
+                    .iter()
+                    .map(|c| c.with_options(options))
+                    .collect(),
+            ),
+            batch: Mutex::default(),
+        };
+        let nodes = groups
+            .into_iter()
+            .map(|peers| match peers.len() {
+                1 => Peers::new(peers),
+                _ => Peers::new(
+                    peers
+                        .iter()
+                        .map(|c| c.with_options(CallOptions::new().timeout(REQUEST_TIMEOUT)))
+                        .collect(),
+                ),
+            })
+            .collect();
         Client {
-            tso_client,
-            txn_client,
+            tso: Arc::new(tso),
+            nodes,
             txn: None,
         }
     }
 
-    /// Gets a timestamp from a TSO.
+    // The storage node that holds the key.
+    fn node(&self, key: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.nodes.len() as u64) as usize
+    }
+
+    // Groups the items by the node of their key, in node order.
+    fn group_by_node<T>(&self, items: Vec<T>, key: impl Fn(&T) -> &[u8]) -> Vec<(usize, Vec<T>)> {
+        let mut groups = BTreeMap::new();
+        for item in items {
+            let node = self.node(key(&item));
+            groups.entry(node).or_insert_with(Vec::new).push(item);
+        }
+        groups.into_iter().collect()
+    }
+
+    /// Gets a timestamp from a TSO, in one request with the timestamps
+    /// asked for by clones of the client meanwhile.
     pub fn get_timestamp(&self) -> Result<u64> {
         // Your code here.
-        RT.block_on(auto_retry(|| self.real_get_timestamp()))
+        RT.block_on(self.real_get_timestamp())
     }
 
     async fn real_get_timestamp(&self) -> Result<u64> {
-        self.tso_client
-            .get_timestamp(&TimestampRequest {})
-            .await
-            .map(|resp| resp.ts)
+        self.tso.get_timestamp().await
+    }
+
+    /// Removes the versions no read at or after `safe_point` can see from
+    /// every storage node, and tells how many records were removed.
+    /// `safe_point` must be below the start timestamp of every running
+    /// transaction.
+    pub fn gc(&self, safe_point: u64) -> Result<GcResponse> {
+        RT.block_on(async {
+            let req = GcRequest {
+                safe_point,
+                request_id: request_id(),
+            };
+            let gcs = self.nodes.iter().map(|node| node.call(|c| c.gc(&req)));
+            let resps = future::join_all(gcs).await;
+            let mut total = GcResponse::default();
+            for resp in resps {
+                let resp = resp?;
+                total.writes += resp.writes;
+                total.data += resp.data;
+            }
+            Ok(total)
+        })
     }
 
     /// Begins a new transaction.
@@ -72,11 +318,12 @@ impl Client {
         );
         self.txn = Some(Txn {
             ts: self.get_timestamp().unwrap(),
-            writes: vec![],
+            writes: BTreeMap::new(),
         });
     }
 
-    /// Gets the value for a given key.
+    /// Gets the value for a given key, as written earlier in the transaction
+    /// if it was, an empty value if it is missing or deleted.
     pub fn get(&self, key: Vec<u8>) -> Result<Vec<u8>> {
         // Your code here.
         RT.block_on(self.real_get(key))
@@ -84,64 +331,239 @@ impl Client {
 
     async fn real_get(&self, key: Vec<u8>) -> Result<Vec<u8>> {
         assert!(self.txn.is_some(), "must begin a txn when get");
-        self.txn_client
-            .get(&GetRequest {
-                key,
-                start_ts: self.txn.as_ref().unwrap().ts,
-            })
-            .await
-            .map(|resp| resp.value)
+        let txn = self.txn.as_ref().unwrap();
+        if let Some(w) = txn.writes.get(&key) {
+            return Ok(match w.op() {
+                Op::Put => w.value.clone(),
+                Op::Delete => vec![],
+            });
+        }
+        let req = GetRequest {
+            key,
+            start_ts: txn.ts,
+        };
+        loop {
+            let node = &self.nodes[self.node(&req.key)];
+            let resp = node.call(|c| c.get(&req)).await?;
+            match resp.error {
+                None => return Ok(resp.value),
+                Some(e) => self.resolve_read_error(e).await?,
+            }
+        }
+    }
+
+    /// Reads the keys in `[start, end)` as `get` would, or from `start` on if
+    /// `end` is empty, leaving out missing and deleted keys. At most `limit`
+    /// keys are read, all of them if it is 0, in descending order if
+    /// `reverse`. The next page starts right after the last key read, or
+    /// ends at it if `reverse`.
+    pub fn scan(
+        &self,
+        start: Vec<u8>,
+        end: Vec<u8>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        RT.block_on(self.real_scan(start, end, limit, reverse))
+    }
+
+    async fn real_scan(
+        &self,
+        start: Vec<u8>,
+        end: Vec<u8>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        assert!(self.txn.is_some(), "must begin a txn when scan");
+        let txn = self.txn.as_ref().unwrap();
+        let buffered: Vec<Write> = txn
+            .writes
+            .range(start.clone()..)
+            .take_while(|(key, _)| end.is_empty() || **key < end)
+            .map(|(_, w)| w.clone())
+            .collect();
+
+        // the keys are spread over every node, each of them has to read
+        // enough to make up for the keys deleted in the buffer
+        let deleted = buffered.iter().filter(|w| w.op() == Op::Delete).count();
+        let req = ScanRequest {
+            start,
+            end,
+            limit: if limit == 0 {
+                0
+            } else {
+                (limit + deleted) as u32
+            },
+            reverse,
+            start_ts: txn.ts,
+        };
+        let scans = (0..self.nodes.len()).map(|node| self.scan_node(node, &req));
+        let mut pairs = BTreeMap::new();
+        for res in future::join_all(scans).await {
+            pairs.extend(res?.into_iter().map(|p| (p.key, p.value)));
+        }
+        for w in buffered {
+            match w.op() {
+                Op::Put => pairs.insert(w.key, w.value),
+                Op::Delete => pairs.remove(&w.key),
+            };
+        }
+
+        let mut pairs: Vec<_> = pairs.into_iter().collect();
+        if reverse {
+            pairs.reverse();
+        }
+        if limit != 0 {
+            pairs.truncate(limit);
+        }
+        Ok(pairs)
+    }
+
+    async fn scan_node(&self, node: usize, req: &ScanRequest) -> Result<Vec<KvPair>> {
+        loop {
+            let resp = self.nodes[node].call(|c| c.scan(req)).await?;
+            match resp.error {
+                None => return Ok(resp.pairs),
+                Some(e) => self.resolve_read_error(e).await?,
+            }
+        }
+    }
+
+    // Resolves the lock a read gave up on so that it can be retried, any
+    // other error fails the read.
+    async fn resolve_read_error(&self, e: KeyError) -> Result<()> {
+        let locked = match e.error {
+            Some(key_error::Error::Locked(locked)) => locked,
+            error => return Err(Error::Other(format!("{:?}", KeyError { error }))),
+        };
+        // the lock is gone either way, unless the request failed
+        match self.real_resolve_lock(locked).await {
+            Err(errors::Error::Rpc(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Commits or rolls back a lock of another transaction as its primary
+    /// was, unless that transaction is still running and holds the lock.
+    pub fn resolve_lock(&self, locked: KeyIsLocked) -> errors::Result<()> {
+        RT.block_on(self.real_resolve_lock(locked))
+    }
+
+    async fn real_resolve_lock(&self, locked: KeyIsLocked) -> errors::Result<()> {
+        info!("resolving lock on {:?} at {}", locked.key, locked.lock_ts);
+        let req = CheckTxnStatusRequest {
+            primary: locked.primary,
+            lock_ts: locked.lock_ts,
+            request_id: request_id(),
+        };
+        let node = &self.nodes[self.node(&req.primary)];
+        let status = node.call(|c| c.check_txn_status(&req)).await?;
+        if status.locked {
+            return Ok(());
+        }
+        let req = ResolveLockRequest {
+            key: locked.key,
+            start_ts: locked.lock_ts,
+            commit_ts: status.commit_ts,
+            request_id: request_id(),
+        };
+        let node = &self.nodes[self.node(&req.key)];
+        let resp = node.call(|c| c.resolve_lock(&req)).await?;
+        resp.error.map_or(Ok(()), |e| Err(e.into()))
     }
 
     /// Sets keys in a buffer until commit time.
     pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
         // Your code here.
         assert!(self.txn.is_some(), "must begin a txn when set");
-        self.txn.as_mut().unwrap().writes.push(Write { key, value });
+        self.buffer(Write {
+            key,
+            value,
+            op: Op::Put as i32,
+        });
     }
 
-    /// Commits a transaction.
+    /// Deletes a key at commit time.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        assert!(self.txn.is_some(), "must begin a txn when delete");
+        self.buffer(Write {
+            key,
+            value: vec![],
+            op: Op::Delete as i32,
+        });
+    }
+
+    fn buffer(&mut self, w: Write) {
+        let writes = &mut self.txn.as_mut().unwrap().writes;
+        writes.insert(w.key.clone(), w);
+    }
+
+    /// Commits a transaction, the client can begin another one afterwards.
+    /// Returns false if the transaction aborted, and an error if it may or
+    /// may not have committed.
     pub fn commit(&mut self) -> Result<bool> {
         // Your code here.
-        RT.block_on(self.real_commit())
+        match self.try_commit() {
+            Ok(()) => Ok(true),
+            Err(errors::Error::Rpc(e)) => Err(e),
+            Err(_) => Ok(false),
+        }
     }
 
-    async fn real_commit(&mut self) -> Result<bool> {
+    /// Commits a transaction like `commit`, telling why it aborted.
+    pub fn try_commit(&mut self) -> errors::Result<()> {
+        let res = RT.block_on(self.real_commit());
+        self.txn = None;
+        res
+    }
+
+    async fn real_commit(&mut self) -> errors::Result<()> {
         assert!(self.txn.is_some(), "must begin a txn when commit");
         let Txn { ts, writes } = self.txn.as_ref().unwrap().to_owned();
         let start_ts = ts;
+        let writes: Vec<Write> = writes.into_values().collect();
 
         if writes.is_empty() {
-            return Ok(true);
+            return Ok(());
         }
 
         // designate the primary and secondaries
-        let primary = writes.first().unwrap().to_owned();
-        let secondaries = writes[1..].to_owned();
-
-        // prewrite the primary
-        let primary_pw_req = PrewriteRequest {
-            ts: start_ts,
-            w: Some(primary.clone()),
-            primary: Some(primary.clone()),
-        };
-        if !self.txn_client.prewrite(&primary_pw_req).await.unwrap().ok {
-            return Ok(false);
-        }
+        let primary = writes.first().unwrap().key.clone();
+        let secondaries: Vec<_> = writes[1..].iter().map(|w| w.key.clone()).collect();
 
-        // prewrite the secondaries
-        for secondary in secondaries.iter() {
-            let pw_req = PrewriteRequest {
+        // prewrite the keys of each node in one request, all at once
+        let groups = self.group_by_node(writes, |w| &w.key);
+        let prewrites = groups.into_iter().map(|(node, mutations)| {
+            let node = &self.nodes[node];
+            let req = PrewriteRequest {
                 ts: start_ts,
-                w: Some(secondary.clone()),
-                primary: Some(primary.clone()),
+                mutations,
+                primary: primary.clone(),
+                request_id: request_id(),
             };
-            if !self.txn_client.prewrite(&pw_req).await.unwrap().ok {
-                return Ok(false);
-            }
+            async move { node.call(|c| c.prewrite(&req)).await }
+        });
+        let mut error = None;
+        for res in future::join_all(prewrites).await {
+            let e = match res {
+                Ok(resp) => resp.errors.into_iter().next().map(errors::Error::from),
+                Err(e) => Some(e.into()),
+            };
+            error = error.or(e);
         }
-
-        let commit_ts = self.real_get_timestamp().await.unwrap();
+        let commit_ts = match error {
+            None => self.real_get_timestamp().await.map_err(errors::Error::from),
+            Some(e) => Err(e),
+        };
+        let commit_ts = match commit_ts {
+            Ok(commit_ts) => commit_ts,
+            Err(e) => {
+                // the primary is not committed yet, so the txn never will be,
+                // its locks need not expire before others can resolve them
+                let _ = self.rollback(&primary, start_ts).await;
+                return Err(e);
+            }
+        };
 
         // commit the primary
         info!(
@@ -151,54 +573,84 @@ impl Client {
 
         let primary_commit_req = CommitRequest {
             is_primary: true,
-            commit_key: primary.key,
+            keys: vec![primary.clone()],
             start_ts,
             commit_ts,
+            request_id: request_id(),
         };
-        // only if success and resp.ok we proceed
-        let r = auto_retry(|| self.txn_client.commit(&primary_commit_req)).await;
+        // only if the primary is committed we proceed
+        let committer = self.nodes[self.node(&primary)].with_retry();
+        let r = committer.call(|c| c.commit(&primary_commit_req)).await;
         info!("txn_client.commit response {:?}", &r);
         match r {
-            Ok(CommitResponse { ok: false }) => return Ok(false),
-            Ok(_) => {}
-            Err(Error::Other(reason)) if reason == "reqhook" => return Ok(false),
-            Err(e) => return Err(e),
+            Ok(CommitResponse { error: None, .. }) => {}
+            Ok(CommitResponse { error: Some(e), .. }) => return Err(e.into()),
+            // the request never reached the server, so the txn cannot
+            // commit anymore
+            Err(Error::Rejected(_)) => {
+                self.rollback(&primary, start_ts).await?;
+                return Err(errors::Error::AlreadyRolledBack(AlreadyRolledBack {
+                    key: primary,
+                    start_ts,
+                }));
+            }
+            Err(e) => return Err(e.into()),
         }
 
         info!("primary committed");
 
-        // commit the secondaries
-        for secondary in secondaries.into_iter() {
-            info!(
-                "commit secondary, start_ts: {:?}, commit_rs: {:?}",
-                start_ts, commit_ts
-            );
-            let secondary_commit_req = CommitRequest {
+        // the txn is committed, the secondaries of each node are committed
+        // in one request, all at once in the background
+        info!(
+            "commit secondaries, start_ts: {:?}, commit_ts: {:?}",
+            start_ts, commit_ts
+        );
+        let groups = self.group_by_node(secondaries, |key| key);
+        let commits = groups.into_iter().map(|(node, keys)| {
+            let committer = self.nodes[node].with_retry();
+            let req = CommitRequest {
                 is_primary: false,
-                commit_key: secondary.key,
+                keys,
                 start_ts,
                 commit_ts,
+                request_id: request_id(),
             };
-            // secondaries commit, even if dropped we see it as success
-            let _ = auto_retry(|| self.txn_client.commit(&secondary_commit_req)).await;
-            info!("secondary committed");
-        }
-        info!("all secondary committed");
+            async move {
+                // secondaries commit, even if dropped readers roll them
+                // forward from the primary
+                let _ = committer.call(|c| c.commit(&req)).await;
+            }
+        });
+        RT.spawn(future::join_all(commits));
 
-        Ok(true)
+        Ok(())
     }
-}
 
-async fn auto_retry<T, F>(f: impl Fn() -> F) -> Result<T>
-where
-    F: Future<Output = Result<T>>,
-{
-    for i in 0..RETRY_TIMES {
-        let r = f().await;
-        if r.is_ok() {
-            return r;
-        }
-        sleep(Duration::from_millis(BACKOFF_TIME_MS << i)).await;
+    // Rolls back the txn started at start_ts through its primary, unless it
+    // committed.
+    async fn rollback(&self, primary: &[u8], start_ts: u64) -> errors::Result<()> {
+        info!("rollback primary, start_ts: {:?}", start_ts);
+        let req = ResolveLockRequest {
+            key: primary.to_vec(),
+            start_ts,
+            commit_ts: 0,
+            request_id: request_id(),
+        };
+        let node = self.nodes[self.node(primary)].with_retry();
+        let resp = node.call(|c| c.resolve_lock(&req)).await?;
+        resp.error.map_or(Ok(()), |e| Err(e.into()))
     }
-    f().await
+}
+
+// A new id of a write request, sent along whenever the request is sent
+// again.
+fn request_id() -> u64 {
+    rand::thread_rng().gen_range(1, u64::MAX)
+}
+
+fn retry_options() -> CallOptions {
+    CallOptions::new().retry(RetryPolicy::new(
+        RETRY_TIMES,
+        Duration::from_millis(BACKOFF_TIME_MS),
+    ))
 }
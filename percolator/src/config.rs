@@ -0,0 +1,287 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use labrpc::{Network, ServerBuilder};
+use raft::proto::raftpb::{add_raft_service, RaftClient};
+use raft::raft::persister::{Persister, SimplePersister};
+
+use crate::{raftstore, tso};
+
+static ID: AtomicUsize = AtomicUsize::new(500_000);
+
+fn uniqstring() -> String {
+    format!("{}", ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// A server replicated by a raft group, as seen by the tester.
+pub trait Replica: Clone + Send + 'static {
+    fn is_leader(&self) -> bool;
+    fn kill(&self);
+}
+
+impl Replica for tso::Node {
+    fn is_leader(&self) -> bool {
+        tso::Node::is_leader(self)
+    }
+
+    fn kill(&self) {
+        tso::Node::kill(self)
+    }
+}
+
+impl Replica for raftstore::Node {
+    fn is_leader(&self) -> bool {
+        raftstore::Node::is_leader(self)
+    }
+
+    fn kill(&self) {
+        raftstore::Node::kill(self)
+    }
+}
+
+// Starts peer `me` of a group, adding its services to the builder.
+type StartReplica<R> =
+    dyn Fn(Vec<RaftClient>, usize, Box<dyn Persister>, &mut ServerBuilder) -> R + Send + Sync;
+
+struct Servers<R> {
+    replicas: Vec<Option<R>>,
+    saved: Vec<Arc<SimplePersister>>,
+    endnames: Vec<Vec<String>>,
+}
+
+/// A raft group on a labrpc network, whose servers are named `name0`,
+/// `name1`, ... and can be partitioned, shut down and restarted like the
+/// kvraft tester does.
+pub struct Group<R: Replica> {
+    pub net: Network,
+    pub n: usize,
+    name: String,
+    servers: Mutex<Servers<R>>,
+    start: Box<StartReplica<R>>,
+}
+
+impl<R: Replica> Group<R> {
+    pub fn new(
+        net: Network,
+        name: &str,
+        n: usize,
+        start: impl Fn(Vec<RaftClient>, usize, Box<dyn Persister>, &mut ServerBuilder) -> R
+            + Send
+            + Sync
+            + 'static,
+    ) -> Group<R> {
+        let servers = Servers {
+            replicas: vec![None; n],
+            saved: (0..n).map(|_| Arc::new(SimplePersister::new())).collect(),
+            endnames: vec![vec![String::new(); n]; n],
+        };
+        let group = Group {
+            net,
+            n,
+            name: name.to_owned(),
+            servers: Mutex::new(servers),
+            start: Box::new(start),
+        };
+        for i in 0..n {
+            group.start_server(i);
+        }
+        group.connect_all();
+        group
+    }
+
+    pub fn server_name(&self, i: usize) -> String {
+        format!("{}{}", self.name, i)
+    }
+
+    pub fn all(&self) -> Vec<usize> {
+        (0..self.n).collect()
+    }
+
+    /// Creates ends named `client-name0`, `client-name1`, ... connected to
+    /// each server.
+    pub fn clients(&self, client: &str) -> Vec<labrpc::Client> {
+        (0..self.n)
+            .map(|j| {
+                let name = format!("{}-{}", client, self.server_name(j));
+                let cli = self.net.create_client(name.clone());
+                self.net.connect(&name, &self.server_name(j));
+                self.net.enable(&name, true);
+                cli
+            })
+            .collect()
+    }
+
+    // Attaches server i to the servers listed in `to`.
+    fn connect(&self, i: usize, to: &[usize], servers: &Servers<R>) {
+        for j in to {
+            self.net.enable(&servers.endnames[i][*j], true);
+            self.net.enable(&servers.endnames[*j][i], true);
+        }
+    }
+
+    // Detaches server i from the servers listed in `from`.
+    fn disconnect(&self, i: usize, from: &[usize], servers: &Servers<R>) {
+        for j in from {
+            self.net.enable(&servers.endnames[i][*j], false);
+            self.net.enable(&servers.endnames[*j][i], false);
+        }
+    }
+
+    pub fn connect_all(&self) {
+        let servers = self.servers.lock().unwrap();
+        for i in 0..self.n {
+            self.connect(i, &self.all(), &servers);
+        }
+    }
+
+    /// Sets up 2 partitions with connectivity between servers in each
+    /// partition.
+    pub fn partition(&self, p1: &[usize], p2: &[usize]) {
+        let servers = self.servers.lock().unwrap();
+        for i in p1 {
+            self.disconnect(*i, p2, &servers);
+            self.connect(*i, p1, &servers);
+        }
+        for i in p2 {
+            self.disconnect(*i, p1, &servers);
+            self.connect(*i, p2, &servers);
+        }
+    }
+
+    /// Shuts server i down, keeping what it persisted for a restart.
+    pub fn shutdown_server(&self, i: usize) {
+        let mut servers = self.servers.lock().unwrap();
+        self.disconnect(i, &self.all(), &servers);
+        self.net.delete_server(&self.server_name(i));
+
+        // a fresh persister, in case the old instance still writes to it
+        let p = SimplePersister::new();
+        p.save_state_and_snapshot(servers.saved[i].raft_state(), servers.saved[i].snapshot());
+        servers.saved[i] = Arc::new(p);
+
+        if let Some(replica) = servers.replicas[i].take() {
+            replica.kill();
+        }
+    }
+
+    /// Starts server i from what it persisted, shut it down first to
+    /// restart it.
+    pub fn start_server(&self, i: usize) {
+        let mut servers = self.servers.lock().unwrap();
+        servers.endnames[i] = (0..self.n).map(|_| uniqstring()).collect();
+        let ends = servers.endnames[i]
+            .iter()
+            .enumerate()
+            .map(|(j, name)| {
+                let cli = self.net.create_client(name.clone());
+                self.net.connect(name, &self.server_name(j));
+                RaftClient::new(cli)
+            })
+            .collect();
+
+        let p = SimplePersister::new();
+        p.save_state_and_snapshot(servers.saved[i].raft_state(), servers.saved[i].snapshot());
+        let p = Arc::new(p);
+        servers.saved[i] = p.clone();
+
+        let mut builder = ServerBuilder::new(self.server_name(i));
+        let replica = (self.start)(ends, i, Box::new(p), &mut builder);
+        servers.replicas[i] = Some(replica);
+        self.net.add_server(builder.build());
+    }
+
+    /// Restarts server i and reconnects it to the running servers.
+    pub fn restart_server(&self, i: usize) {
+        self.start_server(i);
+        let servers = self.servers.lock().unwrap();
+        let running: Vec<_> = (0..self.n)
+            .filter(|j| servers.replicas[*j].is_some())
+            .collect();
+        self.connect(i, &running, &servers);
+    }
+
+    pub fn leader(&self) -> Option<usize> {
+        let servers = self.servers.lock().unwrap();
+        servers
+            .replicas
+            .iter()
+            .position(|r| r.as_ref().is_some_and(|r| r.is_leader()))
+    }
+
+    /// Whether server i believes it is the leader, a deposed one may still.
+    pub fn is_leader(&self, i: usize) -> bool {
+        let servers = self.servers.lock().unwrap();
+        servers.replicas[i].as_ref().is_some_and(|r| r.is_leader())
+    }
+
+    /// The largest snapshot persisted by a server.
+    pub fn snapshot_size(&self) -> usize {
+        let servers = self.servers.lock().unwrap();
+        servers
+            .saved
+            .iter()
+            .map(|p| p.snapshot().len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The largest raft state persisted by a server.
+    pub fn log_size(&self) -> usize {
+        let servers = self.servers.lock().unwrap();
+        servers
+            .saved
+            .iter()
+            .map(|p| p.raft_state().len())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl<R: Replica> Drop for Group<R> {
+    fn drop(&mut self) {
+        let servers = self.servers.lock().unwrap();
+        for replica in servers.replicas.iter().flatten() {
+            replica.kill();
+        }
+    }
+}
+
+/// Starts a replicated TSO on the network, with its servers named `tso0`,
+/// `tso1`, ...
+pub fn tso_group(net: &Network, n: usize, maxraftstate: Option<usize>) -> Group<tso::Node> {
+    Group::new(
+        net.clone(),
+        "tso",
+        n,
+        move |ends, me, persister, builder| {
+            let server = tso::TsoServer::new(ends, me, persister, maxraftstate);
+            let rf = server.rf.clone();
+            let node = tso::Node::new(server);
+            add_raft_service(rf, builder).unwrap();
+            crate::service::add_tso_service(node.clone(), builder).unwrap();
+            node
+        },
+    )
+}
+
+/// Starts a replicated storage node on the network, with its servers named
+/// `store0`, `store1`, ...
+pub fn storage_group(
+    net: &Network,
+    n: usize,
+    maxraftstate: Option<usize>,
+) -> Group<raftstore::Node> {
+    Group::new(
+        net.clone(),
+        "store",
+        n,
+        move |ends, me, persister, builder| {
+            let server = raftstore::StoreServer::new(ends, me, persister, maxraftstate);
+            let rf = server.rf.clone();
+            let node = raftstore::Node::new(server);
+            add_raft_service(rf, builder).unwrap();
+            crate::service::add_transaction_service(node.clone(), builder).unwrap();
+            node
+        },
+    )
+}
@@ -0,0 +1,59 @@
+use std::{error, fmt, result};
+
+use crate::msg::{
+    key_error, AlreadyRolledBack, Committed, KeyError, KeyIsLocked, TxnNotFound, WriteConflict,
+};
+
+/// Why a transaction failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// A request failed, the transaction may or may not have committed.
+    Rpc(labrpc::Error),
+    /// Another transaction committed a key after this one started, it may
+    /// succeed if retried with a new timestamp.
+    WriteConflict(WriteConflict),
+    /// Another transaction holds a lock on a key, which has to be resolved
+    /// before retrying.
+    KeyIsLocked(KeyIsLocked),
+    TxnNotFound(TxnNotFound),
+    /// The transaction was rolled back, e.g. by readers after its locks
+    /// expired, or because its primary could not be committed.
+    AlreadyRolledBack(AlreadyRolledBack),
+    Committed(Committed),
+}
+
+impl From<labrpc::Error> for Error {
+    fn from(e: labrpc::Error) -> Error {
+        Error::Rpc(e)
+    }
+}
+
+impl From<KeyError> for Error {
+    fn from(e: KeyError) -> Error {
+        match e.error {
+            Some(key_error::Error::Conflict(e)) => Error::WriteConflict(e),
+            Some(key_error::Error::Locked(e)) => Error::KeyIsLocked(e),
+            Some(key_error::Error::NotFound(e)) => Error::TxnNotFound(e),
+            Some(key_error::Error::RolledBack(e)) => Error::AlreadyRolledBack(e),
+            Some(key_error::Error::Committed(e)) => Error::Committed(e),
+            None => Error::Rpc(labrpc::Error::Other("empty key error".to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Rpc(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
@@ -5,15 +5,21 @@
 extern crate log;
 
 // After you finish the implementation, `#[allow(unused)]` should be removed.
-#[allow(dead_code, unused)]
-mod client;
+pub mod client;
+#[cfg(test)]
+mod config;
+pub mod errors;
+pub mod raftstore;
+pub mod recorder;
 #[allow(unused)]
 mod server;
-mod service;
+pub use server::{MemoryStorage, TimestampOracle};
+pub mod service;
 #[cfg(test)]
 mod tests;
+pub mod tso;
 
 // This is related to protobuf as described in `msg.proto`.
-mod msg {
+pub mod msg {
     include!(concat!(env!("OUT_DIR"), "/msg.rs"));
 }
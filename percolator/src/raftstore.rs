@@ -0,0 +1,460 @@
+//! A storage node replicated by a raft group.
+//!
+//! Every write request of the transaction service is a command in the log of
+//! the group, applied to the `KvTable` of each peer in log order. A command
+//! carries the clock of the leader that proposed it, which the table stamps
+//! records with and expires locks against, so that every peer applies it
+//! alike.
+//!
+//! Reads are served by the leader from its table without a command, once a
+//! read index confirms it still leads and the table has applied the log up
+//! to it. A read becomes a command only to clean up a lock, or when the
+//! leader has not committed a command of its term yet and so cannot tell
+//! how far the log is committed.
+//!
+//! The client gives every write request an id, which it keeps when it sends
+//! the request again, maybe to another peer. A peer keeps the responses of
+//! the requests it applied in the last `DEDUP_WINDOW`, in its snapshots too,
+//! and answers a request applied again with its first response instead of
+//! applying it twice, so that e.g. a prewrite sent again does not refresh
+//! its lock.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures::channel::oneshot;
+use futures::executor::ThreadPool;
+use futures::task::SpawnExt;
+use futures::{future, StreamExt};
+use futures_timer::Delay;
+use raft::proto::raftpb::RaftClient;
+use raft::raft::errors::Error as RaftError;
+use raft::raft::{persister::Persister, ApplyMsg, Node as RaftNode, Raft};
+
+use crate::msg::applied_request::Response;
+use crate::msg::store_command::Request;
+use crate::msg::*;
+use crate::server::{self, wait_for_locks, Blocked, KvTable};
+use crate::service::transaction;
+
+// PROPOSE_TIMEOUT is how long a request waits for its command to be applied
+// before the peer gives up leading, e.g. in a minority partition.
+const PROPOSE_TIMEOUT: Duration = Duration::from_millis(500);
+// DEDUP_WINDOW is how long a peer keeps the response of a request with an
+// id, well beyond the time a client keeps sending it.
+const DEDUP_WINDOW: u64 = Duration::from_secs(10).as_nanos() as u64;
+
+// What a command answers once applied.
+enum Reply {
+    Get(Result<GetResponse, KeyIsLocked>),
+    Scan(Result<ScanResponse, KeyIsLocked>),
+    Prewrite(PrewriteResponse),
+    Commit(CommitResponse),
+    CheckTxnStatus(CheckTxnStatusResponse),
+    ResolveLock(ResolveLockResponse),
+    Gc(GcResponse),
+}
+
+impl Reply {
+    // The response kept for a request with an id, None for a read.
+    fn to_response(&self) -> Option<Response> {
+        match self {
+            Reply::Get(_) | Reply::Scan(_) => None,
+            Reply::Prewrite(resp) => Some(Response::Prewrite(resp.clone())),
+            Reply::Commit(resp) => Some(Response::Commit(resp.clone())),
+            Reply::CheckTxnStatus(resp) => Some(Response::CheckTxnStatus(resp.clone())),
+            Reply::ResolveLock(resp) => Some(Response::ResolveLock(resp.clone())),
+            Reply::Gc(resp) => Some(Response::Gc(resp.clone())),
+        }
+    }
+
+    fn from_response(response: Response) -> Reply {
+        match response {
+            Response::Prewrite(resp) => Reply::Prewrite(resp),
+            Response::Commit(resp) => Reply::Commit(resp),
+            Response::CheckTxnStatus(resp) => Reply::CheckTxnStatus(resp),
+            Response::ResolveLock(resp) => Reply::ResolveLock(resp),
+            Response::Gc(resp) => Reply::Gc(resp),
+        }
+    }
+}
+
+// The id of a write request, 0 for a read.
+fn request_id(request: &Request) -> u64 {
+    match request {
+        Request::Get(_) | Request::Scan(_) => 0,
+        Request::Prewrite(req) => req.request_id,
+        Request::Commit(req) => req.request_id,
+        Request::CheckTxnStatus(req) => req.request_id,
+        Request::ResolveLock(req) => req.request_id,
+        Request::Gc(req) => req.request_id,
+    }
+}
+
+pub struct StoreServer {
+    pub rf: RaftNode,
+    me: usize,
+    // snapshot if log grows this big
+    maxraftstate: Option<usize>,
+    apply_rx: Option<UnboundedReceiver<ApplyMsg>>,
+
+    table: KvTable,
+    // The index of the last command applied to the table.
+    applied_index: u64,
+    // The requests waiting for their command by log index, with the term it
+    // was proposed in.
+    pending: HashMap<u64, (u64, oneshot::Sender<Reply>)>,
+    // The reads waiting for the table to apply the log up to their read
+    // index.
+    reads: Vec<(u64, oneshot::Sender<()>)>,
+    // The ids of the requests applied in the last DEDUP_WINDOW with the time
+    // they were applied at, oldest first, and their responses.
+    applied: VecDeque<(u64, u64)>,
+    responses: HashMap<u64, Response>,
+}
+
+impl StoreServer {
+    pub fn new(
+        servers: Vec<RaftClient>,
+        me: usize,
+        persister: Box<dyn Persister>,
+        maxraftstate: Option<usize>,
+    ) -> StoreServer {
+        let (apply_tx, apply_rx) = unbounded();
+        let snapshot = persister.snapshot();
+        let rf = Raft::new(servers, me, persister, apply_tx);
+
+        let mut store = StoreServer {
+            rf: RaftNode::new(rf),
+            me,
+            maxraftstate,
+            apply_rx: Some(apply_rx),
+            table: KvTable::default(),
+            applied_index: 0,
+            pending: HashMap::new(),
+            reads: vec![],
+            applied: VecDeque::new(),
+            responses: HashMap::new(),
+        };
+        store.restore(&snapshot);
+        store
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        if snapshot.is_empty() {
+            return;
+        }
+        let snapshot: StoreSnapshot =
+            labcodec::decode(snapshot).expect("failed to decode store snapshot");
+        self.table = KvTable::from_snapshot(snapshot.table.unwrap_or_default());
+        self.applied.clear();
+        self.responses.clear();
+        for applied in snapshot.applied {
+            self.applied.push_back((applied.request_id, applied.time));
+            self.responses
+                .insert(applied.request_id, applied.response.unwrap());
+        }
+    }
+
+    // Proposes the request, None if this peer does not lead.
+    fn propose(&mut self, request: Request) -> Option<oneshot::Receiver<Reply>> {
+        let cmd = StoreCommand {
+            now: server::now(),
+            request: Some(request),
+        };
+        let (index, term) = self.rf.start(&cmd).ok()?;
+        info!("store [me: {}] proposing a command at {}", self.me, index);
+        let (tx, rx) = oneshot::channel();
+        // a request proposed at the index in an earlier term is dropped, and
+        // finds out it has to try another peer
+        self.pending.insert(index, (term, tx));
+        Some(rx)
+    }
+
+    fn apply(&mut self, msg: ApplyMsg) {
+        match msg {
+            ApplyMsg::Command { data, index } => {
+                let cmd: StoreCommand = labcodec::decode(&data).unwrap();
+                let reply = self.apply_once(cmd.request.unwrap(), cmd.now);
+                if let Some((term, tx)) = self.pending.remove(&index) {
+                    // another leader's command may have taken its place
+                    if term == self.rf.term() {
+                        let _ = tx.send(reply);
+                    }
+                }
+                self.applied_up_to(index);
+                self.try_snapshot(index);
+            }
+            ApplyMsg::Snapshot { data, term, index } => {
+                if self.rf.cond_install_snapshot(term, index, &data) {
+                    info!("store [me: {}] installing snapshot at {}", self.me, index);
+                    self.restore(&data);
+                    self.pending.retain(|i, _| *i > index);
+                    self.applied_up_to(index);
+                }
+            }
+        }
+    }
+
+    // Applies the request unless a request with its id was applied lately,
+    // and tells what it answered the first time.
+    fn apply_once(&mut self, request: Request, now: u64) -> Reply {
+        // the clients gave up on these long ago
+        while let Some(&(id, time)) = self.applied.front() {
+            if time + DEDUP_WINDOW > now {
+                break;
+            }
+            self.applied.pop_front();
+            self.responses.remove(&id);
+        }
+
+        let id = request_id(&request);
+        if let Some(response) = self.responses.get(&id) {
+            info!("store [me: {}] request {} applied before", self.me, id);
+            return Reply::from_response(response.clone());
+        }
+        let reply = self.apply_request(request, now);
+        if id != 0 {
+            self.applied.push_back((id, now));
+            self.responses.insert(id, reply.to_response().unwrap());
+        }
+        reply
+    }
+
+    fn apply_request(&mut self, request: Request, now: u64) -> Reply {
+        let table = &mut self.table;
+        match request {
+            Request::Get(req) => Reply::Get(table.get(&req, now)),
+            Request::Scan(req) => Reply::Scan(table.scan(&req, now)),
+            Request::Prewrite(req) => Reply::Prewrite(table.prewrite(req, now)),
+            Request::Commit(req) => Reply::Commit(table.commit(req, now)),
+            Request::CheckTxnStatus(req) => {
+                Reply::CheckTxnStatus(table.check_txn_status(&req, now))
+            }
+            Request::ResolveLock(req) => Reply::ResolveLock(table.resolve_lock(&req, now)),
+            Request::Gc(req) => Reply::Gc(table.gc(req.safe_point)),
+        }
+    }
+
+    // Serves a read from the table as it is, None if it has to clean up a
+    // lock, which only a command can.
+    fn peek(&self, request: &Request) -> Option<Reply> {
+        let now = server::now();
+        match request {
+            Request::Get(req) => match self.table.peek_get(req, now) {
+                Ok(resp) => Some(Reply::Get(Ok(resp))),
+                Err(Blocked::Locked(locked)) => Some(Reply::Get(Err(locked))),
+                Err(Blocked::CleanUp) => None,
+            },
+            Request::Scan(req) => match self.table.peek_scan(req, now) {
+                Ok(resp) => Some(Reply::Scan(Ok(resp))),
+                Err(Blocked::Locked(locked)) => Some(Reply::Scan(Err(locked))),
+                Err(Blocked::CleanUp) => None,
+            },
+            _ => panic!("peek: not a read"),
+        }
+    }
+
+    // Resolves once the table has applied the log up to the index.
+    fn wait_applied(&mut self, index: u64) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        if index <= self.applied_index {
+            let _ = tx.send(());
+        } else {
+            self.reads.push((index, tx));
+        }
+        rx
+    }
+
+    fn applied_up_to(&mut self, index: u64) {
+        self.applied_index = index;
+        let (ready, waiting): (Vec<_>, Vec<_>) =
+            self.reads.drain(..).partition(|(i, _)| *i <= index);
+        self.reads = waiting;
+        for (_, tx) in ready {
+            let _ = tx.send(());
+        }
+    }
+
+    fn try_snapshot(&self, index: u64) {
+        if let Some(maxraftstate) = self.maxraftstate {
+            if self.rf.raft_state_size() >= maxraftstate {
+                let applied = self
+                    .applied
+                    .iter()
+                    .map(|&(request_id, time)| AppliedRequest {
+                        request_id,
+                        time,
+                        response: Some(self.responses[&request_id].clone()),
+                    })
+                    .collect();
+                let snapshot = StoreSnapshot {
+                    table: Some(self.table.to_snapshot()),
+                    applied,
+                };
+                let mut buf = vec![];
+                labcodec::encode(&snapshot, &mut buf).unwrap();
+                self.rf.snapshot(index, &buf);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Node {
+    store: Arc<Mutex<StoreServer>>,
+    tp: ThreadPool,
+}
+
+impl Node {
+    pub fn new(mut store: StoreServer) -> Node {
+        let mut apply_rx = store.apply_rx.take().unwrap();
+        let node = Node {
+            store: Arc::new(Mutex::new(store)),
+            tp: ThreadPool::new().unwrap(),
+        };
+
+        let store = node.store.clone();
+        node.tp
+            .spawn(async move {
+                while let Some(msg) = apply_rx.next().await {
+                    store.lock().unwrap().apply(msg);
+                }
+            })
+            .unwrap();
+        node
+    }
+
+    /// Stops the raft peer of the node.
+    pub fn kill(&self) {
+        self.store.lock().unwrap().rf.kill();
+    }
+
+    /// Whether this peer believes it is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.store.lock().unwrap().rf.is_leader()
+    }
+
+    // Replicates the request and waits for its reply, None unless this peer
+    // leads until it is applied.
+    async fn replicate(&self, request: Request) -> Option<Reply> {
+        let rx = self.store.lock().unwrap().propose(request)?;
+        match future::select(rx, Delay::new(PROPOSE_TIMEOUT)).await {
+            future::Either::Left((Ok(reply), _)) => Some(reply),
+            _ => None,
+        }
+    }
+
+    // Serves the read at a read index, None unless this peer leads until
+    // the table has applied the log up to it. The read is replicated like a
+    // request instead if it has to clean up a lock, or if the peer cannot
+    // give a read index before it commits a command of its term.
+    async fn read(&self, request: Request) -> Option<Reply> {
+        let read_index = self.store.lock().unwrap().rf.read_index();
+        let rx = match read_index {
+            Ok(rx) => rx,
+            Err(RaftError::NotCommitted) => return self.replicate(request).await,
+            Err(_) => return None,
+        };
+        let applied = async {
+            let index = rx.await.ok()?;
+            let applied = self.store.lock().unwrap().wait_applied(index);
+            applied.await.ok()
+        };
+        match future::select(Box::pin(applied), Delay::new(PROPOSE_TIMEOUT)).await {
+            future::Either::Left((Some(()), _)) => {}
+            _ => return None,
+        }
+        let reply = self.store.lock().unwrap().peek(&request);
+        match reply {
+            Some(reply) => Some(reply),
+            None => self.replicate(request).await,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl transaction::Service for Node {
+    async fn get(&self, req: GetRequest) -> labrpc::Result<GetResponse> {
+        let read = || async {
+            match self.read(Request::Get(req.clone())).await {
+                Some(Reply::Get(res)) => res,
+                _ => Ok(GetResponse {
+                    wrong_leader: true,
+                    ..Default::default()
+                }),
+            }
+        };
+        Ok(wait_for_locks(read).await.unwrap_or_else(GetResponse::from))
+    }
+
+    async fn scan(&self, req: ScanRequest) -> labrpc::Result<ScanResponse> {
+        let read = || async {
+            match self.read(Request::Scan(req.clone())).await {
+                Some(Reply::Scan(res)) => res,
+                _ => Ok(ScanResponse {
+                    wrong_leader: true,
+                    ..Default::default()
+                }),
+            }
+        };
+        Ok(wait_for_locks(read)
+            .await
+            .unwrap_or_else(ScanResponse::from))
+    }
+
+    async fn prewrite(&self, req: PrewriteRequest) -> labrpc::Result<PrewriteResponse> {
+        match self.replicate(Request::Prewrite(req)).await {
+            Some(Reply::Prewrite(resp)) => Ok(resp),
+            _ => Ok(PrewriteResponse {
+                wrong_leader: true,
+                ..Default::default()
+            }),
+        }
+    }
+
+    async fn commit(&self, req: CommitRequest) -> labrpc::Result<CommitResponse> {
+        match self.replicate(Request::Commit(req)).await {
+            Some(Reply::Commit(resp)) => Ok(resp),
+            _ => Ok(CommitResponse {
+                wrong_leader: true,
+                ..Default::default()
+            }),
+        }
+    }
+
+    async fn check_txn_status(
+        &self,
+        req: CheckTxnStatusRequest,
+    ) -> labrpc::Result<CheckTxnStatusResponse> {
+        match self.replicate(Request::CheckTxnStatus(req)).await {
+            Some(Reply::CheckTxnStatus(resp)) => Ok(resp),
+            _ => Ok(CheckTxnStatusResponse {
+                wrong_leader: true,
+                ..Default::default()
+            }),
+        }
+    }
+
+    async fn resolve_lock(&self, req: ResolveLockRequest) -> labrpc::Result<ResolveLockResponse> {
+        match self.replicate(Request::ResolveLock(req)).await {
+            Some(Reply::ResolveLock(resp)) => Ok(resp),
+            _ => Ok(ResolveLockResponse {
+                wrong_leader: true,
+                ..Default::default()
+            }),
+        }
+    }
+
+    async fn gc(&self, req: GcRequest) -> labrpc::Result<GcResponse> {
+        match self.replicate(Request::Gc(req)).await {
+            Some(Reply::Gc(resp)) => Ok(resp),
+            _ => Ok(GcResponse {
+                wrong_leader: true,
+                ..Default::default()
+            }),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use linearizability::history::{Call, Recorder};
+use linearizability::models::{TxnInput, TxnOp, TxnOutput};
+
+use crate::client::Client;
+use labrpc::Result;
+
+/// A client that records each of its transactions as one operation of a
+/// history shared with other clients, to be checked with
+/// `linearizability::models::TxnModel`.
+///
+/// A transaction runs from `begin` to the end of `commit`. It is recorded
+/// as pending if the commit fails, and left out if it is never committed,
+/// since its writes are only buffered until then, like one abandoned by
+/// dropping the client. Reads that fail are left out.
+pub struct RecordingClient {
+    client: Client,
+    client_id: usize,
+    recorder: Recorder<TxnInput, TxnOutput>,
+    txn: Option<Txn>,
+}
+
+struct Txn {
+    call: Call<TxnInput, TxnOutput>,
+    reads: Vec<Vec<u8>>,
+}
+
+impl RecordingClient {
+    pub fn new(
+        client: Client,
+        client_id: usize,
+        recorder: Recorder<TxnInput, TxnOutput>,
+    ) -> RecordingClient {
+        RecordingClient {
+            client,
+            client_id,
+            recorder,
+            txn: None,
+        }
+    }
+
+    pub fn begin(&mut self) {
+        let call = self.recorder.call(self.client_id, TxnInput::default());
+        self.client.begin();
+        self.txn = Some(Txn {
+            call,
+            reads: vec![],
+        });
+    }
+
+    pub fn get(&mut self, key: Vec<u8>) -> Result<Vec<u8>> {
+        let res = self.client.get(key.clone());
+        if let (Some(txn), Ok(value)) = (self.txn.as_mut(), &res) {
+            txn.call.input_mut().ops.push(TxnOp::Read(key));
+            txn.reads.push(value.clone());
+        }
+        res
+    }
+
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        if let Some(txn) = self.txn.as_mut() {
+            txn.call
+                .input_mut()
+                .ops
+                .push(TxnOp::Write(key.clone(), value.clone()));
+        }
+        self.client.set(key, value);
+    }
+
+    /// Deletes a key, recorded as a write of the empty value that reads of
+    /// a missing key return.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        if let Some(txn) = self.txn.as_mut() {
+            txn.call
+                .input_mut()
+                .ops
+                .push(TxnOp::Write(key.clone(), vec![]));
+        }
+        self.client.delete(key);
+    }
+
+    pub fn commit(&mut self) -> Result<bool> {
+        let res = self.client.commit();
+        if let Some(Txn { call, reads }) = self.txn.take() {
+            match res {
+                Ok(committed) => call.finish(TxnOutput { reads, committed }),
+                Err(_) => call.unknown(),
+            }
+        }
+        res
+    }
+}
+
+impl Drop for RecordingClient {
+    fn drop(&mut self) {
+        if let Some(txn) = self.txn.take() {
+            txn.call.discard();
+        }
+    }
+}
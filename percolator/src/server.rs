@@ -1,9 +1,11 @@
-use std::collections::BTreeMap;
-use std::ops::Bound::Included;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::ops::Bound::{Excluded, Included, Unbounded};
 use std::sync::atomic::AtomicU64;
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::msg::key_error::Error;
 use crate::msg::*;
 use crate::service::*;
 use crate::*;
@@ -12,6 +14,13 @@ use crate::*;
 // If the key's lifetime exceeds this value, it should be cleaned up.
 // Otherwise, the operation should back off.
 const TTL: u64 = Duration::from_millis(100).as_nanos() as u64;
+// LOCK_WAIT is how long a reader first waits for a lock it cannot clean up
+// before it looks again, doubling every time up to MAX_LOCK_WAIT.
+const LOCK_WAIT: Duration = Duration::from_millis(5);
+const MAX_LOCK_WAIT: Duration = Duration::from_millis(80);
+// LOCK_WAIT_TIMEOUT is how long a reader waits in total before it gives the
+// lock back to the client to resolve.
+const LOCK_WAIT_TIMEOUT: Duration = Duration::from_millis(300);
 
 #[derive(Clone, Default)]
 pub struct TimestampOracle {
@@ -22,10 +31,14 @@ pub struct TimestampOracle {
 #[async_trait::async_trait]
 impl timestamp::Service for TimestampOracle {
     // example get_timestamp RPC handler.
-    async fn get_timestamp(&self, _: TimestampRequest) -> labrpc::Result<TimestampResponse> {
+    async fn get_timestamp(&self, req: TimestampRequest) -> labrpc::Result<TimestampResponse> {
         // Your code here.
+        let count = req.count.max(1) as u64;
         Ok(TimestampResponse {
-            ts: self.next.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            ts: self
+                .next
+                .fetch_add(count, std::sync::atomic::Ordering::SeqCst),
+            wrong_leader: false,
         })
     }
 }
@@ -33,38 +46,67 @@ impl timestamp::Service for TimestampOracle {
 // Key is a tuple (raw key, timestamp).
 pub type Key = (Vec<u8>, u64);
 
+// The last field of every value is when it was written, as told by `now`.
 #[derive(Clone, PartialEq)]
 pub enum Value {
-    Timestamp(u64, Instant),
-    Vector(Vec<u8>, Instant),
+    Timestamp(u64, u64),
+    // A deletion in the Write column, with the start_ts of its transaction.
+    Tombstone(u64, u64),
+    // Marks the transaction that started at the timestamp as rolled back
+    // on a key, the Write column keeps it at that start_ts.
+    Rollback(u64, u64),
+    Vector(Vec<u8>, u64),
+    Lock(Lock, u64),
+}
+
+// A lock left by a prewrite until its transaction commits or rolls back.
+#[derive(Clone, PartialEq)]
+pub struct Lock {
+    primary: Vec<u8>,
+    // Whether the primary is on this node too, only then can readers tell
+    // from it whether the transaction committed.
+    local_primary: bool,
+    // The pending write.
+    op: Op,
 }
 
 impl Value {
     fn to_vec(&self) -> Vec<u8> {
         match self {
-            Value::Timestamp(ts, _) => panic!("to_vec: is ts"),
             Value::Vector(v, _) => v.to_vec(),
+            _ => panic!("to_vec: not a vec"),
         }
     }
 
     fn to_timestamp(&self) -> u64 {
         match self {
-            Value::Timestamp(ts, _) => *ts,
-            Value::Vector(v, _) => panic!("to_timestamp: is vec"),
+            Value::Timestamp(ts, _) | Value::Tombstone(ts, _) | Value::Rollback(ts, _) => *ts,
+            _ => panic!("to_timestamp: not a ts"),
         }
     }
 
     /// the elapsed time
-    pub fn expired(&self, ttl: u64) -> bool {
-        let d = match self {
-            Value::Timestamp(_, i) => i.elapsed(),
-            Value::Vector(_, i) => i.elapsed(),
+    pub fn expired(&self, ttl: u64, now: u64) -> bool {
+        let written = match self {
+            Value::Timestamp(_, t) | Value::Tombstone(_, t) | Value::Rollback(_, t) => *t,
+            Value::Vector(_, t) | Value::Lock(_, t) => *t,
         };
+        let d = now.saturating_sub(written);
         info!("d: {:?}, ttl: {:?}", d, ttl);
-        d > Duration::from_nanos(ttl)
+        d > ttl
     }
 }
 
+// The wall clock in nanoseconds. The records of a table are stamped with it
+// instead of an `Instant`, so that the peers of a replicated table, which
+// are all told the time of the leader, stamp them alike.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
 #[derive(Debug, Clone)]
 pub struct Write(Vec<u8>, Vec<u8>);
 
@@ -146,236 +188,691 @@ impl transaction::Service for MemoryStorage {
     // example get RPC handler.
     async fn get(&self, req: GetRequest) -> labrpc::Result<GetResponse> {
         // Your code here.
-        let mut bigtable = self.data.lock().unwrap();
-        loop {
-            // there are still pending locks in [0, start_ts]
-            let lock = bigtable
-                .read(&req.key, Column::Lock, None, Some(req.start_ts))
-                .map(|(k, v)| (k.to_owned(), v.to_owned()));
-            if let Some(((_, ts), _)) = lock {
-                bigtable = MemoryStorage::back_off_maybe_clean_up_lock(bigtable, ts, &req.key);
-                continue;
-            }
-
-            // now there is no pending locks, find the latest write and return
-            let resp = if let Some(((key, ts), value)) =
-                bigtable.read(&req.key, Column::Write, None, Some(req.start_ts))
-            {
-                let ts = value.to_timestamp();
-                let v = bigtable
-                    .read(key, Column::Data, Some(ts), Some(ts))
-                    .unwrap()
-                    .1
-                    .to_vec();
-                GetResponse { value: v }
-            } else {
-                // no write on this row
-                GetResponse { value: vec![] }
-            };
+        let read = || async { self.data.lock().unwrap().get(&req, now()) };
+        Ok(wait_for_locks(read).await.unwrap_or_else(GetResponse::from))
+    }
 
-            return Ok(resp);
-        }
+    async fn scan(&self, req: ScanRequest) -> labrpc::Result<ScanResponse> {
+        let read = || async { self.data.lock().unwrap().scan(&req, now()) };
+        Ok(wait_for_locks(read)
+            .await
+            .unwrap_or_else(ScanResponse::from))
     }
 
     // example prewrite RPC handler.
     async fn prewrite(&self, req: PrewriteRequest) -> labrpc::Result<PrewriteResponse> {
         // Your code here.
-        let mut bigtable = self.data.lock().unwrap();
+        Ok(self.data.lock().unwrap().prewrite(req, now()))
+    }
 
-        let PrewriteRequest { ts, w, primary } = req;
-        let w = w.unwrap();
-        let primary = primary.unwrap();
+    // example commit RPC handler.
+    async fn commit(&self, req: CommitRequest) -> labrpc::Result<CommitResponse> {
+        // Your code here.
+        Ok(self.data.lock().unwrap().commit(req, now()))
+    }
 
-        let ok = if bigtable
-            .read(&w.key, Column::Write, Some(ts), None)
-            .is_some()
-            || bigtable.read(&w.key, Column::Lock, None, None).is_some()
-        {
-            // case1: abort on writes after this ts
-            // case2: abort on locks at any ts
-            false
-        } else {
-            bigtable.write(
-                &w.key,
-                Column::Data,
-                ts,
-                Value::Vector(w.value, Instant::now()),
-            );
+    async fn check_txn_status(
+        &self,
+        req: CheckTxnStatusRequest,
+    ) -> labrpc::Result<CheckTxnStatusResponse> {
+        Ok(self.data.lock().unwrap().check_txn_status(&req, now()))
+    }
+
+    async fn resolve_lock(&self, req: ResolveLockRequest) -> labrpc::Result<ResolveLockResponse> {
+        Ok(self.data.lock().unwrap().resolve_lock(&req, now()))
+    }
+
+    async fn gc(&self, req: GcRequest) -> labrpc::Result<GcResponse> {
+        Ok(self.data.lock().unwrap().gc(req.safe_point))
+    }
+}
+
+// What became of a transaction, as told by its primary.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TxnStatus {
+    Locked,
+    Committed(u64),
+    RolledBack,
+}
+
+// Tries `read` until it runs into no lock that cannot be cleaned up,
+// waiting with backoff in between without holding the table. The last lock
+// is given back after LOCK_WAIT_TIMEOUT.
+pub(crate) async fn wait_for_locks<T, F>(read: impl Fn() -> F) -> Result<T, KeyIsLocked>
+where
+    F: Future<Output = Result<T, KeyIsLocked>>,
+{
+    let deadline = Instant::now() + LOCK_WAIT_TIMEOUT;
+    let mut wait = LOCK_WAIT;
+    loop {
+        let locked = match read().await {
+            Ok(resp) => return Ok(resp),
+            Err(locked) => locked,
+        };
+        let now = Instant::now();
+        if now >= deadline {
+            info!("key {:?} is locked, giving up", format_key(&locked.key));
+            return Err(locked);
+        }
+        futures_timer::Delay::new(wait.min(deadline - now)).await;
+        wait = (wait * 2).min(MAX_LOCK_WAIT);
+    }
+}
+
+// A read that gave up on a lock.
+impl From<KeyIsLocked> for GetResponse {
+    fn from(locked: KeyIsLocked) -> GetResponse {
+        GetResponse {
+            error: Some(key_error(Error::Locked(locked))),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<KeyIsLocked> for ScanResponse {
+    fn from(locked: KeyIsLocked) -> ScanResponse {
+        ScanResponse {
+            error: Some(key_error(Error::Locked(locked))),
+            ..Default::default()
+        }
+    }
+}
+
+// Why a read cannot be served without writing to the table.
+pub(crate) enum Blocked {
+    // A lock the reader has to back off from.
+    Locked(KeyIsLocked),
+    // A lock the reader has to clean up first.
+    CleanUp,
+}
+
+// Reads the keys in order with `read_key`, stopping at the limit before it
+// runs into locks further on.
+fn scan_keys<E>(
+    req: &ScanRequest,
+    mut keys: Vec<Vec<u8>>,
+    mut read_key: impl FnMut(&[u8]) -> Result<Option<Vec<u8>>, E>,
+) -> Result<ScanResponse, E> {
+    if req.reverse {
+        keys.reverse();
+    }
+    let mut pairs = vec![];
+    for key in keys {
+        if req.limit != 0 && pairs.len() == req.limit as usize {
+            break;
+        }
+        if let Some(value) = read_key(&key)? {
+            pairs.push(KvPair { key, value });
+        }
+    }
+    Ok(ScanResponse {
+        pairs,
+        error: None,
+        wrong_leader: false,
+    })
+}
+
+// The requests of the transaction service, applied to the table at `now`.
+impl KvTable {
+    // Reads the key unless it is locked by a transaction that cannot be
+    // cleaned up yet.
+    pub(crate) fn get(&mut self, req: &GetRequest, now: u64) -> Result<GetResponse, KeyIsLocked> {
+        let value = self.read_key(&req.key, req.start_ts, now)?;
+        Ok(GetResponse {
+            // no write on this row, or the latest one deletes it
+            value: value.unwrap_or_default(),
+            error: None,
+            wrong_leader: false,
+        })
+    }
+
+    // Reads the keys in range in order like `get`, stopping at the limit
+    // before it runs into locks further on.
+    pub(crate) fn scan(
+        &mut self,
+        req: &ScanRequest,
+        now: u64,
+    ) -> Result<ScanResponse, KeyIsLocked> {
+        let keys = self.keys_in_range(&req.start, &req.end);
+        scan_keys(req, keys, |key| self.read_key(key, req.start_ts, now))
+    }
+
+    // Reads the key like `get` without writing to the table, unless a lock
+    // has to be cleaned up first.
+    pub(crate) fn peek_get(&self, req: &GetRequest, now: u64) -> Result<GetResponse, Blocked> {
+        let value = self.peek_key(&req.key, req.start_ts, now)?;
+        Ok(GetResponse {
+            value: value.unwrap_or_default(),
+            error: None,
+            wrong_leader: false,
+        })
+    }
+
+    // Reads the keys in range like `scan` without writing to the table,
+    // unless a lock has to be cleaned up first.
+    pub(crate) fn peek_scan(&self, req: &ScanRequest, now: u64) -> Result<ScanResponse, Blocked> {
+        let keys = self.keys_in_range(&req.start, &req.end);
+        scan_keys(req, keys, |key| self.peek_key(key, req.start_ts, now))
+    }
+
+    pub(crate) fn prewrite(&mut self, req: PrewriteRequest, now: u64) -> PrewriteResponse {
+        let PrewriteRequest {
+            ts,
+            mutations,
+            primary,
+            ..
+        } = req;
+
+        // the mutations are locked all or none
+        let local_primary = mutations.iter().any(|w| w.key == primary);
+        let errors: Vec<_> = mutations
+            .iter()
+            .filter_map(|w| self.prewrite_error(&w.key, ts))
+            .collect();
+        if !errors.is_empty() {
+            return PrewriteResponse {
+                errors,
+                wrong_leader: false,
+            };
+        }
+
+        for w in mutations {
+            let op = w.op();
+            if op == Op::Put {
+                self.write(&w.key, Column::Data, ts, Value::Vector(w.value, now));
+            }
 
             info!("locking on key: {:?}, ts: {}", format_key(&w.key), ts);
-            bigtable.write(
+            self.write(
                 &w.key,
                 Column::Lock,
                 ts,
-                Value::Vector(primary.key, Instant::now()),
+                Value::Lock(
+                    Lock {
+                        primary: primary.clone(),
+                        local_primary,
+                        op,
+                    },
+                    now,
+                ),
             );
-            true
-        };
+        }
 
-        Ok(PrewriteResponse { ok })
+        PrewriteResponse {
+            errors: vec![],
+            wrong_leader: false,
+        }
     }
 
-    // example commit RPC handler.
-    async fn commit(&self, req: CommitRequest) -> labrpc::Result<CommitResponse> {
-        // Your code here.
-
+    pub(crate) fn commit(&mut self, req: CommitRequest, now: u64) -> CommitResponse {
         // commit primary, the record can logically be seen after we write the "Write" column
-        // start the bigtable txn
-        let mut bigtable = self.data.lock().unwrap();
-
         let CommitRequest {
             is_primary,
-            commit_key,
+            keys,
             start_ts,
             commit_ts,
+            ..
         } = req;
 
-        info!(
-            "server: commiting primary: {}, key: {:?}, start_ts: {}, commit_ts: {}",
-            is_primary,
-            commit_key.iter().map(|k| *k as char).collect::<Vec<_>>(),
-            start_ts,
-            commit_ts
-        );
+        for key in keys {
+            info!(
+                "server: commiting primary: {}, key: {:?}, start_ts: {}, commit_ts: {}",
+                is_primary,
+                format_key(&key),
+                start_ts,
+                commit_ts
+            );
 
-        // if is primary, we have to check if the primary is locked
-        let primary_locked = if is_primary {
-            info!("server: commiting primary");
-            bigtable
-                .read(&commit_key, Column::Lock, Some(start_ts), Some(start_ts))
-                .is_some()
-        } else {
-            true
-        };
+            // the key has to be locked, unless it has already been
+            // committed, e.g. by a retried request or a reader
+            let op = match self.read(&key, Column::Lock, Some(start_ts), Some(start_ts)) {
+                Some((_, Value::Lock(lock, _))) => lock.op,
+                _ => match self.commit_error(&key, start_ts) {
+                    None => continue,
+                    error => {
+                        info!("server: {:?} not locked", format_key(&key));
+                        return CommitResponse {
+                            error,
+                            wrong_leader: false,
+                        };
+                    }
+                },
+            };
+
+            // commit the record and erase the lock
+            self.write(
+                &key,
+                Column::Write,
+                commit_ts,
+                write_record(op, start_ts, now),
+            );
+            // todo: paper said it should be commit_ts, I doubt that
+            info!("unlocking on key: {:?}, ts: {}", format_key(&key), start_ts);
+            self.erase(&key, Column::Lock, start_ts);
+        }
 
-        info!("server: primary locked {}", primary_locked);
-        if !primary_locked {
-            return Ok(CommitResponse { ok: false });
+        CommitResponse {
+            error: None,
+            wrong_leader: false,
         }
+    }
 
-        // commit the record and erase the lock
-        bigtable.write(
-            &commit_key,
-            Column::Write,
+    pub(crate) fn check_txn_status(
+        &mut self,
+        req: &CheckTxnStatusRequest,
+        now: u64,
+    ) -> CheckTxnStatusResponse {
+        let (locked, commit_ts) = match self.txn_status(&req.primary, req.lock_ts, now) {
+            TxnStatus::Locked => (true, 0),
+            TxnStatus::Committed(commit_ts) => (false, commit_ts),
+            TxnStatus::RolledBack => (false, 0),
+        };
+        CheckTxnStatusResponse {
+            locked,
             commit_ts,
-            Value::Timestamp(start_ts, Instant::now()),
-        );
-        // todo: paper said it should be commit_ts, I doubt that
-        info!(
-            "unlocking on key: {:?}, ts: {}",
-            format_key(&commit_key),
-            start_ts
-        );
-        bigtable.erase(&commit_key, Column::Lock, start_ts);
+            wrong_leader: false,
+        }
+    }
 
-        Ok(CommitResponse { ok: true })
+    pub(crate) fn resolve_lock(
+        &mut self,
+        req: &ResolveLockRequest,
+        now: u64,
+    ) -> ResolveLockResponse {
+        let status = match req.commit_ts {
+            0 => TxnStatus::RolledBack,
+            commit_ts => TxnStatus::Committed(commit_ts),
+        };
+        ResolveLockResponse {
+            error: self.resolve(&req.key, req.start_ts, status, now),
+            wrong_leader: false,
+        }
     }
-}
 
-impl MemoryStorage {
-    fn back_off_maybe_clean_up_lock<'a>(
-        mut bigtable: MutexGuard<'a, KvTable>,
+    // Reads the value of the key at start_ts, None if it is missing or
+    // deleted, once the locks on it in [0, start_ts] are cleaned up.
+    fn read_key(
+        &mut self,
+        key: &[u8],
+        start_ts: u64,
+        now: u64,
+    ) -> Result<Option<Vec<u8>>, KeyIsLocked> {
+        // there are still pending locks in [0, start_ts]
+        while let Some(((_, ts), _)) = self.read(key, Column::Lock, None, Some(start_ts)) {
+            let ts = *ts;
+            self.back_off_maybe_clean_up_lock(ts, key, now)?;
+        }
+        Ok(self.read_value(key, start_ts))
+    }
+
+    // Reads the key like `read_key` unless there is a lock on it in
+    // [0, start_ts].
+    fn peek_key(&self, key: &[u8], start_ts: u64, now: u64) -> Result<Option<Vec<u8>>, Blocked> {
+        if let Some(((_, ts), _)) = self.read(key, Column::Lock, None, Some(start_ts)) {
+            return Err(match self.lock_to_clean_up(key, *ts, now) {
+                Ok(_) => Blocked::CleanUp,
+                Err(locked) => Blocked::Locked(locked),
+            });
+        }
+        Ok(self.read_value(key, start_ts))
+    }
+
+    // The value of the latest write to the key at start_ts, None if there is
+    // none or it deletes the key.
+    fn read_value(&self, key: &[u8], start_ts: u64) -> Option<Vec<u8>> {
+        match self.read_latest_write(key, start_ts) {
+            Some(Value::Timestamp(ts, _)) => {
+                let ts = *ts;
+                let (_, value) = self.read(key, Column::Data, Some(ts), Some(ts)).unwrap();
+                Some(value.to_vec())
+            }
+            _ => None,
+        }
+    }
+
+    // Cleans up the lock on the key at ts if its primary is here and tells
+    // the fate of the transaction, otherwise the reader has to back off.
+    fn back_off_maybe_clean_up_lock(
+        &mut self,
         ts: u64,
         key: &[u8],
-    ) -> MutexGuard<'a, KvTable> {
+        now: u64,
+    ) -> Result<(), KeyIsLocked> {
         // Your code here.
         info!(
             "backing off and maybe clean up lock, req.key: {:?}",
-            key.iter().map(|b| *b as char).collect::<Vec<_>>(),
+            format_key(key)
         );
-        // look up the lock conflicting with current request
-        // the request starts at start_ts, so we look up lock before that
-        let lock = bigtable
-            .read(key, Column::Lock, None, Some(ts))
-            .map(|(k, v)| (k.to_owned(), v.to_owned()));
-
-        if let Value::Vector(primary_key, time) = lock.unwrap().1 {
-            let is_primary = primary_key == key;
-            if is_primary {
-                info!("Backing off a primary");
-                // primary lock is conflict, try to remove this
-                if bigtable.try_remove_expired_lock(ts, &primary_key) {
-                    // the lock has been removed, and we need to backoff the transaction
-                    bigtable.erase(key, Column::Data, ts);
-                } else {
-                    // todo: exponentially back off to wait
+        let primary = self.lock_to_clean_up(key, ts, now)?;
+        let status = self.txn_status(&primary, ts, now);
+        self.resolve(key, ts, status, now);
+        Ok(())
+    }
+
+    // The primary of the lock on the key at ts if the lock can be cleaned
+    // up, otherwise the lock a reader has to back off from.
+    fn lock_to_clean_up(&self, key: &[u8], ts: u64, now: u64) -> Result<Vec<u8>, KeyIsLocked> {
+        let lock = match self.read(key, Column::Lock, Some(ts), Some(ts)) {
+            Some((_, Value::Lock(lock, _))) => lock.clone(),
+            _ => panic!("value in lock column should always be a lock"),
+        };
+        let locked = KeyIsLocked {
+            key: key.to_vec(),
+            primary: lock.primary.clone(),
+            lock_ts: ts,
+        };
+
+        if !lock.local_primary {
+            // only the client can reach the primary
+            info!(
+                "Backing off a secondary, ts: {}, remote primary: {:?}",
+                ts,
+                format_key(&lock.primary)
+            );
+            return Err(locked);
+        }
+
+        // like txn_status, without rolling back an expired primary
+        match self.read(&lock.primary, Column::Lock, Some(ts), Some(ts)) {
+            Some((_, primary)) if !primary.expired(TTL, now) => {
+                info!("Primary {:?} is locked", format_key(&lock.primary));
+                Err(locked)
+            }
+            _ => Ok(lock.primary),
+        }
+    }
+}
+
+impl KvTable {
+    // Finds the commit_ts of the transaction started at start_ts, if it
+    // committed the key.
+    fn read_commit(&self, key: &[u8], start_ts: u64) -> Option<u64> {
+        self.write
+            .range((key.to_vec(), start_ts)..=(key.to_vec(), u64::MAX))
+            .find(|(_, value)| match value {
+                Value::Timestamp(ts, _) | Value::Tombstone(ts, _) => *ts == start_ts,
+                _ => false,
+            })
+            .map(|((_, commit_ts), _)| *commit_ts)
+    }
+
+    // Why the transaction started at ts cannot lock the key, if it cannot:
+    // another transaction holds a lock, or the key was committed at or
+    // after ts, by this transaction or another one, or this transaction was
+    // rolled back on it. Rollbacks of other transactions wrote nothing, so
+    // they are no conflict.
+    fn prewrite_error(&self, key: &[u8], ts: u64) -> Option<KeyError> {
+        if let Some(((_, lock_ts), Value::Lock(lock, _))) = self.read(key, Column::Lock, None, None)
+        {
+            // a lock of this transaction is a retried prewrite
+            if *lock_ts != ts {
+                return Some(key_error(Error::Locked(KeyIsLocked {
+                    key: key.to_vec(),
+                    primary: lock.primary.clone(),
+                    lock_ts: *lock_ts,
+                })));
+            }
+        }
+        let range = (key.to_vec(), ts)..=(key.to_vec(), u64::MAX);
+        self.write
+            .range(range)
+            .find_map(|((_, commit_ts), value)| match value {
+                Value::Rollback(..) if *commit_ts == ts => {
+                    Some(key_error(Error::RolledBack(AlreadyRolledBack {
+                        key: key.to_vec(),
+                        start_ts: ts,
+                    })))
                 }
-            } else {
-                info!(
-                    "Backing off a secondary, ts: {}, primary: {:?}",
-                    ts,
-                    &primary_key.iter().map(|c| *c as char).collect::<Vec<_>>()
-                );
-                // secondary lock is conflict, find its primary
-                info!(
-                    "Checking lock of key {:?} on range [{}, {}]",
-                    format_key(key),
-                    ts,
-                    ts
-                );
-                let primary_lock = bigtable.read(&primary_key, Column::Lock, Some(ts), Some(ts));
-                if primary_lock.is_some() {
-                    // rollback primary
-                    info!("Primary is locked");
-                    if bigtable.try_remove_expired_lock(ts, &primary_key) {
-                        info!("Stale lock removed");
-                        bigtable.erase(&primary_key, Column::Data, ts);
-                    }
-                } else {
-                    info!("Primary is not locked");
-                    // primary is not locked
-                    //  1. the previous transaction has not commited(and will not be able to commit right now)
-                    //  2. the previous transaction has already commited
-                    if let Some(((_, commit_ts), _)) = bigtable
-                        .read(&primary_key, Column::Write, Some(ts), None)
-                        .map(|(k, v)| (k.to_owned(), v.to_owned()))
-                    {
-                        info!(
-                            "Commiting the key: {:?} on ts: {} by writing to Column::write",
-                            key.iter().map(|c| *c as char).collect::<Vec<_>>(),
-                            ts
-                        );
-
-                        // the previous transaction has already committed, therefore, this secondary is supposed to be committed
-                        bigtable.write(
-                            key,
-                            Column::Write,
+                Value::Rollback(..) => None,
+                value if value.to_timestamp() == ts => {
+                    Some(key_error(Error::Committed(Committed {
+                        key: key.to_vec(),
+                        commit_ts: *commit_ts,
+                    })))
+                }
+                _ => Some(key_error(Error::Conflict(WriteConflict {
+                    key: key.to_vec(),
+                    start_ts: ts,
+                    conflict_commit_ts: *commit_ts,
+                }))),
+            })
+    }
+
+    // Why the transaction started at start_ts cannot commit the key once it
+    // is not locked, if it has not committed it already.
+    fn commit_error(&self, key: &[u8], start_ts: u64) -> Option<KeyError> {
+        if self.read_commit(key, start_ts).is_some() {
+            return None;
+        }
+        if let Some((_, Value::Rollback(..))) =
+            self.read(key, Column::Write, Some(start_ts), Some(start_ts))
+        {
+            return Some(key_error(Error::RolledBack(AlreadyRolledBack {
+                key: key.to_vec(),
+                start_ts,
+            })));
+        }
+        Some(key_error(Error::NotFound(TxnNotFound {
+            key: key.to_vec(),
+            start_ts,
+        })))
+    }
+
+    // Reads the latest commit record of the key in [0, ts], skipping
+    // rollbacks.
+    fn read_latest_write(&self, key: &[u8], ts: u64) -> Option<&Value> {
+        self.write
+            .range((key.to_vec(), 0)..=(key.to_vec(), ts))
+            .rev()
+            .map(|(_, value)| value)
+            .find(|value| !matches!(value, Value::Rollback(..)))
+    }
+
+    // The keys in [start, end), or from start on if end is empty, that have
+    // a record in the Write or Lock column, in order.
+    fn keys_in_range(&self, start: &[u8], end: &[u8]) -> Vec<Vec<u8>> {
+        let lower = Included((start.to_vec(), 0));
+        let upper = match end {
+            [] => Unbounded,
+            end if end <= start => return vec![],
+            end => Excluded((end.to_vec(), 0)),
+        };
+        let keys: BTreeSet<_> = self
+            .write
+            .range((lower.clone(), upper.clone()))
+            .chain(self.lock.range((lower, upper)))
+            .map(|((key, _), _)| key.clone())
+            .collect();
+        keys.into_iter().collect()
+    }
+
+    // Rolls back the transaction started at start_ts on the key, its
+    // prewrite cannot lock the key again once it is.
+    fn rollback(&mut self, key: &[u8], start_ts: u64, now: u64) {
+        info!("rolling back key: {:?}, ts: {}", format_key(key), start_ts);
+        self.erase(key, Column::Lock, start_ts);
+        self.erase(key, Column::Data, start_ts);
+        self.write(key, Column::Write, start_ts, Value::Rollback(start_ts, now));
+    }
+
+    // Removes the records of every key older than its newest commit record
+    // at safe_point, which reads at or after safe_point stop at. Rollbacks
+    // older than it are no longer needed either, as a delayed prewrite
+    // conflicts with it anyway.
+    pub(crate) fn gc(&mut self, safe_point: u64) -> GcResponse {
+        let mut stale = vec![];
+        let mut kept: Option<&[u8]> = None;
+        // newest first
+        for ((key, commit_ts), value) in self.write.iter().rev() {
+            if *commit_ts > safe_point {
+                continue;
+            }
+            if kept == Some(key.as_slice()) {
+                stale.push((key.clone(), *commit_ts));
+            } else if !matches!(value, Value::Rollback(..)) {
+                kept = Some(key);
+            }
+        }
+
+        let mut resp = GcResponse::default();
+        for (key, commit_ts) in stale {
+            if let Some(Value::Timestamp(start_ts, _)) =
+                self.write.remove(&(key.clone(), commit_ts))
+            {
+                if self.data.remove(&(key, start_ts)).is_some() {
+                    resp.data += 1;
+                }
+            }
+            resp.writes += 1;
+        }
+        info!(
+            "gc at {}: {} writes, {} data removed",
+            safe_point, resp.writes, resp.data
+        );
+        resp
+    }
+
+    // Tells the fate of the transaction started at lock_ts from its primary,
+    // rolling it back if the primary lock expired. A primary that is not
+    // locked nor committed is rolled back too, in case its prewrite is
+    // still on the way.
+    fn txn_status(&mut self, primary: &[u8], lock_ts: u64, now: u64) -> TxnStatus {
+        if let Some((_, lock)) = self.read(primary, Column::Lock, Some(lock_ts), Some(lock_ts)) {
+            if !lock.expired(TTL, now) {
+                return TxnStatus::Locked;
+            }
+            info!("Stale lock on primary {:?}", format_key(primary));
+        } else if let Some(commit_ts) = self.read_commit(primary, lock_ts) {
+            return TxnStatus::Committed(commit_ts);
+        }
+        self.rollback(primary, lock_ts, now);
+        TxnStatus::RolledBack
+    }
+
+    // Commits or rolls back the lock on the key at start_ts as its primary
+    // was. Without the lock, the key has to be committed already, or is
+    // rolled back unless it is.
+    fn resolve(
+        &mut self,
+        key: &[u8],
+        start_ts: u64,
+        status: TxnStatus,
+        now: u64,
+    ) -> Option<KeyError> {
+        let op = match self.read(key, Column::Lock, Some(start_ts), Some(start_ts)) {
+            Some((_, Value::Lock(lock, _))) => lock.op,
+            _ => {
+                return match (status, self.read_commit(key, start_ts)) {
+                    (TxnStatus::Committed(_), _) => self.commit_error(key, start_ts),
+                    (TxnStatus::RolledBack, Some(commit_ts)) => {
+                        Some(key_error(Error::Committed(Committed {
+                            key: key.to_vec(),
                             commit_ts,
-                            Value::Timestamp(ts, Instant::now()),
-                        );
+                        })))
+                    }
+                    (TxnStatus::RolledBack, None) => {
+                        self.rollback(key, start_ts, now);
+                        None
                     }
-                    // in both cases, we remove the lock on the key
-                    //  1. if already commited, we help it commit and erase the lock
-                    //  2. if will not committed, the lock should appear as if never set
-                    info!(
-                        "Erasing the lock on key: {:?}, ts: {}",
-                        key.iter().map(|c| *c as char).collect::<Vec<_>>(),
-                        ts
-                    );
-
-                    bigtable.erase(key, Column::Lock, ts);
+                    (TxnStatus::Locked, _) => None,
                 }
             }
-            bigtable
-        } else {
-            panic!("value in lock column should always be a key")
+        };
+        match status {
+            TxnStatus::Committed(commit_ts) => {
+                info!(
+                    "Commiting the key: {:?} on ts: {} by writing to Column::write",
+                    format_key(key),
+                    start_ts
+                );
+                self.write(
+                    key,
+                    Column::Write,
+                    commit_ts,
+                    write_record(op, start_ts, now),
+                );
+                self.erase(key, Column::Lock, start_ts);
+            }
+            TxnStatus::RolledBack => self.rollback(key, start_ts, now),
+            TxnStatus::Locked => {}
         }
+        None
     }
 }
 
+// The table as kept in raft snapshots.
 impl KvTable {
-    /// return @removed: whether the lock is expired and REMOVED
-    fn try_remove_expired_lock(&mut self, start_ts: u64, key: &[u8]) -> bool {
-        if let Some(lock) = self.read(key, Column::Lock, Some(start_ts), Some(start_ts)) {
-            if lock.1.expired(TTL) {
-                // if expired, we remove the lock
-                self.erase(key, Column::Lock, start_ts);
-                return true;
-            }
+    pub(crate) fn to_snapshot(&self) -> TableSnapshot {
+        let records = |col: &BTreeMap<Key, Value>| col.iter().map(to_record).collect();
+        TableSnapshot {
+            write: records(&self.write),
+            data: records(&self.data),
+            lock: records(&self.lock),
+        }
+    }
+
+    pub(crate) fn from_snapshot(snapshot: TableSnapshot) -> KvTable {
+        let col = |records: Vec<Record>| records.into_iter().map(from_record).collect();
+        KvTable {
+            write: col(snapshot.write),
+            data: col(snapshot.data),
+            lock: col(snapshot.lock),
+        }
+    }
+}
+
+fn to_record((key, value): (&Key, &Value)) -> Record {
+    let (value, time) = match value {
+        Value::Timestamp(ts, t) => (record::Value::Timestamp(*ts), *t),
+        Value::Tombstone(ts, t) => (record::Value::Tombstone(*ts), *t),
+        Value::Rollback(ts, t) => (record::Value::Rollback(*ts), *t),
+        Value::Vector(v, t) => (record::Value::Vector(v.clone()), *t),
+        Value::Lock(lock, t) => {
+            let lock = LockRecord {
+                primary: lock.primary.clone(),
+                local_primary: lock.local_primary,
+                op: lock.op as i32,
+            };
+            (record::Value::Lock(lock), *t)
+        }
+    };
+    Record {
+        key: key.0.clone(),
+        ts: key.1,
+        time,
+        value: Some(value),
+    }
+}
+
+fn from_record(record: Record) -> (Key, Value) {
+    let t = record.time;
+    let value = match record.value.expect("record without a value") {
+        record::Value::Timestamp(ts) => Value::Timestamp(ts, t),
+        record::Value::Tombstone(ts) => Value::Tombstone(ts, t),
+        record::Value::Rollback(ts) => Value::Rollback(ts, t),
+        record::Value::Vector(v) => Value::Vector(v, t),
+        record::Value::Lock(lock) => {
+            let op = lock.op();
+            let lock = Lock {
+                primary: lock.primary,
+                local_primary: lock.local_primary,
+                op,
+            };
+            Value::Lock(lock, t)
         }
+    };
+    ((record.key, record.ts), value)
+}
+
+fn key_error(error: Error) -> KeyError {
+    KeyError { error: Some(error) }
+}
 
-        // no lock removed by me
-        false
+// The Write column record committing `op` of the transaction started at `start_ts`.
+fn write_record(op: Op, start_ts: u64, now: u64) -> Value {
+    match op {
+        Op::Put => Value::Timestamp(start_ts, now),
+        Op::Delete => Value::Tombstone(start_ts, now),
     }
 }
 
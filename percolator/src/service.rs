@@ -1,6 +1,7 @@
 use crate::msg::{
-    CommitRequest, CommitResponse, GetRequest, GetResponse, PrewriteRequest, PrewriteResponse,
-    TimestampRequest, TimestampResponse,
+    CheckTxnStatusRequest, CheckTxnStatusResponse, CommitRequest, CommitResponse, GcRequest,
+    GcResponse, GetRequest, GetResponse, PrewriteRequest, PrewriteResponse, ResolveLockRequest,
+    ResolveLockResponse, ScanRequest, ScanResponse, TimestampRequest, TimestampResponse,
 };
 
 labrpc::service! {
@@ -14,8 +15,12 @@ pub use timestamp::{add_service as add_tso_service, Client as TSOClient, Service
 labrpc::service! {
     service transaction {
         rpc get(GetRequest) returns (GetResponse);
+        rpc scan(ScanRequest) returns (ScanResponse);
         rpc prewrite(PrewriteRequest) returns (PrewriteResponse);
         rpc commit(CommitRequest) returns (CommitResponse);
+        rpc check_txn_status(CheckTxnStatusRequest) returns (CheckTxnStatusResponse);
+        rpc resolve_lock(ResolveLockRequest) returns (ResolveLockResponse);
+        rpc gc(GcRequest) returns (GcResponse);
     }
 }
 
@@ -3,35 +3,62 @@ use std::sync::{
     Arc,
 };
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use futures::executor::block_on;
+use futures::future::{self, FutureExt};
 use labrpc::*;
-use prost::Message;
+use linearizability::check_operations;
+use linearizability::history::{operations_from_events, LogFormat, Recorder};
+use linearizability::isolation::{self, AnomalyKind, IsolationLevel};
+use linearizability::models::{TxnInput, TxnModel, TxnOp, TxnOutput};
 
 use crate::client::Client;
+use crate::config::{self, Group};
+use crate::errors;
+use crate::msg::{
+    AlreadyRolledBack, CheckTxnStatusRequest, CommitRequest, CommitResponse, Committed, GcRequest,
+    GcResponse, GetRequest, KeyIsLocked, Op, PrewriteRequest, PrewriteResponse, ResolveLockRequest,
+    TimestampRequest, TxnNotFound, Write, WriteConflict,
+};
+use crate::recorder::RecordingClient;
 use crate::server::{MemoryStorage, TimestampOracle};
+use crate::service::transaction::Service as _;
 use crate::service::{add_transaction_service, add_tso_service, TSOClient, TransactionClient};
+use crate::{raftstore, tso};
+
+const SECONDARY_DELAY: Duration = Duration::from_millis(500);
 
 struct CommitHooks {
     drop_req: AtomicBool,
     drop_resp: AtomicBool,
     fail_primary: AtomicBool,
+    delay_secondaries: AtomicBool,
 }
 
-impl RpcHooks for CommitHooks {
-    fn before_dispatch(&self, fq_name: &str, req: &[u8]) -> Result<()> {
-        if self.drop_req.load(Ordering::Relaxed) && fq_name == "transaction.commit" {
-            let m = crate::msg::CommitRequest::decode(req).unwrap();
-            if m.is_primary && !self.fail_primary.load(Ordering::Relaxed) {
-                return Ok(());
-            }
-            return Err(Error::Other("reqhook".to_owned()));
+// Rejects the commit requests before the server handles them, the primary
+// one only if `fail_primary` is set, or holds back the secondary ones for a
+// while.
+impl Interceptor for CommitHooks {
+    fn intercept(&self, call: ServerCall, next: Next<Vec<u8>>) -> RpcFuture<Result<Vec<u8>>> {
+        if call.fq_name != "transaction.commit" {
+            return next.run(call);
         }
-        Ok(())
-    }
-    fn after_dispatch(&self, fq_name: &str, resp: Result<Vec<u8>>) -> Result<Vec<u8>> {
-        if self.drop_resp.load(Ordering::Relaxed) && fq_name == "transaction.commit" {
-            return Err(Error::Other("resphook".to_owned()));
+        let m: crate::msg::CommitRequest = call.decode().unwrap();
+        if self.drop_req.load(Ordering::Relaxed)
+            && (!m.is_primary || self.fail_primary.load(Ordering::Relaxed))
+        {
+            return Box::pin(future::err(Error::Rejected("reqhook".to_owned())));
+        }
+        if self.delay_secondaries.load(Ordering::Relaxed) && !m.is_primary {
+            return Box::pin(async move {
+                futures_timer::Delay::new(SECONDARY_DELAY).await;
+                next.run(call).await
+            });
+        }
+        let resp = next.run(call);
+        if self.drop_resp.load(Ordering::Relaxed) {
+            return Box::pin(resp.map(|_| Err(Error::Other("resphook".to_owned()))));
         }
         resp
     }
@@ -44,47 +71,119 @@ fn init_logger() {
 }
 
 fn init(num_clinet: usize) -> (Network, Vec<Client>, Arc<CommitHooks>) {
+    init_nodes(num_clinet, 1)
+}
+
+// Like `init`, with the keys spread over `num_nodes` storage servers named
+// `server0`, `server1`, ...
+fn init_nodes(num_clinet: usize, num_nodes: usize) -> (Network, Vec<Client>, Arc<CommitHooks>) {
     init_logger();
 
     let mut clients = vec![];
     let rn = Network::new();
     let tso_server_name = "tso_server";
     let mut tso_server_builder = ServerBuilder::new(tso_server_name.to_owned());
-    let server_name = "server";
-    let mut server_builder = ServerBuilder::new(server_name.to_owned());
     let tso: TimestampOracle = Default::default();
     add_tso_service(tso, &mut tso_server_builder).unwrap();
-    let store: MemoryStorage = Default::default();
-    add_transaction_service(store, &mut server_builder).unwrap();
-    let tso_server = tso_server_builder.build();
-    let server = server_builder.build();
-    rn.add_server(tso_server);
-    rn.add_server(server);
     let hook = Arc::new(CommitHooks {
         drop_req: AtomicBool::new(false),
         drop_resp: AtomicBool::new(false),
         fail_primary: AtomicBool::new(false),
+        delay_secondaries: AtomicBool::new(false),
     });
+    let tso_server = tso_server_builder.build();
+    rn.add_server(tso_server);
+    for j in 0..num_nodes {
+        let mut server_builder = ServerBuilder::new(format!("server{}", j));
+        let store: MemoryStorage = Default::default();
+        add_transaction_service(store, &mut server_builder).unwrap();
+        server_builder.add_interceptor(hook.clone());
+        rn.add_server(server_builder.build());
+    }
     for i in 0..num_clinet {
-        let txn_name_string = format!("txn{}", i);
-        let txn_name = txn_name_string.as_str();
-        let cli = rn.create_client(txn_name.to_owned());
-        cli.set_hooks(hook.clone());
-        let txn_client = TransactionClient::new(cli);
-        rn.enable(txn_name, true);
-        rn.connect(txn_name, server_name);
+        let mut txn_clients = vec![];
+        for j in 0..num_nodes {
+            let txn_name_string = format!("txn{}-{}", i, j);
+            let txn_name = txn_name_string.as_str();
+            let cli = rn.create_client(txn_name.to_owned());
+            txn_clients.push(TransactionClient::new(cli));
+            rn.enable(txn_name, true);
+            rn.connect(txn_name, &format!("server{}", j));
+        }
         let tso_name_string = format!("tso{}", i);
         let tso_name = tso_name_string.as_str();
         let cli = rn.create_client(tso_name.to_owned());
         let tso_client = TSOClient::new(cli);
         rn.enable(tso_name, true);
         rn.connect(tso_name, tso_server_name);
-        clients.push(crate::client::Client::new(tso_client, txn_client));
+        clients.push(crate::client::Client::with_nodes(tso_client, txn_clients));
     }
 
     (rn, clients, hook)
 }
 
+// Like `init`, with a TSO replicated by a group of `num_peers`.
+fn init_replicated_tso(
+    num_clinet: usize,
+    num_peers: usize,
+    maxraftstate: Option<usize>,
+) -> (Network, Group<tso::Node>, Vec<Client>) {
+    init_logger();
+
+    let rn = Network::new();
+    let group = config::tso_group(&rn, num_peers, maxraftstate);
+    let mut server_builder = ServerBuilder::new("server0".to_owned());
+    add_transaction_service(MemoryStorage::default(), &mut server_builder).unwrap();
+    rn.add_server(server_builder.build());
+
+    let clients = (0..num_clinet)
+        .map(|i| {
+            let txn_name = format!("txn{}-0", i);
+            let cli = rn.create_client(txn_name.clone());
+            rn.enable(&txn_name, true);
+            rn.connect(&txn_name, "server0");
+            let tso_clients = group
+                .clients(&format!("tso{}", i))
+                .into_iter()
+                .map(TSOClient::new)
+                .collect();
+            Client::with_tso_peers(tso_clients, vec![TransactionClient::new(cli)])
+        })
+        .collect();
+    (rn, group, clients)
+}
+
+// Like `init`, with the storage node replicated by a group of `num_peers`.
+fn init_replicated_storage(
+    num_clinet: usize,
+    num_peers: usize,
+    maxraftstate: Option<usize>,
+) -> (Network, Group<raftstore::Node>, Vec<Client>) {
+    init_logger();
+
+    let rn = Network::new();
+    let group = config::storage_group(&rn, num_peers, maxraftstate);
+    let mut server_builder = ServerBuilder::new("tso_server".to_owned());
+    add_tso_service(TimestampOracle::default(), &mut server_builder).unwrap();
+    rn.add_server(server_builder.build());
+
+    let clients = (0..num_clinet)
+        .map(|i| {
+            let tso_name = format!("tso{}", i);
+            let cli = rn.create_client(tso_name.clone());
+            rn.enable(&tso_name, true);
+            rn.connect(&tso_name, "tso_server");
+            let txn_clients = group
+                .clients(&format!("txn{}", i))
+                .into_iter()
+                .map(TransactionClient::new)
+                .collect();
+            Client::with_groups(vec![TSOClient::new(cli)], vec![txn_clients])
+        })
+        .collect();
+    (rn, group, clients)
+}
+
 #[test]
 fn test_get_timestamp_under_unreliable_network() {
     let bias = 20;
@@ -105,11 +204,18 @@ fn test_get_timestamp_under_unreliable_network() {
         }));
     }
 
+    // A request to a disabled end times out within 100ms, so the n-th
+    // attempt starts between 100 * (2^(n-1) - 1) and that plus 100 * (n-1)
+    // milliseconds.
     thread::sleep(Duration::from_millis(100 + bias));
+    // tso0 is back before the third attempt.
     rn.enable("tso0", true);
     thread::sleep(Duration::from_millis(200 + 2 * bias));
+    // tso1 is back before the fourth attempt.
     rn.enable("tso1", true);
-    thread::sleep(Duration::from_millis(400 + 4 * bias));
+    thread::sleep(Duration::from_millis(800 + 2 * bias));
+    // tso2 is back after the last attempt has timed out by 1100ms, and
+    // before a fifth one could start at 1500ms.
     rn.enable("tso2", true);
 
     for child in children {
@@ -140,6 +246,44 @@ fn test_predicate_many_preceders_read_predicates() {
     assert_eq!(client1.get(b"3".to_vec()), Ok(Vec::new()));
 }
 
+#[test]
+// https://github.com/ept/hermitage/blob/master/sqlserver.md#predicate-many-preceders-pmp
+fn test_predicate_many_preceders_scan() {
+    let (_, clients, _) = init_nodes(3, 3);
+
+    let mut client0 = clients[0].to_owned();
+    client0.begin();
+    client0.set(b"1".to_vec(), b"10".to_vec());
+    client0.set(b"2".to_vec(), b"20".to_vec());
+    assert_eq!(client0.commit(), Ok(true));
+
+    let before = vec![
+        (b"1".to_vec(), b"10".to_vec()),
+        (b"2".to_vec(), b"20".to_vec()),
+    ];
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    assert_eq!(client1.scan(vec![], vec![], 0, false), Ok(before.clone()));
+
+    let mut client2 = clients[2].to_owned();
+    client2.begin();
+    client2.set(b"3".to_vec(), b"30".to_vec());
+    client2.delete(b"1".to_vec());
+    assert_eq!(client2.commit(), Ok(true));
+
+    assert_eq!(client1.scan(vec![], vec![], 0, false), Ok(before));
+    assert_eq!(client1.commit(), Ok(true));
+
+    client1.begin();
+    assert_eq!(
+        client1.scan(vec![], vec![], 0, false),
+        Ok(vec![
+            (b"2".to_vec(), b"20".to_vec()),
+            (b"3".to_vec(), b"30".to_vec()),
+        ])
+    );
+}
+
 #[test]
 // https://github.com/ept/hermitage/blob/master/sqlserver.md#predicate-many-preceders-pmp
 fn test_predicate_many_preceders_write_predicates() {
@@ -159,7 +303,7 @@ fn test_predicate_many_preceders_write_predicates() {
 
     client1.set(b"1".to_vec(), b"20".to_vec());
     client1.set(b"2".to_vec(), b"30".to_vec());
-    assert_eq!(client1.get(b"2".to_vec()), Ok(b"20".to_vec()));
+    assert_eq!(client1.get(b"2".to_vec()), Ok(b"30".to_vec()));
 
     client2.set(b"2".to_vec(), b"40".to_vec());
     assert_eq!(client1.commit(), Ok(true));
@@ -409,3 +553,1196 @@ fn test_commit_primary_fail() {
     assert_eq!(client1.get(b"4".to_vec()), Ok(Vec::new()));
     assert_eq!(client1.get(b"5".to_vec()), Ok(Vec::new()));
 }
+
+#[test]
+fn test_commit_secondaries_in_background() {
+    let (_, clients, hook) = init(2);
+
+    let mut client0 = clients[0].to_owned();
+    client0.begin();
+    client0.set(b"3".to_vec(), b"30".to_vec());
+    client0.set(b"4".to_vec(), b"40".to_vec());
+    client0.delete(b"5".to_vec());
+    hook.delay_secondaries.store(true, Ordering::Relaxed);
+    let start = Instant::now();
+    assert_eq!(client0.commit(), Ok(true));
+    assert!(start.elapsed() < SECONDARY_DELAY);
+
+    // The secondaries are still locked, readers roll them forward.
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    assert_eq!(client1.get(b"3".to_vec()).unwrap(), b"30");
+    assert_eq!(client1.get(b"4".to_vec()).unwrap(), b"40");
+    assert_eq!(client1.get(b"5".to_vec()).unwrap(), b"");
+    assert!(start.elapsed() < SECONDARY_DELAY);
+    assert_eq!(client1.commit(), Ok(true));
+
+    // Then the delayed commits find them committed already.
+    thread::sleep(2 * SECONDARY_DELAY);
+    client1.begin();
+    assert_eq!(client1.get(b"3".to_vec()).unwrap(), b"30");
+    assert_eq!(client1.get(b"4".to_vec()).unwrap(), b"40");
+}
+
+#[test]
+fn test_wait_for_remote_primary() {
+    let (_, clients, hook) = init_nodes(2, 3);
+
+    let mut client0 = clients[0].to_owned();
+    client0.begin();
+    for k in 0..10 {
+        client0.set(
+            format!("{}", k).into_bytes(),
+            format!("{}0", k).into_bytes(),
+        );
+    }
+    hook.delay_secondaries.store(true, Ordering::Relaxed);
+    assert_eq!(client0.commit(), Ok(true));
+
+    // Secondaries whose primary is on another node are neither rolled
+    // forward nor back by readers, which wait for the commit instead.
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    for k in 0..10 {
+        assert_eq!(
+            client1.get(format!("{}", k).into_bytes()),
+            Ok(format!("{}0", k).into_bytes())
+        );
+    }
+}
+
+#[test]
+fn test_delayed_prewrite_after_rollback() {
+    let store = MemoryStorage::default();
+    let prewrite = |ts, value: &[u8]| {
+        let req = PrewriteRequest {
+            ts,
+            mutations: vec![Write {
+                key: b"1".to_vec(),
+                value: value.to_vec(),
+                op: Op::Put as i32,
+            }],
+            primary: b"1".to_vec(),
+            ..Default::default()
+        };
+        let resp = block_on(store.prewrite(req)).unwrap();
+        resp.errors
+            .into_iter()
+            .next()
+            .map_or(Ok(()), |e| Err(e.into()))
+    };
+    let commit = |start_ts, commit_ts| {
+        let req = CommitRequest {
+            is_primary: true,
+            keys: vec![b"1".to_vec()],
+            start_ts,
+            commit_ts,
+            ..Default::default()
+        };
+        let resp = block_on(store.commit(req)).unwrap();
+        resp.error.map_or(Ok(()), |e| Err(e.into()))
+    };
+    let get = |start_ts| {
+        let req = GetRequest {
+            key: b"1".to_vec(),
+            start_ts,
+        };
+        block_on(store.get(req)).unwrap().value
+    };
+
+    assert_eq!(prewrite(1, b"10"), Ok(()));
+    assert_eq!(commit(1, 2), Ok(()));
+    assert_eq!(prewrite(3, b"11"), Ok(()));
+    // The lock expires and a reader rolls the transaction back.
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(get(4), b"10");
+
+    // The prewrite is delivered again, but cannot lock the key.
+    let rolled_back = Err(errors::Error::AlreadyRolledBack(AlreadyRolledBack {
+        key: b"1".to_vec(),
+        start_ts: 3,
+    }));
+    assert_eq!(prewrite(3, b"11"), rolled_back);
+    assert_eq!(commit(3, 5), rolled_back);
+    assert_eq!(get(6), b"10");
+
+    // Later transactions are not affected by the rollback.
+    assert_eq!(prewrite(7, b"12"), Ok(()));
+    assert_eq!(commit(7, 8), Ok(()));
+    assert_eq!(get(9), b"12");
+    // A retried commit finds the transaction committed, a delayed prewrite
+    // too.
+    assert_eq!(commit(7, 8), Ok(()));
+    assert!(matches!(
+        prewrite(7, b"12"),
+        Err(errors::Error::Committed(Committed { commit_ts: 8, .. }))
+    ));
+    assert!(matches!(
+        prewrite(5, b"13"),
+        Err(errors::Error::WriteConflict(WriteConflict {
+            conflict_commit_ts: 8,
+            ..
+        }))
+    ));
+    assert_eq!(
+        commit(6, 9),
+        Err(errors::Error::TxnNotFound(TxnNotFound {
+            key: b"1".to_vec(),
+            start_ts: 6,
+        }))
+    );
+}
+
+#[test]
+fn test_lock_wait() {
+    let store = MemoryStorage::default();
+    // A secondary whose primary is on another node.
+    let req = PrewriteRequest {
+        ts: 1,
+        mutations: vec![Write {
+            key: b"2".to_vec(),
+            value: b"20".to_vec(),
+            op: Op::Put as i32,
+        }],
+        primary: b"1".to_vec(),
+        ..Default::default()
+    };
+    assert_eq!(block_on(store.prewrite(req)).unwrap().errors, vec![]);
+    let get = GetRequest {
+        key: b"2".to_vec(),
+        start_ts: 3,
+    };
+
+    // The reader gives up, even after the lock expired.
+    thread::sleep(Duration::from_millis(150));
+    let resp = block_on(store.get(get.clone())).unwrap();
+    assert_eq!(
+        resp.error.map(errors::Error::from),
+        Some(errors::Error::KeyIsLocked(KeyIsLocked {
+            key: b"2".to_vec(),
+            primary: b"1".to_vec(),
+            lock_ts: 1,
+        }))
+    );
+
+    // The lock is resolved while the reader waits.
+    let resolver = store.clone();
+    let child = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        let req = ResolveLockRequest {
+            key: b"2".to_vec(),
+            start_ts: 1,
+            commit_ts: 2,
+            ..Default::default()
+        };
+        block_on(resolver.resolve_lock(req)).unwrap();
+    });
+    let resp = block_on(store.get(get)).unwrap();
+    assert_eq!(resp.error, None);
+    assert_eq!(resp.value, b"20");
+    child.join().unwrap();
+}
+
+#[test]
+fn test_resolve_remote_locks() {
+    let (_, clients, hook) = init_nodes(3, 3);
+
+    // The secondaries are never committed by the transaction.
+    let mut client0 = clients[0].to_owned();
+    client0.begin();
+    for k in 0..4 {
+        client0.set(
+            format!("{}", k).into_bytes(),
+            format!("{}0", k).into_bytes(),
+        );
+    }
+    hook.drop_req.store(true, Ordering::Relaxed);
+    assert_eq!(client0.commit(), Ok(true));
+
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    for k in 0..4 {
+        assert_eq!(
+            client1.get(format!("{}", k).into_bytes()),
+            Ok(format!("{}0", k).into_bytes())
+        );
+    }
+
+    // Nor is the primary, so the transaction is rolled back.
+    client0.begin();
+    for k in 0..4 {
+        client0.set(
+            format!("{}", k).into_bytes(),
+            format!("{}1", k).into_bytes(),
+        );
+    }
+    hook.fail_primary.store(true, Ordering::Relaxed);
+    assert_eq!(client0.commit(), Ok(false));
+
+    let mut client2 = clients[2].to_owned();
+    client2.begin();
+    for k in 0..4 {
+        assert_eq!(
+            client2.get(format!("{}", k).into_bytes()),
+            Ok(format!("{}0", k).into_bytes())
+        );
+    }
+}
+
+#[test]
+fn test_commit_errors() {
+    let (_, clients, hook) = init(3);
+
+    let mut client0 = clients[0].to_owned();
+    let mut client1 = clients[1].to_owned();
+    client0.begin();
+    client1.begin();
+    client0.set(b"1".to_vec(), b"10".to_vec());
+    client1.set(b"1".to_vec(), b"11".to_vec());
+    assert_eq!(client0.try_commit(), Ok(()));
+    assert!(matches!(
+        client1.try_commit(),
+        Err(errors::Error::WriteConflict(WriteConflict { key, .. })) if key == b"1"
+    ));
+
+    // The secondary stays locked after the primary fails to commit.
+    client0.begin();
+    client0.set(b"1".to_vec(), b"20".to_vec());
+    client0.set(b"2".to_vec(), b"20".to_vec());
+    hook.drop_req.store(true, Ordering::Relaxed);
+    hook.fail_primary.store(true, Ordering::Relaxed);
+    assert!(matches!(
+        client0.try_commit(),
+        Err(errors::Error::AlreadyRolledBack(_))
+    ));
+    hook.drop_req.store(false, Ordering::Relaxed);
+
+    client1.begin();
+    client1.set(b"2".to_vec(), b"21".to_vec());
+    let locked = match client1.try_commit() {
+        Err(errors::Error::KeyIsLocked(locked)) => locked,
+        res => panic!("unexpected {:?}", res),
+    };
+    assert_eq!(locked.primary, b"1");
+    assert_eq!(client1.resolve_lock(locked), Ok(()));
+    client1.begin();
+    client1.set(b"2".to_vec(), b"21".to_vec());
+    assert_eq!(client1.try_commit(), Ok(()));
+
+    let mut client2 = clients[2].to_owned();
+    client2.begin();
+    assert_eq!(client2.get(b"1".to_vec()), Ok(b"10".to_vec()));
+    assert_eq!(client2.get(b"2".to_vec()), Ok(b"21".to_vec()));
+}
+
+#[test]
+fn test_delete() {
+    let (_, clients, _) = init(3);
+    let recorder = Recorder::new();
+    let mut clients: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(i, client)| RecordingClient::new(client, i, recorder.clone()))
+        .collect();
+
+    clients[0].begin();
+    clients[0].set(b"1".to_vec(), b"10".to_vec());
+    clients[0].set(b"2".to_vec(), b"20".to_vec());
+    assert_eq!(clients[0].commit(), Ok(true));
+
+    clients[1].begin();
+    clients[2].begin();
+    clients[2].delete(b"1".to_vec());
+    clients[2].delete(b"2".to_vec());
+    assert_eq!(clients[2].commit(), Ok(true));
+
+    // Deleted after the snapshot was taken.
+    assert_eq!(clients[1].get(b"1".to_vec()), Ok(b"10".to_vec()));
+    assert_eq!(clients[1].commit(), Ok(true));
+
+    clients[1].begin();
+    assert_eq!(clients[1].get(b"1".to_vec()), Ok(vec![]));
+    assert_eq!(clients[1].get(b"2".to_vec()), Ok(vec![]));
+    clients[1].set(b"1".to_vec(), b"11".to_vec());
+    assert_eq!(clients[1].commit(), Ok(true));
+
+    clients[0].begin();
+    assert_eq!(clients[0].get(b"1".to_vec()), Ok(b"11".to_vec()));
+    assert_eq!(clients[0].get(b"2".to_vec()), Ok(vec![]));
+    assert_eq!(clients[0].commit(), Ok(true));
+
+    assert!(check_operations(TxnModel {}, recorder.take()));
+}
+
+#[test]
+fn test_read_your_own_writes() {
+    let (_, clients, _) = init(2);
+
+    let mut client0 = clients[0].to_owned();
+    client0.begin();
+    client0.set(b"1".to_vec(), b"10".to_vec());
+    client0.set(b"2".to_vec(), b"20".to_vec());
+    assert_eq!(client0.commit(), Ok(true));
+
+    client0.begin();
+    assert_eq!(client0.get(b"3".to_vec()), Ok(vec![]));
+    client0.set(b"3".to_vec(), b"30".to_vec());
+    assert_eq!(client0.get(b"3".to_vec()), Ok(b"30".to_vec()));
+    // Only the last write to a key is committed.
+    client0.set(b"3".to_vec(), b"31".to_vec());
+    assert_eq!(client0.get(b"3".to_vec()), Ok(b"31".to_vec()));
+    client0.delete(b"1".to_vec());
+    assert_eq!(client0.get(b"1".to_vec()), Ok(vec![]));
+    client0.delete(b"2".to_vec());
+    client0.set(b"2".to_vec(), b"21".to_vec());
+    assert_eq!(client0.get(b"2".to_vec()), Ok(b"21".to_vec()));
+    assert_eq!(client0.commit(), Ok(true));
+
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    assert_eq!(client1.get(b"1".to_vec()), Ok(vec![]));
+    assert_eq!(client1.get(b"2".to_vec()), Ok(b"21".to_vec()));
+    assert_eq!(client1.get(b"3".to_vec()), Ok(b"31".to_vec()));
+}
+
+#[test]
+fn test_batched_commit() {
+    let nodes = 3;
+    let (rn, clients, _) = init_nodes(2, nodes);
+    let storage_count = |rn: &Network| {
+        (0..nodes)
+            .map(|j| rn.count(&format!("server{}", j)))
+            .sum::<usize>()
+    };
+
+    let mut client0 = clients[0].to_owned();
+    client0.begin();
+    for k in 0..100 {
+        client0.set(
+            format!("{}", k).into_bytes(),
+            format!("{}0", k).into_bytes(),
+        );
+    }
+    assert_eq!(client0.commit(), Ok(true));
+    // One prewrite per node, then the primary and, in the background, the
+    // secondaries of each node are committed in one request each.
+    let start = Instant::now();
+    while storage_count(&rn) < 2 * nodes + 1 && start.elapsed() < Duration::from_secs(1) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(storage_count(&rn), 2 * nodes + 1);
+    // The start and commit timestamps.
+    assert_eq!(rn.count("tso_server"), 2);
+
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    for k in 0..100 {
+        assert_eq!(
+            client1.get(format!("{}", k).into_bytes()),
+            Ok(format!("{}0", k).into_bytes())
+        );
+    }
+    assert_eq!(storage_count(&rn), 2 * nodes + 1 + 100);
+}
+
+#[test]
+fn test_batched_prewrite_conflict() {
+    let (_, clients, _) = init_nodes(3, 3);
+
+    let mut client0 = clients[0].to_owned();
+    let mut client1 = clients[1].to_owned();
+    client0.begin();
+    client1.begin();
+    for k in 0..10 {
+        client0.set(format!("{}", k).into_bytes(), b"0".to_vec());
+    }
+    client1.set(b"5".to_vec(), b"1".to_vec());
+    assert_eq!(client1.commit(), Ok(true));
+    assert_eq!(client0.commit(), Ok(false));
+
+    let mut client2 = clients[2].to_owned();
+    client2.begin();
+    assert_eq!(client2.get(b"5".to_vec()), Ok(b"1".to_vec()));
+}
+
+#[test]
+fn test_scan() {
+    let (_, clients, _) = init_nodes(2, 3);
+    let pair = |k: &str, v: &str| (k.as_bytes().to_vec(), v.as_bytes().to_vec());
+
+    let mut client0 = clients[0].to_owned();
+    client0.begin();
+    for k in 0..10 {
+        client0.set(
+            format!("{}", k).into_bytes(),
+            format!("{}0", k).into_bytes(),
+        );
+    }
+    assert_eq!(client0.commit(), Ok(true));
+
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    let all: Vec<_> = (0..10)
+        .map(|k| pair(&format!("{}", k), &format!("{}0", k)))
+        .collect();
+    assert_eq!(client1.scan(vec![], vec![], 0, false), Ok(all.clone()));
+    assert_eq!(
+        client1.scan(b"3".to_vec(), b"7".to_vec(), 0, false),
+        Ok(all[3..7].to_vec())
+    );
+    assert_eq!(
+        client1.scan(b"3".to_vec(), b"7".to_vec(), 2, true),
+        Ok(vec![pair("6", "60"), pair("5", "50")])
+    );
+    assert_eq!(
+        client1.scan(b"7".to_vec(), b"3".to_vec(), 0, false),
+        Ok(vec![])
+    );
+
+    // page through all keys, both ways
+    let mut pages = vec![];
+    let mut start = vec![];
+    loop {
+        let page = client1.scan(start.clone(), vec![], 3, false).unwrap();
+        if page.is_empty() {
+            break;
+        }
+        start = page.last().unwrap().0.clone();
+        start.push(0);
+        pages.push(page);
+    }
+    assert_eq!(pages.len(), 4);
+    assert_eq!(pages.concat(), all);
+    let mut pages = vec![];
+    let mut end = vec![];
+    loop {
+        let page = client1.scan(vec![], end.clone(), 4, true).unwrap();
+        if page.is_empty() {
+            break;
+        }
+        end = page.last().unwrap().0.clone();
+        pages.push(page);
+    }
+    assert_eq!(pages.len(), 3);
+    assert_eq!(
+        pages.concat(),
+        all.iter().cloned().rev().collect::<Vec<_>>()
+    );
+
+    // The writes of the transaction are scanned too, deleted keys do not
+    // count towards the limit.
+    client1.delete(b"4".to_vec());
+    client1.delete(b"5".to_vec());
+    client1.set(b"45".to_vec(), b"450".to_vec());
+    client1.set(b"7".to_vec(), b"71".to_vec());
+    assert_eq!(
+        client1.scan(b"4".to_vec(), vec![], 3, false),
+        Ok(vec![pair("45", "450"), pair("6", "60"), pair("7", "71")])
+    );
+    assert_eq!(
+        client1.scan(vec![], b"7".to_vec(), 3, true),
+        Ok(vec![pair("6", "60"), pair("45", "450"), pair("3", "30")])
+    );
+    assert_eq!(client1.commit(), Ok(true));
+
+    client0.begin();
+    assert_eq!(
+        client0.scan(b"4".to_vec(), b"8".to_vec(), 0, false),
+        Ok(vec![pair("45", "450"), pair("6", "60"), pair("7", "71")])
+    );
+}
+
+#[test]
+fn test_scan_resolves_locks() {
+    let (_, clients, hook) = init_nodes(2, 3);
+
+    let mut client0 = clients[0].to_owned();
+    client0.begin();
+    for k in 0..10 {
+        client0.set(
+            format!("{}", k).into_bytes(),
+            format!("{}0", k).into_bytes(),
+        );
+    }
+    hook.drop_req.store(true, Ordering::Relaxed);
+    assert_eq!(client0.commit(), Ok(true));
+    hook.drop_req.store(false, Ordering::Relaxed);
+
+    // The secondaries stay locked, the scan rolls them forward.
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    let all: Vec<_> = (0..10)
+        .map(|k| {
+            (
+                format!("{}", k).into_bytes(),
+                format!("{}0", k).into_bytes(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        client1.scan(vec![], vec![], 0, true),
+        Ok(all.into_iter().rev().collect())
+    );
+
+    assert_eq!(client1.commit(), Ok(true));
+
+    // The locks of a transaction that did not commit are rolled back.
+    client0.begin();
+    client0.set(b"0".to_vec(), b"01".to_vec());
+    client0.set(b"1".to_vec(), b"11".to_vec());
+    hook.drop_req.store(true, Ordering::Relaxed);
+    hook.fail_primary.store(true, Ordering::Relaxed);
+    assert_eq!(client0.commit(), Ok(false));
+    client1.begin();
+    assert_eq!(
+        client1.scan(b"0".to_vec(), b"2".to_vec(), 0, false),
+        Ok(vec![
+            (b"0".to_vec(), b"00".to_vec()),
+            (b"1".to_vec(), b"10".to_vec()),
+        ])
+    );
+}
+
+#[test]
+fn test_gc() {
+    let store = MemoryStorage::default();
+    let prewrite = |ts, value: &[u8]| {
+        let req = PrewriteRequest {
+            ts,
+            mutations: vec![Write {
+                key: b"1".to_vec(),
+                value: value.to_vec(),
+                op: Op::Put as i32,
+            }],
+            primary: b"1".to_vec(),
+            ..Default::default()
+        };
+        let resp = block_on(store.prewrite(req)).unwrap();
+        resp.errors
+            .into_iter()
+            .next()
+            .map_or(Ok(()), |e| Err(e.into()))
+    };
+    let commit = |start_ts, commit_ts| -> errors::Result<()> {
+        let req = CommitRequest {
+            is_primary: true,
+            keys: vec![b"1".to_vec()],
+            start_ts,
+            commit_ts,
+            ..Default::default()
+        };
+        let resp = block_on(store.commit(req)).unwrap();
+        resp.error.map_or(Ok(()), |e| Err(e.into()))
+    };
+    let get = |start_ts| {
+        let req = GetRequest {
+            key: b"1".to_vec(),
+            start_ts,
+        };
+        block_on(store.get(req)).unwrap().value
+    };
+    let gc = |safe_point| {
+        block_on(store.gc(GcRequest {
+            safe_point,
+            ..Default::default()
+        }))
+        .unwrap()
+    };
+
+    assert_eq!(prewrite(1, b"10"), Ok(()));
+    assert_eq!(commit(1, 2), Ok(()));
+    assert_eq!(prewrite(3, b"11"), Ok(()));
+    assert_eq!(commit(3, 4), Ok(()));
+    assert_eq!(prewrite(5, b"12"), Ok(()));
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(get(6), b"11");
+    assert_eq!(prewrite(7, b"13"), Ok(()));
+    assert_eq!(commit(7, 8), Ok(()));
+
+    // Only the version committed at 2 is hidden at 6, the rollback at 5 is
+    // newer than the version read at 6.
+    assert_eq!(
+        gc(6),
+        GcResponse {
+            writes: 1,
+            data: 1,
+            wrong_leader: false
+        }
+    );
+    assert_eq!(get(4), b"11");
+    assert_eq!(get(6), b"11");
+    assert_eq!(get(9), b"13");
+    assert_eq!(gc(6), GcResponse::default());
+
+    assert_eq!(
+        gc(9),
+        GcResponse {
+            writes: 2,
+            data: 1,
+            wrong_leader: false
+        }
+    );
+    assert_eq!(get(9), b"13");
+    // Without its rollback record, the delayed prewrite still conflicts
+    // with the version kept.
+    assert_eq!(
+        prewrite(5, b"12"),
+        Err(errors::Error::WriteConflict(WriteConflict {
+            key: b"1".to_vec(),
+            start_ts: 5,
+            conflict_commit_ts: 8,
+        }))
+    );
+    assert_eq!(get(10), b"13");
+}
+
+#[test]
+fn test_gc_across_nodes() {
+    let (_, clients, _) = init_nodes(2, 3);
+
+    let mut client0 = clients[0].to_owned();
+    for v in 0..3 {
+        client0.begin();
+        for k in 0..10 {
+            // waits for the secondaries of the last version to be committed
+            client0.get(format!("{}", k).into_bytes()).unwrap();
+            client0.set(
+                format!("{}", k).into_bytes(),
+                format!("{}{}", k, v).into_bytes(),
+            );
+        }
+        assert_eq!(client0.commit(), Ok(true));
+    }
+    client0.begin();
+    client0.get(b"0".to_vec()).unwrap();
+    client0.delete(b"0".to_vec());
+    assert_eq!(client0.commit(), Ok(true));
+
+    let mut client1 = clients[1].to_owned();
+    client1.begin();
+    for k in 0..10 {
+        let value = if k == 0 {
+            vec![]
+        } else {
+            format!("{}2", k).into_bytes()
+        };
+        assert_eq!(client1.get(format!("{}", k).into_bytes()), Ok(value));
+    }
+    assert_eq!(client1.commit(), Ok(true));
+
+    let safe_point = client1.get_timestamp().unwrap();
+    // Each key keeps its last version, the tombstone of the deleted one.
+    assert_eq!(
+        client1.gc(safe_point),
+        Ok(GcResponse {
+            writes: 21,
+            data: 21,
+            wrong_leader: false,
+        })
+    );
+    assert_eq!(client1.gc(safe_point), Ok(GcResponse::default()));
+
+    client1.begin();
+    for k in 0..10 {
+        let value = if k == 0 {
+            vec![]
+        } else {
+            format!("{}2", k).into_bytes()
+        };
+        assert_eq!(client1.get(format!("{}", k).into_bytes()), Ok(value));
+    }
+    client1.set(b"0".to_vec(), b"03".to_vec());
+    assert_eq!(client1.commit(), Ok(true));
+
+    client0.begin();
+    assert_eq!(client0.get(b"0".to_vec()), Ok(b"03".to_vec()));
+}
+
+#[test]
+fn test_recorded_history() {
+    let (_, clients, _) = init(4);
+    let recorder = Recorder::new();
+    let mut children = vec![];
+    for (i, client) in clients.into_iter().enumerate() {
+        let mut client = RecordingClient::new(client, i, recorder.clone());
+        children.push(thread::spawn(move || {
+            for j in 0..10 {
+                client.begin();
+                if (i + j) % 3 == 0 {
+                    client.get(b"a".to_vec()).unwrap();
+                    client.get(b"b".to_vec()).unwrap();
+                } else {
+                    // Read-modify-write transactions cannot write skew.
+                    let key = if (i + j) % 3 == 1 { b"a" } else { b"b" };
+                    client.get(key.to_vec()).unwrap();
+                    client.set(key.to_vec(), format!("{} {}", i, j).into_bytes());
+                }
+                client.commit().unwrap();
+            }
+        }));
+    }
+    for child in children {
+        child.join().unwrap();
+    }
+
+    let history = recorder.take();
+    assert_eq!(history.len(), 40);
+    assert_eq!(isolation::check(&history), Ok(vec![]));
+    let mut log = vec![];
+    TxnModel::write_log(&mut log, &history).unwrap();
+    assert!(check_operations(TxnModel {}, history));
+
+    let history = operations_from_events(TxnModel::read_log(&log[..]).unwrap());
+    assert_eq!(history.len(), 40);
+    assert!(check_operations(TxnModel {}, history));
+}
+
+#[test]
+fn test_recorded_abandoned_txn() {
+    let (_, clients, _) = init(2);
+    let recorder = Recorder::new();
+    let mut clients: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(i, client)| RecordingClient::new(client, i, recorder.clone()))
+        .collect();
+
+    clients[0].begin();
+    clients[0].set(b"1".to_vec(), b"10".to_vec());
+    clients[1].begin();
+    clients[1].set(b"2".to_vec(), b"20".to_vec());
+    assert_eq!(clients[1].commit(), Ok(true));
+    // Abandoned mid-transaction.
+    clients.remove(0);
+    assert_eq!(recorder.len(), 1);
+
+    // A read of the committed write is explained, one of the abandoned write
+    // is not, it was never even prewritten.
+    let read = |key: &[u8], value: &[u8]| {
+        recorder.record(
+            2,
+            TxnInput {
+                ops: vec![TxnOp::Read(key.to_vec())],
+            },
+            |_| TxnOutput {
+                reads: vec![value.to_vec()],
+                committed: true,
+            },
+        );
+    };
+    read(b"2", b"20");
+    read(b"1", b"");
+    assert!(check_operations(TxnModel {}, recorder.take()));
+    read(b"1", b"10");
+    assert!(!check_operations(TxnModel {}, recorder.take()));
+}
+
+#[test]
+fn test_recorded_write_skew() {
+    let (_, clients, _) = init(2);
+    let recorder = Recorder::new();
+    let mut clients: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(i, client)| RecordingClient::new(client, i, recorder.clone()))
+        .collect();
+
+    for (i, client) in clients.iter_mut().enumerate() {
+        client.begin();
+        assert_eq!(client.get(b"1".to_vec()), Ok(vec![]));
+        assert_eq!(client.get(b"2".to_vec()), Ok(vec![]));
+        client.set(format!("{}", i + 1).into_bytes(), b"10".to_vec());
+    }
+    for client in &mut clients {
+        assert_eq!(client.commit(), Ok(true));
+    }
+
+    // Allowed by snapshot isolation, but not serializable.
+    let anomalies = isolation::check(&recorder.take()).unwrap();
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].kind, AnomalyKind::G2);
+    assert_eq!(
+        isolation::violated_level(&anomalies),
+        Some(IsolationLevel::Serializable)
+    );
+}
+
+#[test]
+fn test_timestamp_count() {
+    let (rn, clients, _) = init(1);
+    let tso = TSOClient::new(rn.create_client("tso".to_owned()));
+    rn.enable("tso", true);
+    rn.connect("tso", "tso_server");
+    let get = |count| block_on(tso.get_timestamp(&TimestampRequest { count })).unwrap();
+
+    let ts = get(10).ts;
+    assert_eq!(get(0).ts, ts + 10);
+    assert_eq!(get(1).ts, ts + 11);
+    assert_eq!(clients[0].get_timestamp(), Ok(ts + 12));
+}
+
+#[test]
+fn test_batched_timestamps() {
+    let (rn, clients, _) = init(1);
+    let children: Vec<_> = (0..8)
+        .map(|_| {
+            let client = clients[0].to_owned();
+            thread::spawn(move || {
+                (0..50)
+                    .map(|_| client.get_timestamp().unwrap())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let mut all = vec![];
+    for child in children {
+        let ts = child.join().unwrap();
+        assert!(ts.windows(2).all(|w| w[0] < w[1]), "{:?}", ts);
+        all.extend(ts);
+    }
+    all.sort_unstable();
+    all.dedup();
+    assert_eq!(all.len(), 400);
+    // The calls made meanwhile share a request.
+    assert!(rn.count("tso_server") < 400);
+}
+
+#[test]
+fn test_replicated_tso() {
+    let (rn, group, clients) = init_replicated_tso(3, 3, None);
+
+    let children: Vec<_> = clients
+        .iter()
+        .cloned()
+        .map(|client| {
+            thread::spawn(move || {
+                (0..300)
+                    .map(|_| client.get_timestamp().unwrap())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let mut all = vec![];
+    for child in children {
+        let ts = child.join().unwrap();
+        assert!(ts.windows(2).all(|w| w[0] < w[1]), "{:?}", ts);
+        all.extend(ts);
+    }
+    all.sort_unstable();
+    all.dedup();
+    assert_eq!(all.len(), 900);
+
+    // More than a window at once.
+    let tso = TSOClient::new(rn.create_client("tso".to_owned()));
+    rn.enable("tso", true);
+    rn.connect("tso", &group.server_name(group.leader().unwrap()));
+    let resp = block_on(tso.get_timestamp(&TimestampRequest { count: 2500 })).unwrap();
+    assert!(!resp.wrong_leader);
+    assert!(resp.ts > all[899]);
+    assert!(clients[0].get_timestamp().unwrap() >= resp.ts + 2500);
+}
+
+#[test]
+fn test_replicated_tso_failover() {
+    let (rn, group, clients) = init_replicated_tso(1, 3, None);
+    let mut client = clients[0].to_owned();
+    let mut last = client.get_timestamp().unwrap();
+    let mut next = |client: &Client| {
+        let ts = client.get_timestamp().unwrap();
+        assert!(ts > last, "{} after {}", ts, last);
+        last = ts;
+    };
+
+    // The leader crashes.
+    let leader = group.leader().unwrap();
+    group.shutdown_server(leader);
+    next(&client);
+    client.begin();
+    client.set(b"1".to_vec(), b"10".to_vec());
+    assert_eq!(client.commit(), Ok(true));
+    group.restart_server(leader);
+    next(&client);
+
+    // The leader is cut off from the others, and stops handing out
+    // timestamps once they elect another one.
+    let leader = group.leader().unwrap();
+    let others: Vec<_> = group.all().into_iter().filter(|i| *i != leader).collect();
+    group.partition(&others, &[leader]);
+    while !others.iter().any(|i| group.is_leader(*i)) {
+        thread::sleep(Duration::from_millis(50));
+    }
+    next(&client);
+    let name = format!("old-{}", group.server_name(leader));
+    let old_leader = TSOClient::new(rn.create_client(name.clone()));
+    rn.enable(&name, true);
+    rn.connect(&name, &group.server_name(leader));
+    let resp = block_on(old_leader.get_timestamp(&TimestampRequest { count: 1 })).unwrap();
+    assert!(resp.wrong_leader);
+    group.connect_all();
+    next(&client);
+
+    // Every server restarts.
+    for i in group.all() {
+        group.shutdown_server(i);
+    }
+    for i in group.all() {
+        group.restart_server(i);
+    }
+    next(&client);
+    client.begin();
+    assert_eq!(client.get(b"1".to_vec()), Ok(b"10".to_vec()));
+    assert_eq!(client.commit(), Ok(true));
+    next(&client);
+}
+
+#[test]
+fn test_replicated_tso_snapshot() {
+    let maxraftstate = 20;
+    let (_, group, clients) = init_replicated_tso(1, 3, Some(maxraftstate));
+
+    let mut last = 0;
+    for _ in 0..6 {
+        // the lease of the window expires in between
+        thread::sleep(Duration::from_millis(350));
+        for _ in 0..10 {
+            let ts = clients[0].get_timestamp().unwrap();
+            assert!(ts >= last);
+            last = ts + 1;
+        }
+    }
+    assert!(group.snapshot_size() > 0);
+    assert!(group.log_size() <= 2 * maxraftstate);
+
+    for i in group.all() {
+        group.shutdown_server(i);
+    }
+    for i in group.all() {
+        group.restart_server(i);
+    }
+    assert!(clients[0].get_timestamp().unwrap() >= last);
+}
+
+#[test]
+fn test_replicated_storage() {
+    let (_, _group, clients) = init_replicated_storage(3, 3, None);
+
+    // Every client increments the counter, retrying on conflicts.
+    let children: Vec<_> = clients
+        .iter()
+        .cloned()
+        .map(|mut client| {
+            thread::spawn(move || {
+                for _ in 0..5 {
+                    loop {
+                        client.begin();
+                        let n = match client.get(b"n".to_vec()).unwrap().as_slice() {
+                            [] => 0,
+                            n => String::from_utf8_lossy(n).parse::<u32>().unwrap(),
+                        };
+                        client.set(b"n".to_vec(), (n + 1).to_string().into_bytes());
+                        if client.commit() == Ok(true) {
+                            break;
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for child in children {
+        child.join().unwrap();
+    }
+
+    let mut client = clients[0].to_owned();
+    client.begin();
+    assert_eq!(client.get(b"n".to_vec()), Ok(b"15".to_vec()));
+    assert_eq!(client.commit(), Ok(true));
+}
+
+#[test]
+fn test_replicated_storage_reads() {
+    let (_, group, clients) = init_replicated_storage(1, 3, None);
+    let mut client = clients[0].to_owned();
+    client.begin();
+    client.set(b"1".to_vec(), b"10".to_vec());
+    assert_eq!(client.commit(), Ok(true));
+
+    // The reads are served at a read index, a command each would grow the
+    // log by far more.
+    let log_size = group.log_size();
+    for _ in 0..10 {
+        client.begin();
+        assert_eq!(client.get(b"1".to_vec()), Ok(b"10".to_vec()));
+        assert_eq!(client.commit(), Ok(true));
+    }
+    assert!(group.log_size() < log_size + 100);
+}
+
+#[test]
+fn test_replicated_storage_dedup() {
+    let (rn, group, clients) = init_replicated_storage(1, 3, None);
+    let mut client = clients[0].to_owned();
+    client.begin();
+    client.set(b"0".to_vec(), b"0".to_vec());
+    assert_eq!(client.commit(), Ok(true));
+    let leader = group.leader().unwrap();
+    let cli = rn.create_client("txn".to_owned());
+    rn.enable("txn", true);
+    rn.connect("txn", &group.server_name(leader));
+    let txn = TransactionClient::new(cli);
+
+    let start_ts = client.get_timestamp().unwrap();
+    let prewrite = PrewriteRequest {
+        ts: start_ts,
+        mutations: vec![Write {
+            key: b"1".to_vec(),
+            value: b"10".to_vec(),
+            op: Op::Put as i32,
+        }],
+        primary: b"1".to_vec(),
+        request_id: 1,
+    };
+    let resp = block_on(txn.prewrite(&prewrite)).unwrap();
+    assert_eq!(resp, PrewriteResponse::default());
+
+    // The prewrite is sent again once its lock expired, and is answered
+    // alike without renewing the lock.
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(block_on(txn.prewrite(&prewrite)).unwrap(), resp);
+    let check = CheckTxnStatusRequest {
+        primary: b"1".to_vec(),
+        lock_ts: start_ts,
+        ..Default::default()
+    };
+    let resp = block_on(txn.check_txn_status(&check)).unwrap();
+    assert!(!resp.locked);
+}
+
+#[test]
+fn test_replicated_storage_failover() {
+    let (rn, group, clients) = init_replicated_storage(1, 3, None);
+    let mut client = clients[0].to_owned();
+    let put = |client: &mut Client, k: &[u8], v: &[u8]| {
+        client.begin();
+        client.set(k.to_vec(), v.to_vec());
+        assert_eq!(client.commit(), Ok(true));
+    };
+    let leader_client = |name: &str| {
+        let leader = group.leader().unwrap();
+        let name = format!("{}-{}", name, group.server_name(leader));
+        let cli = TransactionClient::new(rn.create_client(name.clone()));
+        rn.enable(&name, true);
+        rn.connect(&name, &group.server_name(leader));
+        cli
+    };
+    put(&mut client, b"1", b"10");
+
+    // A key stays locked when the leader crashes after its prewrite, and
+    // the transaction commits it through the new leader.
+    let start_ts = client.get_timestamp().unwrap();
+    let prewrite = PrewriteRequest {
+        ts: start_ts,
+        mutations: vec![Write {
+            key: b"2".to_vec(),
+            value: b"20".to_vec(),
+            op: Op::Put as i32,
+        }],
+        primary: b"2".to_vec(),
+        ..Default::default()
+    };
+    let resp = block_on(leader_client("prewrite").prewrite(&prewrite)).unwrap();
+    assert!(resp.errors.is_empty());
+    let crashed = group.leader().unwrap();
+    group.shutdown_server(crashed);
+    put(&mut client, b"3", b"30");
+    let commit = CommitRequest {
+        is_primary: true,
+        keys: vec![b"2".to_vec()],
+        start_ts,
+        commit_ts: client.get_timestamp().unwrap(),
+        ..Default::default()
+    };
+    let resp = block_on(leader_client("commit").commit(&commit)).unwrap();
+    assert_eq!(
+        resp,
+        CommitResponse {
+            error: None,
+            wrong_leader: false,
+        }
+    );
+    group.restart_server(crashed);
+
+    // The leader is cut off from the others, and stops serving requests
+    // once they elect another one.
+    let leader = group.leader().unwrap();
+    let old_leader = leader_client("old");
+    let others: Vec<_> = group.all().into_iter().filter(|i| *i != leader).collect();
+    group.partition(&others, &[leader]);
+    while !others.iter().any(|i| group.is_leader(*i)) {
+        thread::sleep(Duration::from_millis(50));
+    }
+    put(&mut client, b"1", b"11");
+    let get = GetRequest {
+        key: b"1".to_vec(),
+        start_ts: client.get_timestamp().unwrap(),
+    };
+    let resp = block_on(old_leader.get(&get)).unwrap();
+    assert!(resp.wrong_leader);
+    group.connect_all();
+
+    // Every server restarts.
+    for i in group.all() {
+        group.shutdown_server(i);
+    }
+    for i in group.all() {
+        group.restart_server(i);
+    }
+    client.begin();
+    assert_eq!(client.get(b"1".to_vec()), Ok(b"11".to_vec()));
+    assert_eq!(client.get(b"2".to_vec()), Ok(b"20".to_vec()));
+    assert_eq!(client.get(b"3".to_vec()), Ok(b"30".to_vec()));
+    assert_eq!(client.commit(), Ok(true));
+}
+
+#[test]
+fn test_replicated_storage_snapshot() {
+    let maxraftstate = 1000;
+    let (_, group, clients) = init_replicated_storage(1, 3, Some(maxraftstate));
+    let mut client = clients[0].to_owned();
+
+    for i in 0..30 {
+        client.begin();
+        client.set(
+            format!("{}", i % 10).into_bytes(),
+            format!("{}", i).into_bytes(),
+        );
+        assert_eq!(client.commit(), Ok(true));
+    }
+    assert!(group.snapshot_size() > 0);
+    assert!(group.log_size() <= 2 * maxraftstate);
+
+    // A peer catches up from the snapshot of the leader.
+    let follower = (0..group.n).find(|i| !group.is_leader(*i)).unwrap();
+    group.shutdown_server(follower);
+    for i in 30..40 {
+        client.begin();
+        client.set(
+            format!("{}", i % 10).into_bytes(),
+            format!("{}", i).into_bytes(),
+        );
+        assert_eq!(client.commit(), Ok(true));
+    }
+    group.restart_server(follower);
+
+    for i in group.all() {
+        group.shutdown_server(i);
+    }
+    for i in group.all() {
+        group.restart_server(i);
+    }
+    client.begin();
+    for k in 0..10 {
+        let v = format!("{}", 30 + k).into_bytes();
+        assert_eq!(client.get(format!("{}", k).into_bytes()), Ok(v));
+    }
+    assert_eq!(client.commit(), Ok(true));
+}
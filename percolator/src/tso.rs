@@ -0,0 +1,279 @@
+//! A timestamp oracle replicated by a raft group.
+//!
+//! The group agrees on a high-water mark, `max_ts`, through raft log entries
+//! that each allocate the next window of timestamps to the leader that
+//! proposed it. The leader hands out its window from memory, so only one
+//! timestamp in `WINDOW` has to wait for raft, and every request that runs
+//! out of the window meanwhile waits for the same entry. A new leader starts
+//! from the mark once its own entry is applied, above every window of the
+//! leaders before it.
+//!
+//! A deposed leader that has not heard of its successor yet must not keep
+//! handing out its window. A successor has the entry that allocated the
+//! window in its log, so it is only elected after its election timeout
+//! expired since it received that entry. The leader therefore only uses a
+//! window for `LEASE` after proposing it, which is shorter than raft's
+//! minimum election timeout, and proposes a new one afterwards.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures::channel::oneshot;
+use futures::executor::ThreadPool;
+use futures::task::SpawnExt;
+use futures::{future, StreamExt};
+use futures_timer::Delay;
+use raft::proto::raftpb::RaftClient;
+use raft::raft::{persister::Persister, ApplyMsg, Node as RaftNode, Raft};
+
+use crate::msg::{TimestampRequest, TimestampResponse, TsoAllocate, TsoSnapshot};
+use crate::service::timestamp;
+
+// WINDOW is how many timestamps a log entry allocates, unless a request
+// asks for more.
+const WINDOW: u64 = 1000;
+// LEASE is how long a leader may use a window after proposing it, below the
+// 350ms minimum election timeout of raft.
+const LEASE: Duration = Duration::from_millis(300);
+// PROPOSE_TIMEOUT is how long a request waits for a window before the peer
+// gives up leading, e.g. in a minority partition.
+const PROPOSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// The timestamps a leader hands out in its term.
+struct Window {
+    term: u64,
+    next: u64,
+    end: u64,
+    lease: Instant,
+}
+
+// A window being replicated.
+struct Proposal {
+    index: u64,
+    term: u64,
+    at: Instant,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+// What a request gets from the oracle.
+enum Allocation {
+    Timestamp(u64),
+    // The window ran out, the request is woken once a new one is applied.
+    Wait(oneshot::Receiver<()>),
+    NotLeader,
+}
+
+pub struct TsoServer {
+    pub rf: RaftNode,
+    me: usize,
+    // snapshot if log grows this big
+    maxraftstate: Option<usize>,
+    apply_rx: Option<UnboundedReceiver<ApplyMsg>>,
+
+    // No timestamp at or above it was allocated by the group.
+    max_ts: u64,
+    window: Option<Window>,
+    proposal: Option<Proposal>,
+}
+
+impl TsoServer {
+    pub fn new(
+        servers: Vec<RaftClient>,
+        me: usize,
+        persister: Box<dyn Persister>,
+        maxraftstate: Option<usize>,
+    ) -> TsoServer {
+        let (apply_tx, apply_rx) = unbounded();
+        let snapshot = persister.snapshot();
+        let rf = Raft::new(servers, me, persister, apply_tx);
+
+        let mut tso = TsoServer {
+            rf: RaftNode::new(rf),
+            me,
+            maxraftstate,
+            apply_rx: Some(apply_rx),
+            max_ts: 0,
+            window: None,
+            proposal: None,
+        };
+        tso.restore(&snapshot);
+        tso
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        if snapshot.is_empty() {
+            return;
+        }
+        let snapshot: TsoSnapshot =
+            labcodec::decode(snapshot).expect("failed to decode tso snapshot");
+        self.max_ts = snapshot.max_ts;
+    }
+
+    fn allocate(&mut self, count: u64) -> Allocation {
+        let state = self.rf.get_state();
+        if !state.is_leader() {
+            return Allocation::NotLeader;
+        }
+        if let Some(w) = self.window.as_mut() {
+            if w.term == state.term() && w.next + count <= w.end && Instant::now() < w.lease {
+                w.next += count;
+                return Allocation::Timestamp(w.next - count);
+            }
+        }
+
+        let (tx, rx) = oneshot::channel();
+        match self.proposal.as_mut() {
+            Some(p) if p.term == state.term() => p.waiters.push(tx),
+            _ => {
+                let at = Instant::now();
+                let alloc = TsoAllocate {
+                    count: count.max(WINDOW),
+                };
+                let (index, term) = match self.rf.start(&alloc) {
+                    Ok(started) => started,
+                    Err(_) => return Allocation::NotLeader,
+                };
+                info!("tso [me: {}] proposing a window at {}", self.me, index);
+                // waiters of a proposal of an earlier term are dropped, and
+                // find out they have to try another peer
+                self.proposal = Some(Proposal {
+                    index,
+                    term,
+                    at,
+                    waiters: vec![tx],
+                });
+            }
+        }
+        Allocation::Wait(rx)
+    }
+
+    fn apply(&mut self, msg: ApplyMsg) {
+        match msg {
+            ApplyMsg::Command { data, index } => {
+                let alloc: TsoAllocate = labcodec::decode(&data).unwrap();
+                let start = self.max_ts;
+                self.max_ts += alloc.count;
+
+                if self.proposal.as_ref().map(|p| p.index) == Some(index) {
+                    let p = self.proposal.take().unwrap();
+                    // another leader's entry may have taken its place
+                    if p.term == self.rf.term() && self.rf.is_leader() {
+                        self.extend_window(p.term, start, p.at + LEASE);
+                        for waiter in p.waiters {
+                            let _ = waiter.send(());
+                        }
+                    }
+                }
+                self.try_snapshot(index);
+            }
+            ApplyMsg::Snapshot { data, term, index } => {
+                if self.rf.cond_install_snapshot(term, index, &data) {
+                    info!("tso [me: {}] installing snapshot at {}", self.me, index);
+                    self.restore(&data);
+                    if matches!(&self.proposal, Some(p) if p.index <= index) {
+                        self.proposal = None;
+                    }
+                }
+            }
+        }
+    }
+
+    // Hands the timestamps from start to max_ts out in the term, after the
+    // rest of the window of the term if they follow it.
+    fn extend_window(&mut self, term: u64, start: u64, lease: Instant) {
+        match self.window.as_mut() {
+            Some(w) if w.term == term && w.end == start => {
+                w.end = self.max_ts;
+                w.lease = lease;
+            }
+            _ => {
+                self.window = Some(Window {
+                    term,
+                    next: start,
+                    end: self.max_ts,
+                    lease,
+                })
+            }
+        }
+    }
+
+    fn try_snapshot(&self, index: u64) {
+        if let Some(maxraftstate) = self.maxraftstate {
+            if self.rf.raft_state_size() >= maxraftstate {
+                let mut buf = vec![];
+                let snapshot = TsoSnapshot {
+                    max_ts: self.max_ts,
+                };
+                labcodec::encode(&snapshot, &mut buf).unwrap();
+                self.rf.snapshot(index, &buf);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Node {
+    tso: Arc<Mutex<TsoServer>>,
+    tp: ThreadPool,
+}
+
+impl Node {
+    pub fn new(mut tso: TsoServer) -> Node {
+        let mut apply_rx = tso.apply_rx.take().unwrap();
+        let node = Node {
+            tso: Arc::new(Mutex::new(tso)),
+            tp: ThreadPool::new().unwrap(),
+        };
+
+        let tso = node.tso.clone();
+        node.tp
+            .spawn(async move {
+                while let Some(msg) = apply_rx.next().await {
+                    tso.lock().unwrap().apply(msg);
+                }
+            })
+            .unwrap();
+        node
+    }
+
+    /// Stops the raft peer of the oracle.
+    pub fn kill(&self) {
+        self.tso.lock().unwrap().rf.kill();
+    }
+
+    /// Whether this peer believes it is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.tso.lock().unwrap().rf.is_leader()
+    }
+}
+
+#[async_trait::async_trait]
+impl timestamp::Service for Node {
+    async fn get_timestamp(&self, req: TimestampRequest) -> labrpc::Result<TimestampResponse> {
+        let count = req.count.max(1) as u64;
+        let wrong_leader = TimestampResponse {
+            ts: 0,
+            wrong_leader: true,
+        };
+        let deadline = Instant::now() + PROPOSE_TIMEOUT;
+        loop {
+            let allocation = self.tso.lock().unwrap().allocate(count);
+            let rx = match allocation {
+                Allocation::Timestamp(ts) => {
+                    return Ok(TimestampResponse {
+                        ts,
+                        wrong_leader: false,
+                    })
+                }
+                Allocation::Wait(rx) => rx,
+                Allocation::NotLeader => return Ok(wrong_leader),
+            };
+            let timeout = Delay::new(deadline.saturating_duration_since(Instant::now()));
+            match future::select(rx, timeout).await {
+                future::Either::Left((Ok(()), _)) => {}
+                _ => return Ok(wrong_leader),
+            }
+        }
+    }
+}
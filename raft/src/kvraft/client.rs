@@ -1,15 +1,16 @@
 use std::{
     fmt,
     sync::atomic::{AtomicU64, Ordering},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use futures::{executor::block_on, select, FutureExt};
+use futures::executor::block_on;
 use futures_timer::Delay;
+use labrpc::CallOptions;
 
 use crate::proto::kvraftpb::*;
 
-const REQ_TIMEOUT: u64 = 500;
+const REQ_TIMEOUT: Duration = Duration::from_millis(500);
 
 const OP_PUT: i32 = 1;
 const OP_APPEND: i32 = 2;
@@ -63,27 +64,16 @@ impl Clerk {
             name: self.name.clone(),
             reqno,
         };
-        'loop1: loop {
-            let mut fut = self.servers[index as usize].get(&args).fuse();
-            let mut timeout_timer = Delay::new(Duration::from_millis(REQ_TIMEOUT)).fuse();
-            //todo: make this async
-            //todo: timeout!!
-            'loop2: loop {
-                select! {
-                    result = fut => {
-                        match result {
-                            Ok(reply) => {
-                                if !reply.wrong_leader && reply.err.is_empty() {
-                                    self.last_leader.store(index, Ordering::SeqCst);
-                                    break 'loop1 reply.value;
-                                }
-                            }
-                            Err(_) => break 'loop2,
-                        }
-                    }
-
-                    _ = timeout_timer => break 'loop2,
+        loop {
+            let t0 = Instant::now();
+            let server = self.servers[index as usize].with_options(Clerk::call_options());
+            match server.get(&args).await {
+                Ok(reply) if !reply.wrong_leader && reply.err.is_empty() => {
+                    self.last_leader.store(index, Ordering::SeqCst);
+                    break reply.value;
                 }
+                Ok(_) => Clerk::wait_for_leader(t0).await,
+                Err(_) => {}
             }
 
             index = (index + 1) % (self.servers.len() as u64);
@@ -116,31 +106,33 @@ impl Clerk {
             },
         };
 
-        'loop1: loop {
-            let mut fut = self.servers[index as usize].put_append(&args).fuse();
-            let mut timeout_timer = Delay::new(Duration::from_millis(REQ_TIMEOUT)).fuse();
-
-            'loop2: loop {
-                select! {
-                    result = fut => {
-                        match result {
-                            Ok(reply) => {
-                                if !reply.wrong_leader && reply.err.is_empty() {
-                                    self.last_leader.store(index, Ordering::SeqCst);
-                                    break 'loop1;
-                                }
-                            }
-                            Err(_) => break 'loop2,
-                        }
-                    }
-
-                    _ = timeout_timer => break 'loop2,
+        loop {
+            let t0 = Instant::now();
+            let server = self.servers[index as usize].with_options(Clerk::call_options());
+            match server.put_append(&args).await {
+                Ok(reply) if !reply.wrong_leader && reply.err.is_empty() => {
+                    self.last_leader.store(index, Ordering::SeqCst);
+                    break;
                 }
+                Ok(_) => Clerk::wait_for_leader(t0).await,
+                Err(_) => {}
             }
             index = (index + 1) % (self.servers.len() as u64);
         }
     }
 
+    /// every request gives up after REQ_TIMEOUT, and the clerk moves on
+    /// to the next server.
+    fn call_options() -> CallOptions {
+        CallOptions::new().timeout(REQ_TIMEOUT)
+    }
+
+    /// the server is not (or no longer) the leader, give the cluster the
+    /// rest of the request timeout to elect one before trying the next.
+    async fn wait_for_leader(t0: Instant) {
+        Delay::new(REQ_TIMEOUT.saturating_sub(t0.elapsed())).await;
+    }
+
     pub fn put(&self, key: String, value: String) {
         block_on(self.put_append(Op::Put(key, value)));
     }
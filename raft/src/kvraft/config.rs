@@ -27,7 +27,9 @@ struct Servers {
 fn init_logger() {
     use std::sync::Once;
     static LOGGER_INIT: Once = Once::new();
-    LOGGER_INIT.call_once(env_logger::init);
+    LOGGER_INIT.call_once(|| {
+        let _ = env_logger::try_init();
+    });
 }
 
 pub struct Config {
@@ -169,7 +171,7 @@ impl Config {
     pub fn connect_all(&self) {
         let servers = self.servers.lock().unwrap();
         for i in 0..self.n {
-            self.connect(i, &self.all(), &*servers);
+            self.connect(i, &self.all(), &servers);
         }
     }
 
@@ -178,12 +180,12 @@ impl Config {
         debug!("partition servers into: {:?} {:?}", p1, p2);
         let servers = self.servers.lock().unwrap();
         for i in p1 {
-            self.disconnect(*i, p2, &*servers);
-            self.connect(*i, p1, &*servers);
+            self.disconnect(*i, p2, &servers);
+            self.connect(*i, p1, &servers);
         }
         for i in p2 {
-            self.disconnect(*i, p1, &*servers);
-            self.connect(*i, p2, &*servers);
+            self.disconnect(*i, p1, &servers);
+            self.connect(*i, p2, &servers);
         }
     }
 
@@ -232,7 +234,7 @@ impl Config {
     /// Shutdown a server by isolating it
     pub fn shutdown_server(&self, i: usize) {
         let mut servers = self.servers.lock().unwrap();
-        self.disconnect(i, &self.all(), &*servers);
+        self.disconnect(i, &self.all(), &servers);
 
         // disable client connections to the server.
         // it's important to do this before creating
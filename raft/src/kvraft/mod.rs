@@ -2,6 +2,7 @@ pub mod client;
 #[cfg(test)]
 pub mod config;
 pub mod errors;
+pub mod recorder;
 pub mod server;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,63 @@
+use linearizability::history::Recorder;
+use linearizability::models::{KvInput, KvOutput, Op};
+
+use crate::kvraft::client::Clerk;
+
+/// A clerk that records its operations into a history shared with other
+/// clients, to be checked with `linearizability::models::KvModel`.
+pub struct RecordingClerk<'a> {
+    clerk: &'a Clerk,
+    client_id: usize,
+    recorder: Recorder<KvInput, KvOutput>,
+}
+
+impl<'a> RecordingClerk<'a> {
+    pub fn new(
+        clerk: &'a Clerk,
+        client_id: usize,
+        recorder: Recorder<KvInput, KvOutput>,
+    ) -> RecordingClerk<'a> {
+        RecordingClerk {
+            clerk,
+            client_id,
+            recorder,
+        }
+    }
+
+    pub fn get(&self, key: String) -> String {
+        let input = KvInput {
+            op: Op::Get,
+            key,
+            value: String::new(),
+        };
+        self.recorder
+            .record(self.client_id, input, |input| KvOutput {
+                value: self.clerk.get(input.key.clone()),
+            })
+            .value
+    }
+
+    pub fn put(&self, key: String, value: String) {
+        let input = KvInput {
+            op: Op::Put,
+            key,
+            value,
+        };
+        self.recorder.record(self.client_id, input, |input| {
+            self.clerk.put(input.key.clone(), input.value.clone());
+            KvOutput::default()
+        });
+    }
+
+    pub fn append(&self, key: String, value: String) {
+        let input = KvInput {
+            op: Op::Append,
+            key,
+            value,
+        };
+        self.recorder.record(self.client_id, input, |input| {
+            self.clerk.append(input.key.clone(), input.value.clone());
+            KvOutput::default()
+        });
+    }
+}
@@ -1,10 +1,11 @@
+use std::env;
+use std::fs;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
-use std::sync::Mutex;
 use std::task::Poll;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use futures::channel::oneshot;
 use futures::executor::block_on;
@@ -13,12 +14,14 @@ use futures::{Future, FutureExt};
 use futures_timer::Delay;
 use rand::{seq::SliceRandom, Rng};
 
-use linearizability::check_operations_timeout;
-use linearizability::model::Operation;
-use linearizability::models::{KvInput, KvModel, KvOutput, Op};
+use linearizability::history::{LogFormat, Recorder};
+use linearizability::models::KvModel;
+use linearizability::visualize::write_html;
+use linearizability::{check_operations_verbose, CheckOutcome};
 
 use crate::kvraft::client::Clerk;
 use crate::kvraft::config::Config;
+use crate::kvraft::recorder::RecordingClerk;
 
 /// The tester generously allows solutions to complete elections in one second
 /// (much more than the paper's range of timeouts).
@@ -392,8 +395,7 @@ fn generic_test_linearizability(
 
     cfg.begin(&title);
 
-    let begin = Instant::now();
-    let operations = Arc::new(Mutex::new(vec![]));
+    let recorder = Recorder::new();
 
     let done_partitioner = Arc::new(AtomicUsize::new(0));
     let done_clients = Arc::new(AtomicUsize::new(0));
@@ -412,69 +414,32 @@ fn generic_test_linearizability(
         let clnt_txs_ = clnt_txs.clone();
         let cfg_ = cfg.clone();
         let done_clients_ = done_clients.clone();
-        let operations_ = operations.clone();
+        let recorder_ = recorder.clone();
         cfg.net
             .spawn_poller(spawn_clients_and_wait(cfg.clone(), nclients, move || {
                 let cfg1 = cfg_.clone();
                 let clnt_txs1 = clnt_txs_.clone();
                 let done_clients1 = done_clients_.clone();
-                let operations1 = operations_.clone();
+                let recorder1 = recorder_.clone();
                 move |cli, myck| {
                     // TODO: change the closure to a future.
+                    let ck = RecordingClerk::new(myck, cli, recorder1.clone());
                     let mut j = 0;
                     let mut rng = rand::thread_rng();
                     while done_clients1.load(Ordering::Relaxed) == 0 {
                         let key = format!("{}", rng.gen::<usize>() % nclients);
                         let nv = format!("x {} {} y", cli, j);
 
-                        let start = begin.elapsed().as_nanos() as i64;
-                        let (inp, out) = if rng.gen::<usize>() % 1000 < 500 {
-                            append(&cfg1, myck, &key, &nv);
+                        if rng.gen::<usize>() % 1000 < 500 {
+                            ck.append(key, nv);
                             j += 1;
-                            (
-                                KvInput {
-                                    op: Op::Append,
-                                    key,
-                                    value: nv,
-                                },
-                                KvOutput {
-                                    value: "".to_string(),
-                                },
-                            )
                         } else if rng.gen::<usize>() % 1000 < 100 {
-                            put(&cfg1, myck, &key, &nv);
+                            ck.put(key, nv);
                             j += 1;
-                            (
-                                KvInput {
-                                    op: Op::Put,
-                                    key,
-                                    value: nv,
-                                },
-                                KvOutput {
-                                    value: "".to_string(),
-                                },
-                            )
                         } else {
-                            let v = get(&cfg1, myck, &key);
-                            (
-                                KvInput {
-                                    op: Op::Get,
-                                    key,
-                                    value: "".to_string(),
-                                },
-                                KvOutput { value: v },
-                            )
-                        };
-
-                        let end = begin.elapsed().as_nanos() as i64;
-                        let op = Operation {
-                            input: inp,
-                            call: start,
-                            output: out,
-                            finish: end,
-                        };
-                        let mut data = operations1.lock().unwrap();
-                        data.push(op);
+                            ck.get(key);
+                        }
+                        cfg1.op();
                     }
                     clnt_txs1[cli].send(j).unwrap();
                 }
@@ -545,12 +510,25 @@ fn generic_test_linearizability(
     cfg.check_timeout();
     cfg.end();
 
-    if !check_operations_timeout(
-        KvModel {},
-        Arc::try_unwrap(operations).unwrap().into_inner().unwrap(),
-        LINEARIZABILITY_CHECK_TIMEOUT,
-    ) {
-        panic!("history is not linearizable");
+    let history = recorder.take();
+    let mut log = vec![];
+    KvModel::write_log(&mut log, &history).unwrap();
+    let res = check_operations_verbose(KvModel {}, history, LINEARIZABILITY_CHECK_TIMEOUT);
+    if res.outcome == CheckOutcome::Illegal {
+        let path = env::temp_dir().join(format!("kvraft-{}", std::process::id()));
+        let saved = fs::write(path.with_extension("txt"), log)
+            .and_then(|_| write_html(&res, path.with_extension("html")));
+        match saved {
+            Ok(()) => panic!(
+                "history is not linearizable, see {}.{{txt,html}}: {}",
+                path.display(),
+                res
+            ),
+            Err(e) => panic!("history is not linearizable: {}, save: {}", res, e),
+        }
+    }
+    if res.outcome == CheckOutcome::Unknown {
+        warn!("linearizability check timed out, assuming history is ok");
     }
 }
 
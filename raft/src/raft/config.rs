@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -59,7 +59,10 @@ impl Storage {
 fn init_logger() {
     use std::sync::Once;
     static LOGGER_INIT: Once = Once::new();
-    LOGGER_INIT.call_once(env_logger::init);
+    // The kvraft tests share this binary and set up the logger too.
+    LOGGER_INIT.call_once(|| {
+        let _ = env_logger::try_init();
+    });
 }
 
 pub struct Config {
@@ -84,6 +87,10 @@ pub struct Config {
     t0: Instant,
     // rpc_total() at start of test
     rpcs0: usize,
+    // bytes_total() at start of test
+    bytes0: u64,
+    // per-method statistics at start of test
+    methods0: BTreeMap<&'static str, labrpc::RpcStats>,
     // number of agreements
     cmds0: usize,
 }
@@ -122,6 +129,8 @@ impl Config {
             start: Instant::now(),
             t0: Instant::now(),
             rpcs0: 0,
+            bytes0: 0,
+            methods0: BTreeMap::new(),
             cmds0: 0,
         };
 
@@ -144,6 +153,10 @@ impl Config {
         self.net.total_count()
     }
 
+    fn bytes_total(&self) -> u64 {
+        self.net.total_bytes()
+    }
+
     /// Maximum log size across all servers
     pub fn log_size(&self) -> usize {
         self.saved
@@ -339,6 +352,8 @@ impl Config {
         info!("{} ...", description);
         self.t0 = Instant::now();
         self.rpcs0 = self.rpc_total();
+        self.bytes0 = self.bytes_total();
+        self.methods0 = self.net.metrics().by_method();
         self.cmds0 = 0;
 
         let mut s = self.storage.lock().unwrap();
@@ -356,13 +371,32 @@ impl Config {
         let npeers = self.n;
         // number of RPC sends
         let nrpc = self.rpc_total() - self.rpcs0;
+        // number of bytes
+        let nbytes = self.bytes_total() - self.bytes0;
 
         // number of Raft agreements reported
         let s = self.storage.lock().unwrap();
         let ncmds = s.max_index - s.max_index0;
 
         info!("  ... Passed --");
-        info!("  {:?}  {} {} {}", t, npeers, nrpc, ncmds);
+        info!("  {:?}  {} {} {} {}", t, npeers, nrpc, nbytes, ncmds);
+        for (method, stats) in self.net.metrics().by_method() {
+            let stats0 = self.methods0.get(method).cloned().unwrap_or_default();
+            let calls = stats.calls - stats0.calls;
+            if calls == 0 {
+                continue;
+            }
+            let latency = (stats.latency.sum() - stats0.latency.sum())
+                / (stats.latency.count() - stats0.latency.count()).max(1) as f64;
+            debug!(
+                "  {}: {} rpcs, {} bytes, {} errors, {:.3}ms mean latency",
+                method,
+                calls,
+                stats.bytes() - stats0.bytes(),
+                stats.errors - stats0.errors,
+                latency * 1000.0
+            );
+        }
     }
 
     /// start or re-start a Raft.
@@ -6,6 +6,8 @@ pub enum Error {
     Decode(labcodec::DecodeError),
     Rpc(labrpc::Error),
     NotLeader,
+    // The leader has not committed an entry of its term yet.
+    NotCommitted,
     PlaceHolder,
 }
 
@@ -101,7 +101,7 @@ enum Actions {
 
 enum RepliesFrom {
     RequestVote(u64, RequestVoteReply),
-    AppendEntries(u64, u64, AppendEntriesReply),
+    AppendEntries(u64, u64, u64, AppendEntriesReply),
     InstallSnapshot(u64, u64, InstallSnapshotReply),
 }
 
@@ -135,6 +135,15 @@ pub struct Raft {
     // XXX: log
     log: Vec<LogEntry>,
 
+    // the heartbeat round of the leader, a round confirms it still leads
+    // once a majority answers it
+    round: u64,
+    // the latest round each peer answered in the current term
+    acked_rounds: Vec<u64>,
+    // reads waiting for a round to confirm their read index, with the round
+    // and the index
+    reads: Vec<(u64, u64, oneshot::Sender<u64>)>,
+
     last_included_index: u64,
     last_included_term: u64,
 
@@ -217,6 +226,9 @@ impl Raft {
             next_index: vec![1; npeers],
             // XXX: log entry index start with 1
             log: vec![],
+            round: 0,
+            acked_rounds: vec![0; npeers],
+            reads: vec![],
             last_included_index: 0,
             last_included_term: 0,
             action_tx: None,
@@ -259,6 +271,22 @@ impl Raft {
         Ok((self.last_log_index_logical(), self.last_log_term()))
     }
 
+    fn read_index(&mut self) -> Result<oneshot::Receiver<u64>> {
+        if !self.is_leader() {
+            return Err(Error::NotLeader);
+        }
+        // an earlier leader may have committed entries this one has not
+        // learnt are committed until it commits one of its own
+        if self.term_at_logical(self.commit_index as usize) != Some(self.term()) {
+            return Err(Error::NotCommitted);
+        }
+        let (tx, rx) = oneshot::channel();
+        // the round in flight may have been answered before this call
+        self.reads.push((self.round + 1, self.commit_index, tx));
+        self.fill_heartbeat_chan();
+        Ok(rx)
+    }
+
     // called by upper application layer, let the raft instance conditionally
     // install the snapshot, this should result in calling install_snapshot()
     // by `self`
@@ -580,6 +608,8 @@ impl Raft {
         if let Some(v) = voted_for {
             self.voted_for = v;
         }
+        // drops the senders, the reads can no longer be confirmed
+        self.reads.clear();
         self.persist();
     }
 
@@ -594,6 +624,7 @@ impl Raft {
     fn turn_leader(&mut self) {
         self.next_index = vec![self.last_log_index_logical() + 1; self.peers.len()];
         self.match_index = vec![0; self.peers.len()];
+        self.acked_rounds = vec![0; self.peers.len()];
         self.state.role = Role::Leader;
     }
 
@@ -839,6 +870,10 @@ impl Raft {
         }
 
         rfinfo!(self, "Sending heartbeat");
+        self.round += 1;
+        let round = self.round;
+        // a group of one confirms the round by itself
+        self.confirm_reads();
 
         // prev: 10, log:[11, 12], next = 13
         for i in 0..self.peers.len() {
@@ -866,6 +901,7 @@ impl Raft {
                             let _ = reply_tx.unbounded_send(RepliesFrom::AppendEntries(
                                 i as u64,
                                 next_index_on_success,
+                                round,
                                 reply,
                             ));
                         }
@@ -950,8 +986,8 @@ impl Raft {
     fn mux_replies(&mut self, reply_from: RepliesFrom) {
         match reply_from {
             RepliesFrom::RequestVote(peer, reply) => self.handle_request_vote_reply(peer, reply),
-            RepliesFrom::AppendEntries(peer, next, reply) => {
-                self.handle_append_entries_reply(peer, next, reply)
+            RepliesFrom::AppendEntries(peer, next, round, reply) => {
+                self.handle_append_entries_reply(peer, next, round, reply)
             }
             RepliesFrom::InstallSnapshot(peer, next, reply) => {
                 self.handle_install_snapshot_reply(peer, next, reply)
@@ -986,6 +1022,7 @@ impl Raft {
         &mut self,
         from: u64,
         next_index: u64,
+        round: u64,
         reply: AppendEntriesReply,
     ) {
         if reply.term > self.term() {
@@ -1000,6 +1037,12 @@ impl Raft {
             );
             return;
         }
+        // a failed reply in this term still follows this leader
+        if reply.term == self.term() {
+            let acked = &mut self.acked_rounds[from as usize];
+            *acked = (*acked).max(round);
+            self.confirm_reads();
+        }
         // if success, means that the log sent is replicated on `from`
         if reply.success {
             // change(next_index): place 1
@@ -1032,6 +1075,22 @@ impl Raft {
         }
     }
 
+    // Answers the reads whose round a majority has answered.
+    fn confirm_reads(&mut self) {
+        let mut rounds = self.acked_rounds.clone();
+        rounds[self.me] = self.round;
+        rounds.sort_unstable();
+        let confirmed = rounds[(rounds.len() - 1) / 2];
+        let (ready, waiting): (Vec<_>, Vec<_>) = self
+            .reads
+            .drain(..)
+            .partition(|(round, ..)| *round <= confirmed);
+        self.reads = waiting;
+        for (_, index, tx) in ready {
+            let _ = tx.send(index);
+        }
+    }
+
     fn handle_install_snapshot_reply(
         &mut self,
         from: u64,
@@ -1100,7 +1159,7 @@ impl Raft {
             Ok(nv_state) => {
                 let nv_state: RaftNonVolatileState = nv_state;
                 self.state.term = nv_state.current_term;
-                self.voted_for = nv_state.voted_for as i64;
+                self.voted_for = nv_state.voted_for;
                 self.log = nv_state.log;
                 self.last_included_index = nv_state.last_included_index;
                 self.last_included_term = nv_state.last_included_term;
@@ -1286,6 +1345,19 @@ impl Node {
         rf.start(command)
     }
 
+    /// Asks the leader for a read index: its commit index, once a round of
+    /// heartbeats sent after the call shows that a majority still follows
+    /// it. A read served once the service has applied the log up to the
+    /// index sees every command committed before the call, without a
+    /// command of its own.
+    ///
+    /// Returns [`Error::NotLeader`] unless this peer leads, and
+    /// [`Error::NotCommitted`] until it has committed a command of its own
+    /// term. The receiver is cancelled if this peer stops leading.
+    pub fn read_index(&self) -> Result<oneshot::Receiver<u64>> {
+        self.rf.lock().unwrap().read_index()
+    }
+
     /// The current term of this peer.
     pub fn term(&self) -> u64 {
         // Your code here.
@@ -225,6 +225,43 @@ fn test_fail_no_agree_2b() {
     cfg.end();
 }
 
+#[test]
+fn test_read_index_2b() {
+    let servers = 3;
+    let mut cfg = Config::new(servers);
+
+    cfg.begin("Test (2B): read index needs a majority");
+
+    let index = cfg.one(Entry { x: 10 }, servers, false);
+    let leader = cfg.check_one_leader();
+    let rafts = cfg.rafts.clone();
+    let node = |i: usize| rafts.lock().unwrap()[i].clone().unwrap();
+
+    let follower = (leader + 1) % servers;
+    if node(follower).read_index().is_ok() {
+        panic!("follower {} gave a read index", follower);
+    }
+    let read_index = block_on(node(leader).read_index().expect("leader rejected read"));
+    if read_index != Ok(index) {
+        panic!("expected read index {}, got {:?}", index, read_index);
+    }
+
+    // an isolated leader cannot tell it still leads
+    cfg.disconnect((leader + 1) % servers);
+    cfg.disconnect((leader + 2) % servers);
+    let mut rx = node(leader).read_index().expect("leader rejected read");
+    thread::sleep(RAFT_ELECTION_TIMEOUT);
+    if let Ok(Some(read_index)) = rx.try_recv() {
+        panic!("isolated leader gave read index {}", read_index);
+    }
+
+    cfg.connect((leader + 1) % servers);
+    cfg.connect((leader + 2) % servers);
+    cfg.one(Entry { x: 20 }, servers, true);
+
+    cfg.end();
+}
+
 #[test]
 fn test_concurrent_starts_2b() {
     let servers = 3;